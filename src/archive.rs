@@ -0,0 +1,93 @@
+//! 解压下载完成的压缩包
+//!
+//! `--extract` 时按文件名识别 `.tar.gz`、`.tgz`、`.tar.zst`、`.tzst` 及 `.zip`，边读取边解压到目标目录，需启用
+//! `archive` 功能
+
+use std::path::Path;
+
+#[cfg(not(feature = "archive"))]
+use anyhow::anyhow;
+use indicatif::ProgressBar;
+
+#[cfg(not(feature = "archive"))]
+use crate::message::Msg;
+use crate::Result;
+
+/// 支持的压缩包格式
+#[derive(Clone, Copy)]
+pub enum Format {
+    TarGz,
+    TarZst,
+    Zip,
+}
+
+impl Format {
+    /// 按文件名识别格式，不区分大小写
+    pub fn detect(path: &Path) -> Option<Self> {
+        let name = path.file_name()?.to_string_lossy().to_lowercase();
+        if name.ends_with(".tar.gz") || name.ends_with(".tgz") {
+            Some(Self::TarGz)
+        } else if name.ends_with(".tar.zst") || name.ends_with(".tzst") {
+            Some(Self::TarZst)
+        } else if name.ends_with(".zip") {
+            Some(Self::Zip)
+        } else {
+            None
+        }
+    }
+}
+
+/// 将 `path` 解压到 `dir`，`bar` 的位置为已读取的压缩包字节数；条目路径不会超出 `dir`
+#[cfg(feature = "archive")]
+pub async fn extract(path: &Path, format: Format, dir: &Path, bar: ProgressBar) -> Result {
+    use std::fs::{create_dir_all, File};
+    use std::io::BufReader;
+
+    let (path, dir) = (path.to_path_buf(), dir.to_path_buf());
+    tokio::task::spawn_blocking(move || {
+        create_dir_all(&dir)?;
+        let file = BufReader::new(Tracked {
+            inner: File::open(&path)?,
+            bar,
+        });
+        match format {
+            Format::TarGz => tar::Archive::new(flate2::read::GzDecoder::new(file)).unpack(&dir)?,
+            Format::TarZst => tar::Archive::new(zstd::Decoder::with_buffer(file)?).unpack(&dir)?,
+            Format::Zip => zip::ZipArchive::new(file)?.extract(&dir)?,
+        }
+        Ok(())
+    })
+    .await?
+}
+
+/// 未启用 `archive` 功能时无法解压
+#[cfg(not(feature = "archive"))]
+pub async fn extract(_path: &Path, _format: Format, _dir: &Path, _bar: ProgressBar) -> Result {
+    Err(anyhow!(Msg::ArchiveUnsupported))
+}
+
+/// 读取时更新进度条的文件
+#[cfg(feature = "archive")]
+struct Tracked<R> {
+    inner: R,
+    bar: ProgressBar,
+}
+
+#[cfg(feature = "archive")]
+impl<R: std::io::Read> std::io::Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let n = self.inner.read(buf)?;
+        self.bar.inc(n as u64);
+        Ok(n)
+    }
+}
+
+/// zip 先读取末尾的目录再读取各条目，进度随读取位置跳动
+#[cfg(feature = "archive")]
+impl<R: std::io::Seek> std::io::Seek for Tracked<R> {
+    fn seek(&mut self, pos: std::io::SeekFrom) -> std::io::Result<u64> {
+        let position = self.inner.seek(pos)?;
+        self.bar.set_position(position);
+        Ok(position)
+    }
+}
@@ -0,0 +1,78 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 凭据的认证方式
+#[derive(Clone, Copy)]
+pub enum Scheme {
+    Basic,
+    Bearer,
+}
+
+impl FromStr for Scheme {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "basic" => Ok(Self::Basic),
+            "bearer" => Ok(Self::Bearer),
+            _ => Err(anyhow!(Msg::UnknownAuthScheme(s.to_string()))),
+        }
+    }
+}
+
+/// 请求使用的凭据
+pub enum Auth {
+    Basic { user: String, password: String },
+    Bearer(String),
+}
+
+impl Auth {
+    /// `Authorization` 请求头的值
+    pub fn header_value(&self) -> String {
+        match self {
+            Self::Basic { user, password } => {
+                format!(
+                    "Basic {}",
+                    STANDARD.encode(format!("{}:{}", user, password))
+                )
+            }
+            Self::Bearer(token) => format!("Bearer {}", token),
+        }
+    }
+
+    /// 从系统钥匙串读取 `service` 中 `user` 的密码或令牌
+    pub fn from_keyring(service: &str, user: &str, scheme: Scheme) -> Result<Self> {
+        let secret = keyring_secret(service, user)?;
+        Ok(match scheme {
+            Scheme::Basic => Self::Basic {
+                user: user.to_string(),
+                password: secret,
+            },
+            Scheme::Bearer => Self::Bearer(secret),
+        })
+    }
+}
+
+#[cfg(feature = "keyring")]
+fn keyring_secret(service: &str, user: &str) -> Result<String> {
+    match keyring::Entry::new(service, user)?.get_password() {
+        Ok(t) => Ok(t),
+        Err(keyring::Error::NoEntry) => Err(anyhow!(Msg::KeyringMissing {
+            service: service.to_string(),
+            user: user.to_string(),
+        })),
+        Err(e) => Err(e.into()),
+    }
+}
+
+/// 未启用 `keyring` 功能时无法读取钥匙串
+#[cfg(not(feature = "keyring"))]
+fn keyring_secret(_service: &str, _user: &str) -> Result<String> {
+    Err(anyhow!(Msg::KeyringUnsupported))
+}
@@ -0,0 +1,299 @@
+//! 下载 Azure Blob Storage 中的 blob
+//!
+//! `az://<account>/<container>/<blob>` 转为 `https://<account>.blob.core.windows.net/<container>/<blob>`，
+//! 使用 `--backend azure` 时直接使用给出的 http(s) 地址。SAS 令牌附加在查询参数中；使用账户密钥时每个请求以
+//! Shared Key 签名，签名包含 `Range` 等请求头，需在请求头齐全后计算
+
+use std::collections::HashMap;
+use std::env;
+use std::sync::Mutex;
+use std::time::SystemTime;
+
+use anyhow::anyhow;
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use hyper::header::{
+    HeaderName, HeaderValue, AUTHORIZATION, CONTENT_ENCODING, CONTENT_LANGUAGE, CONTENT_LENGTH,
+    CONTENT_TYPE, DATE, IF_MATCH, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE, RANGE,
+};
+use hyper::{Body, Request, Uri};
+
+use crate::message::Msg;
+use crate::proxy::percent_decode;
+use crate::util::hmac_sha256;
+use crate::Result;
+
+/// Shared Key 签名的请求使用的 API 版本
+const VERSION: &str = "2021-08-06";
+const DEFAULT_ENDPOINT_SUFFIX: &str = "core.windows.net";
+
+/// 一次运行中使用账户密钥签名的各主机
+#[derive(Default)]
+pub struct State {
+    signers: Mutex<Vec<Signer>>,
+}
+
+/// 使用账户密钥签名的主机及对应的账户和密钥
+#[derive(Clone)]
+struct Signer {
+    host: String,
+    account: String,
+    key: Vec<u8>,
+}
+
+/// 环境变量中的账户配置，`AZURE_STORAGE_CONNECTION_STRING` 优先
+#[derive(Default)]
+struct Settings {
+    account: Option<String>,
+    key: Option<String>,
+    sas: Option<String>,
+    /// 连接字符串中的 `BlobEndpoint`，如 Azurite 的 `http://127.0.0.1:10000/devstoreaccount1`
+    endpoint: Option<String>,
+    protocol: Option<String>,
+    suffix: Option<String>,
+}
+
+impl Settings {
+    fn from_env() -> Self {
+        let mut settings = Self::default();
+        if let Ok(text) = env::var("AZURE_STORAGE_CONNECTION_STRING") {
+            let mut pairs: HashMap<&str, &str> = text
+                .split(';')
+                .filter_map(|t| t.split_once('='))
+                .map(|(key, value)| (key.trim(), value.trim()))
+                .collect();
+            let mut take = |key| pairs.remove(key).map(String::from);
+            settings = Self {
+                account: take("AccountName"),
+                key: take("AccountKey"),
+                sas: take("SharedAccessSignature"),
+                endpoint: take("BlobEndpoint"),
+                protocol: take("DefaultEndpointsProtocol"),
+                suffix: take("EndpointSuffix"),
+            };
+        }
+        let var = |name| env::var(name).ok();
+        settings.account = settings.account.or_else(|| var("AZURE_STORAGE_ACCOUNT"));
+        settings.key = settings.key.or_else(|| var("AZURE_STORAGE_KEY"));
+        settings.sas = settings.sas.or_else(|| var("AZURE_STORAGE_SAS_TOKEN"));
+        settings
+    }
+}
+
+impl State {
+    /// 把 `az://<account>/<container>/<blob>` 转为 blob 的地址，其他地址按 Azure 的地址处理
+    ///
+    /// 地址中没有 SAS 令牌时依次使用环境变量中的 SAS 令牌及账户密钥，都没有时匿名访问
+    pub fn resolve(&self, uri: &Uri) -> Result<Uri> {
+        let settings = Settings::from_env();
+        let (account, resolved) = if uri.scheme_str() == Some("az") {
+            let invalid = || anyhow!(Msg::InvalidAzureUri(uri.to_string()));
+            let account = uri.host().ok_or_else(invalid)?.to_string();
+            let path = uri.path();
+            match path.trim_start_matches('/').split_once('/') {
+                Some((container, blob)) if !container.is_empty() && !blob.is_empty() => {}
+                _ => return Err(invalid()),
+            }
+            let endpoint = match &settings.endpoint {
+                Some(t) => t.trim_end_matches('/').to_string(),
+                None => format!(
+                    "{}://{}.blob.{}",
+                    settings.protocol.as_deref().unwrap_or("https"),
+                    account,
+                    settings
+                        .suffix
+                        .as_deref()
+                        .unwrap_or(DEFAULT_ENDPOINT_SUFFIX)
+                ),
+            };
+            let mut resolved = format!("{}{}", endpoint, path);
+            if let Some(query) = uri.query() {
+                resolved = format!("{}?{}", resolved, query);
+            }
+            (account, resolved.parse()?)
+        } else {
+            // 未配置账户时取主机名的第一段，如 `<account>.blob.core.windows.net`
+            let account = match &settings.account {
+                Some(t) => t.clone(),
+                None => {
+                    let host = uri.host().unwrap_or_default();
+                    host.split('.').next().unwrap_or_default().to_string()
+                }
+            };
+            (account, uri.clone())
+        };
+        if resolved.query().is_some() {
+            return Ok(resolved);
+        }
+        if let Some(sas) = &settings.sas {
+            let resolved = format!("{}?{}", resolved, sas.trim_start_matches('?'));
+            return Ok(resolved.parse()?);
+        }
+        if let Some(key) = &settings.key {
+            let key = STANDARD
+                .decode(key)
+                .map_err(|_| anyhow!(Msg::InvalidAzureKey))?;
+            let host = resolved
+                .authority()
+                .map(|t| t.to_string())
+                .unwrap_or_default();
+            // 探测、镜像及重试都会再次解析，同一主机只保留一项
+            let mut signers = self.signers.lock().unwrap();
+            let signer = Signer { host, account, key };
+            match signers.iter_mut().find(|t| t.host == signer.host) {
+                Some(t) => *t = signer,
+                None => signers.push(signer),
+            }
+        }
+        Ok(resolved)
+    }
+
+    /// 使用账户密钥时为请求附加 Shared Key 签名，需在设置完其他请求头后调用
+    pub fn sign(&self, request: &mut Request<Body>) {
+        let signers = self.signers.lock().unwrap();
+        let host = request.uri().authority().map(|t| t.as_str());
+        let Signer { account, key, .. } =
+            match signers.iter().find(|t| Some(t.host.as_str()) == host) {
+                Some(t) => t.clone(),
+                None => return,
+            };
+        drop(signers);
+        let headers = request.headers_mut();
+        let date = httpdate::fmt_http_date(SystemTime::now());
+        for (name, value) in [("x-ms-date", date.as_str()), ("x-ms-version", VERSION)] {
+            if let Ok(value) = HeaderValue::from_str(value) {
+                headers.insert(HeaderName::from_static(name), value);
+            }
+        }
+        let string_to_sign = string_to_sign(request, &account);
+        let signature = STANDARD.encode(hmac_sha256(&key, string_to_sign.as_bytes()));
+        let authorization = format!("SharedKey {}:{}", account, signature);
+        if let Ok(value) = HeaderValue::from_str(&authorization) {
+            request.headers_mut().insert(AUTHORIZATION, value);
+        }
+    }
+}
+
+/// Shared Key 签名的内容：方法、标准请求头、按名称排序的 `x-ms-*` 请求头及规范化的资源
+fn string_to_sign(request: &Request<Body>, account: &str) -> String {
+    let headers = request.headers();
+    let header = |name: &HeaderName| {
+        headers
+            .get(name)
+            .and_then(|t| t.to_str().ok())
+            .unwrap_or_default()
+    };
+    let mut lines = vec![request.method().to_string()];
+    for name in [&CONTENT_ENCODING, &CONTENT_LANGUAGE] {
+        lines.push(header(name).to_string());
+    }
+    // `Content-Length` 为 0 时留空
+    lines.push(match header(&CONTENT_LENGTH) {
+        "0" => String::new(),
+        t => t.to_string(),
+    });
+    let content_md5 = HeaderName::from_static("content-md5");
+    for name in [
+        &content_md5,
+        &CONTENT_TYPE,
+        &DATE,
+        &IF_MODIFIED_SINCE,
+        &IF_MATCH,
+        &IF_NONE_MATCH,
+        &IF_UNMODIFIED_SINCE,
+        &RANGE,
+    ] {
+        lines.push(header(name).to_string());
+    }
+    let mut ms_headers: Vec<_> = headers
+        .keys()
+        .filter(|t| t.as_str().starts_with("x-ms-"))
+        .map(|t| {
+            let values: Vec<_> = headers
+                .get_all(t)
+                .iter()
+                .filter_map(|t| t.to_str().ok())
+                .map(str::trim)
+                .collect();
+            format!("{}:{}", t, values.join(","))
+        })
+        .collect();
+    ms_headers.sort();
+    lines.extend(ms_headers);
+    let uri = request.uri();
+    let mut resource = format!("/{}{}", account, uri.path());
+    let mut params: Vec<(String, String)> = uri
+        .query()
+        .unwrap_or_default()
+        .split('&')
+        .filter(|t| !t.is_empty())
+        .map(|t| {
+            let (name, value) = t.split_once('=').unwrap_or((t, ""));
+            let decode = |t| String::from_utf8_lossy(&percent_decode(t)).into_owned();
+            (decode(name).to_lowercase(), decode(value))
+        })
+        .collect();
+    params.sort();
+    // 同名参数的值以逗号连接
+    let mut merged: Vec<(String, Vec<String>)> = Vec::new();
+    for (name, value) in params {
+        match merged.last_mut() {
+            Some((last, values)) if *last == name => values.push(value),
+            _ => merged.push((name, vec![value])),
+        }
+    }
+    for (name, values) in merged {
+        resource.push_str(&format!("\n{}:{}", name, values.join(",")));
+    }
+    lines.push(resource);
+    lines.join("\n")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(uri: &str, headers: &[(&str, &str)]) -> Request<Body> {
+        let mut builder = Request::get(uri);
+        for (name, value) in headers {
+            builder = builder.header(*name, *value);
+        }
+        builder.body(Body::empty()).unwrap()
+    }
+
+    #[test]
+    fn string_to_sign_lists_headers_and_resource() {
+        let request = request(
+            "https://myaccount.blob.core.windows.net/mycontainer/my%20blob.bin",
+            &[
+                ("x-ms-version", VERSION),
+                ("x-ms-date", "Fri, 26 Jun 2015 23:39:12 GMT"),
+                ("range", "bytes=0-1023"),
+                ("content-length", "0"),
+            ],
+        );
+        let expected = "GET\n\n\n\n\n\n\n\n\n\n\nbytes=0-1023\n\
+            x-ms-date:Fri, 26 Jun 2015 23:39:12 GMT\n\
+            x-ms-version:2021-08-06\n\
+            /myaccount/mycontainer/my%20blob.bin";
+        assert_eq!(string_to_sign(&request, "myaccount"), expected);
+    }
+
+    #[test]
+    fn canonicalized_resource_matches_documented_examples() {
+        // “Authorize with Shared Key” 中规范化资源的示例
+        for (uri, expected) in [
+            (
+                "https://myaccount.blob.core.windows.net/mycontainer?restype=container&comp=metadata",
+                "/myaccount/mycontainer\ncomp:metadata\nrestype:container",
+            ),
+            (
+                "https://myaccount.blob.core.windows.net/mycontainer?restype=container&comp=list&include=snapshots&include=metadata&include=uncommittedblobs",
+                "/myaccount/mycontainer\ncomp:list\ninclude:metadata,snapshots,uncommittedblobs\nrestype:container",
+            ),
+        ] {
+            let string_to_sign = string_to_sign(&request(uri, &[]), "myaccount");
+            assert_eq!(string_to_sign.lines().skip(12).collect::<Vec<_>>().join("\n"), expected);
+        }
+    }
+}
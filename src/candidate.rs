@@ -0,0 +1,112 @@
+use std::cmp::Ordering;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+
+use crate::message::Msg;
+
+/// 候选 URI 的选择标准
+#[derive(Clone, Copy)]
+pub enum Criterion {
+    /// 探测延迟最低
+    Latency,
+    /// 支持 range 请求
+    Ranges,
+    /// 资源大小与期望一致
+    Size,
+}
+
+impl FromStr for Criterion {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim() {
+            "latency" => Ok(Self::Latency),
+            "ranges" => Ok(Self::Ranges),
+            "size" => Ok(Self::Size),
+            _ => Err(anyhow!(Msg::UnknownCriterion(s.to_string()))),
+        }
+    }
+}
+
+/// 候选 URI 的探测结果
+pub struct Candidate {
+    pub latency: Duration,
+    pub accept_ranges: bool,
+    pub content_length: usize,
+}
+
+/// 按 `criteria` 的先后顺序比较，选出最佳候选并返回其下标
+///
+/// 未指定 `expected_size` 时，以候选中出现次数最多的大小作为期望大小
+pub fn select(
+    candidates: &[Candidate],
+    criteria: &[Criterion],
+    expected_size: Option<usize>,
+) -> Option<usize> {
+    let expected_size = expected_size.or_else(|| most_common_size(candidates));
+    (0..candidates.len()).min_by(|&a, &b| {
+        let (a, b) = (&candidates[a], &candidates[b]);
+        criteria
+            .iter()
+            .map(|criterion| match criterion {
+                Criterion::Latency => a.latency.cmp(&b.latency),
+                Criterion::Ranges => b.accept_ranges.cmp(&a.accept_ranges),
+                Criterion::Size => {
+                    let matches = |t: &Candidate| Some(t.content_length) == expected_size;
+                    matches(b).cmp(&matches(a))
+                }
+            })
+            .find(|t| *t != Ordering::Equal)
+            .unwrap_or(Ordering::Equal)
+    })
+}
+
+fn most_common_size(candidates: &[Candidate]) -> Option<usize> {
+    let mut counts = HashMap::new();
+    for candidate in candidates {
+        *counts.entry(candidate.content_length).or_insert(0) += 1;
+    }
+    counts
+        .into_iter()
+        .max_by_key(|&(size, count)| (count, size))
+        .map(|(size, _)| size)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn select_follows_criteria_order() {
+        let candidate = |millis, accept_ranges, content_length| Candidate {
+            latency: Duration::from_millis(millis),
+            accept_ranges,
+            content_length,
+        };
+        // 最快的没有 range 支持，第二快的大小与多数不同
+        let candidates = [
+            candidate(10, false, 100),
+            candidate(20, true, 99),
+            candidate(30, true, 100),
+            candidate(40, true, 100),
+        ];
+        let criteria =
+            |s: &str| -> Vec<Criterion> { s.split(',').map(|t| t.parse().unwrap()).collect() };
+        for (order, expected_size, expected) in [
+            ("latency", None, Some(0)),
+            ("ranges,latency", None, Some(1)),
+            ("ranges,size,latency", None, Some(2)),
+            ("size,latency", None, Some(0)),
+            ("size,latency", Some(99), Some(1)),
+            ("ranges,size,latency", Some(99), Some(1)),
+        ] {
+            let selected = select(&candidates, &criteria(order), expected_size);
+            assert_eq!(selected, expected, "{:?} {:?}", order, expected_size);
+        }
+        assert_eq!(select(&[], &criteria("latency"), None), None);
+        assert!("speed".parse::<Criterion>().is_err());
+    }
+}
@@ -0,0 +1,303 @@
+use std::fmt::{self, Display, Formatter};
+use std::path::Path;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use md5::Md5;
+use sha1::Sha1;
+use sha2::{Digest, Sha256, Sha512};
+use tokio::fs::File;
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 摘要算法，按强度从低到高排列
+#[derive(Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Algorithm {
+    Md5,
+    Sha1,
+    Sha256,
+    Sha512,
+}
+
+impl Algorithm {
+    /// 解析 HTTP 摘要头中的算法名称
+    fn parse(name: &str) -> Option<Self> {
+        match name.to_ascii_lowercase().as_str() {
+            "md5" => Some(Self::Md5),
+            "sha" | "sha-1" | "sha1" => Some(Self::Sha1),
+            "sha-256" | "sha256" => Some(Self::Sha256),
+            "sha-512" | "sha512" => Some(Self::Sha512),
+            _ => None,
+        }
+    }
+
+    pub fn hasher(self) -> Hasher {
+        match self {
+            Self::Md5 => Hasher::Md5(Md5::new()),
+            Self::Sha1 => Hasher::Sha1(Sha1::new()),
+            Self::Sha256 => Hasher::Sha256(Sha256::new()),
+            Self::Sha512 => Hasher::Sha512(Sha512::new()),
+        }
+    }
+
+    /// 摘要的字节数
+    fn len(self) -> usize {
+        match self {
+            Self::Md5 => 16,
+            Self::Sha1 => 20,
+            Self::Sha256 => 32,
+            Self::Sha512 => 64,
+        }
+    }
+}
+
+impl Display for Algorithm {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Md5 => "md5",
+            Self::Sha1 => "sha1",
+            Self::Sha256 => "sha256",
+            Self::Sha512 => "sha512",
+        })
+    }
+}
+
+/// 增量计算摘要
+pub enum Hasher {
+    Md5(Md5),
+    Sha1(Sha1),
+    Sha256(Sha256),
+    Sha512(Sha512),
+}
+
+impl Hasher {
+    pub fn update(&mut self, data: &[u8]) {
+        match self {
+            Self::Md5(h) => h.update(data),
+            Self::Sha1(h) => h.update(data),
+            Self::Sha256(h) => h.update(data),
+            Self::Sha512(h) => h.update(data),
+        }
+    }
+
+    pub fn finalize(self) -> Vec<u8> {
+        match self {
+            Self::Md5(h) => h.finalize().to_vec(),
+            Self::Sha1(h) => h.finalize().to_vec(),
+            Self::Sha256(h) => h.finalize().to_vec(),
+            Self::Sha512(h) => h.finalize().to_vec(),
+        }
+    }
+}
+
+/// 期望的摘要值
+#[derive(Clone, PartialEq, Eq)]
+pub struct Checksum {
+    pub algorithm: Algorithm,
+    pub value: Vec<u8>,
+}
+
+impl Checksum {
+    /// 解析 `Digest`（RFC 3230）、`Content-Digest`/`Repr-Digest`（RFC 9530）头，
+    /// 存在多个算法时选择最强的一个
+    pub fn parse_header(value: &str) -> Option<Self> {
+        value
+            .split(',')
+            .filter_map(|item| {
+                let (name, value) = item.trim().split_once('=')?;
+                let algorithm = Algorithm::parse(name.trim())?;
+                let value = STANDARD.decode(value.trim().trim_matches(':')).ok()?;
+                Some(Self { algorithm, value })
+            })
+            .max_by_key(|t| t.algorithm)
+    }
+
+    /// 解析 `Content-MD5` 头
+    pub fn parse_content_md5(value: &str) -> Option<Self> {
+        Some(Self {
+            algorithm: Algorithm::Md5,
+            value: STANDARD.decode(value.trim()).ok()?,
+        })
+    }
+
+    pub fn hasher(&self) -> Hasher {
+        self.algorithm.hasher()
+    }
+
+    pub fn verify(&self, actual: &[u8]) -> Result {
+        if self.value != actual {
+            return Err(anyhow!(Msg::ChecksumMismatch {
+                algorithm: self.algorithm.to_string(),
+                expected: hex(&self.value),
+                actual: hex(actual),
+            }));
+        }
+        Ok(())
+    }
+}
+
+impl FromStr for Checksum {
+    type Err = Error;
+
+    /// 解析 `<algorithm>:<hex>`，如 `sha256:e3b0c442...`
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!(Msg::InvalidChecksum(s.to_string()));
+        let (name, value) = s.trim().split_once(':').ok_or_else(invalid)?;
+        let algorithm = Algorithm::parse(name).ok_or_else(invalid)?;
+        if value.len() != algorithm.len() * 2 || !value.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return Err(invalid());
+        }
+        let value = (0..value.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(&value[i..i + 2], 16))
+            .collect::<std::result::Result<_, _>>()
+            .map_err(|_| invalid())?;
+        Ok(Self { algorithm, value })
+    }
+}
+
+impl Display for Checksum {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.algorithm, hex(&self.value))
+    }
+}
+
+/// 在 `sha256sum` 格式（`<hex>  <name>` 或 `<hex> *<name>`）或 BSD 格式（`SHA256 (<name>) = <hex>`）的摘要文件中
+/// 查找 `name` 的 SHA-256，文件名带有目录时按最后一段匹配；只有一行且没有文件名时视为该文件的摘要
+pub fn find_sha256(text: &str, name: &str) -> Option<Checksum> {
+    let lines: Vec<_> = text
+        .lines()
+        .map(str::trim)
+        .filter(|t| !t.is_empty() && !t.starts_with('#'))
+        .collect();
+    lines.iter().find_map(|line| {
+        let (value, file) = match line.strip_prefix("SHA256 (") {
+            Some(rest) => {
+                let (file, value) = rest.rsplit_once(") = ")?;
+                (value, file)
+            }
+            None => match line.split_once(char::is_whitespace) {
+                Some((value, file)) => (value, file.trim_start().trim_start_matches('*')),
+                None if lines.len() == 1 => (*line, name),
+                None => return None,
+            },
+        };
+        if file.rsplit('/').next() != Some(name) {
+            return None;
+        }
+        format!("sha256:{}", value).parse().ok()
+    })
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+/// 计算文件从 `offset` 开始、长度为 `len` 部分的摘要
+pub async fn hash_file(
+    path: impl AsRef<Path>,
+    offset: u64,
+    len: u64,
+    mut hasher: Hasher,
+) -> Result<Vec<u8>> {
+    let mut file = File::open(path).await?;
+    file.seek(SeekFrom::Start(offset)).await?;
+    let mut file = file.take(len);
+    let mut buffer = vec![0; 64 * 1024];
+    loop {
+        let n = file.read(&mut buffer).await?;
+        if n == 0 {
+            break;
+        }
+        hasher.update(&buffer[..n]);
+    }
+    Ok(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MD5: &str = "d41d8cd98f00b204e9800998ecf8427e";
+    const SHA256: &str = "e3b0c44298fc1c149afbf4c8996fb92427ae41e4649b934ca495991b7852b855";
+
+    #[test]
+    fn checksum_option_is_parsed() {
+        for (input, expected) in [
+            (format!("md5:{}", MD5), Some(format!("md5:{}", MD5))),
+            (format!("MD5:{}", MD5), Some(format!("md5:{}", MD5))),
+            (
+                format!("sha-256:{}", SHA256),
+                Some(format!("sha256:{}", SHA256)),
+            ),
+            (
+                format!(" sha256:{} ", SHA256.to_ascii_uppercase()),
+                Some(format!("sha256:{}", SHA256)),
+            ),
+            (
+                format!("sha1:{}", "a".repeat(40)),
+                Some(format!("sha1:{}", "a".repeat(40))),
+            ),
+            (
+                format!("sha512:{}", "0".repeat(128)),
+                Some(format!("sha512:{}", "0".repeat(128))),
+            ),
+            // 缺少算法、算法未知、长度与算法不符或不是十六进制
+            (SHA256.to_string(), None),
+            (format!("crc32:{}", MD5), None),
+            (format!("sha256:{}", MD5), None),
+            (format!("md5:{}0", MD5), None),
+            (format!("md5:{}", "g".repeat(32)), None),
+            (format!("md5:{}", "+f".repeat(16)), None),
+            (format!("md5:{}日", &MD5[..29]), None),
+        ] {
+            let actual = input.parse::<Checksum>().ok().map(|t| t.to_string());
+            assert!(actual == expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn digest_header_is_parsed() {
+        // 空内容的 MD5 及 SHA-256
+        let md5 = "1B2M2Y8AsgTpgAmY7PhCfg==";
+        let sha256 = "47DEQpj8HBSa+/TImW+5JCeuQeRkm5NMpJWZG3hSuFU=";
+        for (input, expected) in [
+            (format!("md5={}", md5), Some(format!("md5:{}", MD5))),
+            (
+                format!("SHA-256={}", sha256),
+                Some(format!("sha256:{}", SHA256)),
+            ),
+            (
+                format!("sha-256=:{}:", sha256),
+                Some(format!("sha256:{}", SHA256)),
+            ),
+            (
+                format!("sha-256=:{}:, md5=:{}:", sha256, md5),
+                Some(format!("sha256:{}", SHA256)),
+            ),
+            (
+                format!("md5={}, sha-256={}", md5, sha256),
+                Some(format!("sha256:{}", SHA256)),
+            ),
+            (
+                format!("unixsum=30637, md5={}", md5),
+                Some(format!("md5:{}", MD5)),
+            ),
+            (
+                format!("sha-256=!!!, md5={}", md5),
+                Some(format!("md5:{}", MD5)),
+            ),
+            ("unixsum=30637".to_string(), None),
+            (String::new(), None),
+        ] {
+            let actual = Checksum::parse_header(&input).map(|t| t.to_string());
+            assert!(actual == expected, "{:?}", input);
+        }
+        let actual = Checksum::parse_content_md5(md5).map(|t| t.to_string());
+        assert_eq!(actual, Some(format!("md5:{}", MD5)));
+    }
+}
@@ -1,49 +1,1672 @@
-use std::env::temp_dir;
+use std::env::{self, temp_dir};
+use std::net::SocketAddr;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::AtomicUsize;
+use std::time::{Duration, Instant};
 
 use anyhow::anyhow;
-use clap::{crate_authors, crate_description, crate_name, crate_version, Arg, Command};
+use clap::{crate_authors, crate_description, crate_name, crate_version, Arg, ArgMatches, Command};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::http::uri::Authority;
 use hyper::Uri;
+use sha2::{Digest, Sha256};
+use time::format_description::well_known::Rfc3339;
+use time::{OffsetDateTime, Time};
+use tracing::Level;
 use uuid::Uuid;
 
+use crate::auth::Auth;
+use crate::candidate::Criterion;
+use crate::checksum::Checksum;
+use crate::chunker::ChunkSize;
+use crate::defaults;
+use crate::filename;
+use crate::handle::Handle;
+use crate::history;
+use crate::init::Init;
+use crate::limit::{self, Schedule};
+use crate::message::{help, Msg};
+use crate::metalink::Metalink;
+use crate::netrc::Netrc;
+use crate::piece::{PieceHashes, Pieces};
+use crate::proxy::{Header, Proxies};
+use crate::retry::Retry;
+use crate::style::{Preset, PRESETS};
+use crate::transport::Transport;
 use crate::Result;
 
-pub struct Config {
-    pub size: usize,
+/// 执行的操作
+pub enum Action {
+    /// 并发下载文件
+    Download {
+        size: usize,
+        uri: Uri,
+        output: OutputTarget,
+    },
+    /// 批量下载多个资源，所有资源共用 `size` 个连接
+    Batch { size: usize, targets: Vec<Target> },
+    /// 仅输出资源大小
+    Size { uri: Uri, human: bool },
+    /// 合并临时文件目录中已下载的块文件
+    Merge { blocks: usize, file_path: String },
+    /// 作为守护进程运行，通过兼容 aria2 的 JSON-RPC 添加及管理下载
+    Daemon {
+        addr: SocketAddr,
+        secret: Option<String>,
+        /// 未指定 `dir` 时的保存目录
+        dir: PathBuf,
+        /// 未指定 `split` 时每个下载的连接数
+        split: usize,
+        /// 是否在 `/` 上提供网页界面
+        web_ui: bool,
+    },
+    /// 列出下载历史
+    History {
+        status: Option<String>,
+        /// 地址或路径中含有的文本
+        search: Option<String>,
+        limit: usize,
+        json: bool,
+    },
+}
+
+/// 下载的保存位置
+#[derive(Clone)]
+pub enum OutputTarget {
+    /// 指定的保存路径
+    Path(String),
+    /// 由 `Content-Disposition` 或最终 URI 推断文件名，保存到该目录
+    Infer(PathBuf),
+}
+
+/// 批量下载中的一项
+pub struct Target {
     pub uri: Uri,
     pub file_path: String,
+    /// 该项使用的临时文件目录
+    pub temp_file_dir: PathBuf,
+}
+
+/// 调用 `sync_all` 将数据写入磁盘的时机
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Fsync {
+    /// 不主动同步
+    Never,
+    /// 完成后同步一次
+    End,
+    /// 下载过程中按间隔同步，完成后再同步一次
+    Periodic(Duration),
+}
+
+/// 206 响应的 `Content-Range` 与请求的范围不一致时的处理方式
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RangeMismatch {
+    /// 直接报错
+    Strict,
+    /// 按服务器返回的范围重新对应写入位置
+    Lenient,
+}
+
+/// `--backend` 指定的存储服务，http(s) 地址按该服务的方式认证
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Backend {
+    Azure,
+    WebDav,
+}
+
+/// `--stats` 指定的统计输出格式
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Stats {
+    Text,
+    Json,
+}
+
+/// `--progress` 指定的进度输出方式
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum Progress {
+    Bars,
+    /// 每行一个 JSON 事件写入标准输出
+    Json,
+    /// 定期输出一行总进度
+    Plain,
+}
+
+pub struct Config {
+    pub action: Action,
+    /// 块文件所在的临时目录
     pub temp_file_dir: PathBuf,
+    /// 自定义 `Host` 请求头
+    pub host: Option<String>,
+    /// TLS SNI 使用的服务器名称
+    pub server_name: Option<String>,
+    /// 附加在每个请求上的请求头
+    pub headers: Vec<(HeaderName, HeaderValue)>,
+    /// 按资源地址选择的 HTTP 代理
+    pub proxy: Proxies,
+    /// 允许重定向到的主机，为空时不限制
+    pub allowed_hosts: Vec<String>,
+    /// 最多跟随的重定向次数
+    pub max_redirects: usize,
+    /// 请求使用的凭据
+    pub auth: Option<Auth>,
+    /// 未指定凭据时从中查找各主机凭据的 `.netrc`
+    pub netrc: Option<Netrc>,
+    /// SFTP 登录使用的私钥
+    pub ssh_key: Option<PathBuf>,
+    pub retry: Retry,
+    /// 建立连接的超时时间
+    pub connect_timeout: Option<Duration>,
+    /// 读取响应体时两次收到数据之间的最长间隔
+    pub read_timeout: Option<Duration>,
+    /// 可选的传输方式，首个用于探测及首次请求
+    pub transports: Vec<Transport>,
+    pub backend: Option<Backend>,
+    /// 下载失败时保留临时文件
+    pub keep_partial: bool,
+    /// 输出详细信息
+    pub verbose: bool,
+    /// 进度条样式
+    pub progress_style: Preset,
+    pub progress: Progress,
+    /// 只输出错误
+    pub quiet: bool,
+    pub stats: Stats,
+    /// 结构化日志的级别
+    pub log_level: Option<Level>,
+    /// 结构化日志写入的文件
+    pub log_file: Option<PathBuf>,
+    /// 完成后在标准输出打印文件的绝对路径
+    pub print_path: bool,
+    /// 下载成功及失败后执行的命令
+    pub on_complete: Option<String>,
+    pub on_error: Option<String>,
+    /// 下载结束后发送桌面通知
+    pub notify: bool,
+    /// 下载结束后 POST 通知的地址
+    pub notify_webhook: Option<Uri>,
+    /// 记录下载历史的数据库，`--no-history` 时为空
+    pub history_file: Option<PathBuf>,
+    /// 合并块文件时使用的缓冲区大小
+    pub merge_buffer: usize,
+    /// 下载完成后设置的文件权限
+    pub chmod: Option<u32>,
+    pub fsync: Fsync,
+    /// 所有连接合计的速度上限（字节/秒）
+    pub limit_rate: Option<u64>,
+    /// 按时段调整的合计速度上限
+    pub limit_schedule: Option<Schedule>,
+    /// 每个连接的速度上限（字节/秒）
+    pub limit_rate_per_conn: Option<u64>,
+    /// 同时进行的下载数上限
+    pub max_concurrent_downloads: Option<usize>,
+    /// 所有下载合计的连接数上限
+    pub max_connections_total: Option<usize>,
+    /// 速度下限（字节/秒）及检测周期
+    pub speed_limit: Option<(u64, Duration)>,
+    /// 候选 URI，从中选出最佳的一个下载
+    pub candidates: Vec<Uri>,
+    /// Metalink 列出的镜像，各块分散到其中大小一致的镜像下载
+    pub mirrors: Vec<Uri>,
+    /// 下载前从各镜像试探下载的字节数，按测得的速度分配块
+    pub benchmark_mirrors: Option<usize>,
+    /// 候选 URI 的选择标准，按优先级排列
+    pub criteria: Vec<Criterion>,
+    /// 期望的资源大小
+    pub expected_size: Option<usize>,
+    /// 各块先写入临时文件目录中的块文件，完成后合并，默认直接写入预分配的输出文件
+    pub temp_blocks: bool,
+    /// 保证不创建临时文件目录，需要时报错
+    pub no_temp: bool,
+    /// 直接写入时为输出文件预先占用磁盘空间
+    pub preallocate: bool,
+    /// 获取下载地址的初始化请求
+    pub init: Option<Init>,
+    /// 沿用上次中断时留下的块文件继续下载
+    pub continue_download: bool,
+    /// 块最终失败时继续下载其余块，失败的部分留作空洞
+    pub allow_partial: bool,
+    /// 续传的部分下载文件
+    pub resume_from: Option<PathBuf>,
+    /// 开始下载后输出续传句柄
+    pub print_resume_handle: bool,
+    /// 继续下载使用的续传句柄
+    pub resume_handle: Option<Handle>,
+    /// 最终文件小于该大小时视为失败
+    pub min_size: Option<u64>,
+    /// 每次 multi-range 请求合并的块数
+    pub multi_range: Option<usize>,
+    /// 指标服务监听的地址
+    pub metrics_addr: Option<SocketAddr>,
+    /// 下载完成后验证的分离签名（地址或路径）及公钥文件
+    pub signature: Option<(String, PathBuf)>,
+    /// 下载完成后校验的摘要，优先于服务器声明的摘要
+    pub checksum: Option<Checksum>,
+    /// 从资源旁边的摘要文件获取 SHA-256
+    pub auto_checksum: bool,
+    /// 下载完成后解压压缩包
+    pub extract: bool,
+    /// 解压的目标目录，为空时解压到文件所在目录
+    pub extract_dir: Option<PathBuf>,
+    /// 块列表的输出路径及分块大小
+    pub chunks: Option<(PathBuf, ChunkSize)>,
+    /// 使用全屏仪表盘显示进度
+    pub tui: bool,
+    /// 提供已有前缀的本地文件
+    pub local_prefix: Option<PathBuf>,
+    /// 输出路径没有扩展名时按 `Content-Type` 追加
+    pub add_extension: bool,
+    pub range_mismatch: RangeMismatch,
+    /// 存在期间暂停下载的控制文件
+    pub pause_file: Option<PathBuf>,
+    /// 只下载首尾各一小段验证下载流程
+    pub smoke_test: bool,
+    /// 只探测并输出下载计划
+    pub dry_run: bool,
+    /// 仅下载的片及片的大小
+    pub pieces: Option<(Pieces, usize)>,
+    /// 各片的摘要，块下载完成后立即校验
+    pub piece_hashes: Option<PieceHashes>,
+    /// 开始下载的时间
+    pub start_at: Option<Instant>,
 }
 
 impl Config {
+    /// 解析命令行参数，参数有误时输出帮助并退出
     pub fn get() -> Result<Self> {
-        let matches = Command::new(crate_name!())
+        let command = Self::command();
+        let args = defaults::merge(&command, env::args().collect())?;
+        Self::from_matches(command.get_matches_from(args))
+    }
+
+    /// 以各选项的默认值用 `size` 个连接下载 `uri` 到 `file_path`，不读取命令行参数及默认选项文件
+    ///
+    /// 供 [`Downloader`](crate::Downloader) 使用，不记录下载历史
+    pub fn download(size: usize, uri: Uri, file_path: String) -> Result<Self> {
+        if size == 0 {
+            return Err(anyhow!(Msg::InvalidConnections(size.to_string())));
+        }
+        check_not_exists(&file_path)?;
+        Ok(Self {
+            action: Action::Download {
+                size,
+                uri,
+                output: OutputTarget::Path(file_path),
+            },
+            temp_file_dir: new_temp_file_dir(),
+            host: None,
+            server_name: None,
+            headers: Vec::new(),
+            proxy: Proxies::new(None, None, Vec::new())?,
+            allowed_hosts: Vec::new(),
+            max_redirects: 10,
+            auth: None,
+            netrc: None,
+            ssh_key: None,
+            retry: Retry {
+                attempts: 3,
+                base_delay: Duration::from_secs(1),
+                max_delay: Duration::from_secs(30),
+                max_overall: None,
+                deadline: None,
+                timeout: None,
+                timeout_backoff: 1.0,
+                timeout_cap: None,
+                switch_after: 2,
+                max_retry_after: Duration::from_secs(300),
+                overall: AtomicUsize::new(0),
+            },
+            connect_timeout: None,
+            read_timeout: None,
+            transports: vec![Transport::Http1],
+            backend: None,
+            keep_partial: false,
+            verbose: false,
+            progress_style: Preset::Default,
+            progress: Progress::Bars,
+            quiet: false,
+            stats: Stats::Text,
+            log_level: None,
+            log_file: None,
+            print_path: false,
+            on_complete: None,
+            on_error: None,
+            notify: false,
+            notify_webhook: None,
+            history_file: None,
+            merge_buffer: 64 * 1024,
+            chmod: None,
+            fsync: Fsync::Never,
+            limit_rate: None,
+            limit_schedule: None,
+            limit_rate_per_conn: None,
+            max_concurrent_downloads: None,
+            max_connections_total: None,
+            speed_limit: None,
+            candidates: Vec::new(),
+            mirrors: Vec::new(),
+            benchmark_mirrors: None,
+            criteria: vec![Criterion::Ranges, Criterion::Size, Criterion::Latency],
+            expected_size: None,
+            temp_blocks: false,
+            no_temp: false,
+            preallocate: true,
+            init: None,
+            continue_download: false,
+            allow_partial: false,
+            resume_from: None,
+            print_resume_handle: false,
+            resume_handle: None,
+            min_size: None,
+            multi_range: None,
+            metrics_addr: None,
+            signature: None,
+            checksum: None,
+            auto_checksum: false,
+            extract: false,
+            extract_dir: None,
+            chunks: None,
+            tui: false,
+            local_prefix: None,
+            add_extension: false,
+            range_mismatch: RangeMismatch::Strict,
+            pause_file: None,
+            smoke_test: false,
+            dry_run: false,
+            pieces: None,
+            piece_hashes: None,
+            start_at: None,
+        })
+    }
+
+    fn command() -> Command<'static> {
+        Command::new(crate_name!())
             .version(crate_version!())
             .author(crate_authors!())
             .about(crate_description!())
+            .subcommand_negates_reqs(true)
+            .args_conflicts_with_subcommands(true)
             .args(&[
-                Arg::new("size").help("并发任务数量").required(true),
-                Arg::new("uri").help("资源 URI").required(true),
-                Arg::new("file-path").help("保存文件路径").required(true),
+                Arg::new("size")
+                    .help(help("size"))
+                    .required_unless_present("resume-handle"),
+                Arg::new("uri")
+                    .help(help("uri"))
+                    .required_unless_present_any(["resume-handle", "url"]),
+                Arg::new("file-path").help(help("file-path")),
+                Arg::new("url")
+                    .long("url")
+                    .takes_value(true)
+                    .number_of_values(2)
+                    .multiple_occurrences(true)
+                    .value_names(&["uri", "file-path"])
+                    .conflicts_with_all(&[
+                        "resume-handle",
+                        "print-resume-handle",
+                        "resume-from",
+                        "local-prefix",
+                        "init-post",
+                        "candidates",
+                        "mirror",
+                        "benchmark-mirrors",
+                        "expected-size",
+                        "verify-signature",
+                        "checksum",
+                        "chunks",
+                        "smoke-test",
+                        "pieces",
+                        "piece-hashes",
+                    ])
+                    .help(help("url")),
+                Arg::new("config")
+                    .long("config")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("config")),
+                Arg::new("output-dir")
+                    .long("output-dir")
+                    .takes_value(true)
+                    .help(help("output-dir")),
+                Arg::new("lang")
+                    .long("lang")
+                    .takes_value(true)
+                    .possible_values(["zh", "en"])
+                    .global(true)
+                    .help(help("lang")),
+                Arg::new("host")
+                    .long("host")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("host")),
+                Arg::new("sni")
+                    .long("sni")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("sni")),
+                Arg::new("header")
+                    .long("header")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .global(true)
+                    .help(help("header")),
+                Arg::new("proxy")
+                    .long("proxy")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("proxy")),
+                Arg::new("proxy-header")
+                    .long("proxy-header")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .global(true)
+                    .help(help("proxy-header")),
+                Arg::new("no-proxy")
+                    .long("no-proxy")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("no-proxy")),
+                Arg::new("max-redirects")
+                    .long("max-redirects")
+                    .takes_value(true)
+                    .default_value("10")
+                    .global(true)
+                    .help(help("max-redirects")),
+                Arg::new("allowed-hosts")
+                    .long("allowed-hosts")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("allowed-hosts")),
+                Arg::new("user")
+                    .long("user")
+                    .takes_value(true)
+                    .global(true)
+                    .conflicts_with_all(&["token", "keyring-service"])
+                    .help(help("user")),
+                Arg::new("token")
+                    .long("token")
+                    .takes_value(true)
+                    .global(true)
+                    .conflicts_with("keyring-service")
+                    .help(help("token")),
+                Arg::new("netrc")
+                    .long("netrc")
+                    .global(true)
+                    .help(help("netrc")),
+                Arg::new("netrc-file")
+                    .long("netrc-file")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("netrc-file")),
+                Arg::new("keyring-service")
+                    .long("keyring-service")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("keyring-service")),
+                Arg::new("keyring-user")
+                    .long("keyring-user")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("keyring-user")),
+                Arg::new("keyring-auth")
+                    .long("keyring-auth")
+                    .takes_value(true)
+                    .possible_values(["bearer", "basic"])
+                    .default_value("bearer")
+                    .global(true)
+                    .help(help("keyring-auth")),
+                Arg::new("ssh-key")
+                    .long("ssh-key")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("ssh-key")),
+                Arg::new("retry")
+                    .long("retry")
+                    .takes_value(true)
+                    .default_value("3")
+                    .global(true)
+                    .help(help("retry")),
+                Arg::new("retry-delay")
+                    .long("retry-delay")
+                    .takes_value(true)
+                    .default_value("1")
+                    .global(true)
+                    .help(help("retry-delay")),
+                Arg::new("retry-max-delay")
+                    .long("retry-max-delay")
+                    .takes_value(true)
+                    .default_value("30")
+                    .global(true)
+                    .help(help("retry-max-delay")),
+                Arg::new("max-overall-retries")
+                    .long("max-overall-retries")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("max-overall-retries")),
+                Arg::new("max-time")
+                    .long("max-time")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("max-time")),
+                Arg::new("max-concurrent-downloads")
+                    .long("max-concurrent-downloads")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("max-concurrent-downloads")),
+                Arg::new("max-connections-total")
+                    .long("max-connections-total")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("max-connections-total")),
+                Arg::new("timeout")
+                    .long("timeout")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("timeout")),
+                Arg::new("connect-timeout")
+                    .long("connect-timeout")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("connect-timeout")),
+                Arg::new("read-timeout")
+                    .long("read-timeout")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("read-timeout")),
+                Arg::new("max-retry-after")
+                    .long("max-retry-after")
+                    .takes_value(true)
+                    .default_value("300")
+                    .global(true)
+                    .help(help("max-retry-after")),
+                Arg::new("timeout-backoff")
+                    .long("timeout-backoff")
+                    .takes_value(true)
+                    .default_value("1")
+                    .global(true)
+                    .help(help("timeout-backoff")),
+                Arg::new("timeout-cap")
+                    .long("timeout-cap")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("timeout-cap")),
+                Arg::new("transports")
+                    .long("transports")
+                    .takes_value(true)
+                    .default_value("h1")
+                    .global(true)
+                    .help(help("transports")),
+                Arg::new("http2")
+                    .long("http2")
+                    .conflicts_with("transports")
+                    .global(true)
+                    .help(help("http2")),
+                Arg::new("http3")
+                    .long("http3")
+                    .conflicts_with_all(&["transports", "http2"])
+                    .global(true)
+                    .help(help("http3")),
+                Arg::new("backend")
+                    .long("backend")
+                    .takes_value(true)
+                    .possible_values(["azure", "webdav"])
+                    .global(true)
+                    .help(help("backend")),
+                Arg::new("switch-after")
+                    .long("switch-after")
+                    .takes_value(true)
+                    .default_value("2")
+                    .global(true)
+                    .help(help("switch-after")),
+                Arg::new("verbose")
+                    .short('v')
+                    .long("verbose")
+                    .global(true)
+                    .help(help("verbose")),
+                Arg::new("quiet")
+                    .short('q')
+                    .long("quiet")
+                    .conflicts_with("verbose")
+                    .global(true)
+                    .help(help("quiet")),
+                Arg::new("stats")
+                    .long("stats")
+                    .takes_value(true)
+                    .possible_values(["text", "json"])
+                    .default_value("text")
+                    .global(true)
+                    .help(help("stats")),
+                Arg::new("log-level")
+                    .long("log-level")
+                    .takes_value(true)
+                    .possible_values(["error", "warn", "info", "debug", "trace"])
+                    .global(true)
+                    .help(help("log-level")),
+                Arg::new("log-file")
+                    .long("log-file")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("log-file")),
+                Arg::new("progress-style")
+                    .long("progress-style")
+                    .takes_value(true)
+                    .possible_values(PRESETS)
+                    .default_value("default")
+                    .global(true)
+                    .help(help("progress-style")),
+                Arg::new("progress")
+                    .long("progress")
+                    .takes_value(true)
+                    .possible_values(["bar", "json", "plain"])
+                    .default_value("bar")
+                    .global(true)
+                    .help(help("progress")),
+                Arg::new("on-complete")
+                    .long("on-complete")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("on-complete")),
+                Arg::new("on-error")
+                    .long("on-error")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("on-error")),
+                Arg::new("notify")
+                    .long("notify")
+                    .global(true)
+                    .help(help("notify")),
+                Arg::new("notify-webhook")
+                    .long("notify-webhook")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("notify-webhook")),
+                Arg::new("history-file")
+                    .long("history-file")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("history-file")),
+                Arg::new("no-history")
+                    .long("no-history")
+                    .global(true)
+                    .help(help("no-history")),
+                Arg::new("print-path")
+                    .long("print-path")
+                    .global(true)
+                    .help(help("print-path")),
+                Arg::new("merge-buffer")
+                    .long("merge-buffer")
+                    .takes_value(true)
+                    .default_value("65536")
+                    .global(true)
+                    .help(help("merge-buffer")),
+                Arg::new("chmod")
+                    .long("chmod")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("chmod")),
+                Arg::new("limit-rate")
+                    .long("limit-rate")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("limit-rate")),
+                Arg::new("limit-schedule")
+                    .long("limit-schedule")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("limit-schedule")),
+                Arg::new("limit-rate-per-conn")
+                    .long("limit-rate-per-conn")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("limit-rate-per-conn")),
+                Arg::new("speed-limit")
+                    .long("speed-limit")
+                    .takes_value(true)
+                    .global(true)
+                    .help(help("speed-limit")),
+                Arg::new("speed-time")
+                    .long("speed-time")
+                    .takes_value(true)
+                    .default_value("30")
+                    .global(true)
+                    .help(help("speed-time")),
+                Arg::new("fsync")
+                    .long("fsync")
+                    .takes_value(true)
+                    .possible_values(["never", "end", "periodic"])
+                    .default_value("never")
+                    .global(true)
+                    .help(help("fsync")),
+                Arg::new("fsync-interval")
+                    .long("fsync-interval")
+                    .takes_value(true)
+                    .default_value("5")
+                    .global(true)
+                    .help(help("fsync-interval")),
+                Arg::new("keep-partial")
+                    .long("keep-partial")
+                    .help(help("keep-partial")),
+                Arg::new("temp-blocks")
+                    .long("temp-blocks")
+                    .help(help("temp-blocks")),
+                Arg::new("no-preallocate")
+                    .long("no-preallocate")
+                    .help(help("no-preallocate")),
+                Arg::new("no-temp")
+                    .long("no-temp")
+                    .conflicts_with_all(&["temp-blocks", "smoke-test"])
+                    .help(help("no-temp")),
+                Arg::new("allow-partial")
+                    .long("allow-partial")
+                    .conflicts_with_all(&[
+                        "temp-blocks",
+                        "continue",
+                        "print-resume-handle",
+                        "resume-handle",
+                        "resume-from",
+                        "local-prefix",
+                        "pieces",
+                        "smoke-test",
+                    ])
+                    .help(help("allow-partial")),
+                Arg::new("continue")
+                    .long("continue")
+                    .conflicts_with_all(&["no-temp", "resume-handle"])
+                    .help(help("continue")),
+                Arg::new("resume-from")
+                    .long("resume-from")
+                    .takes_value(true)
+                    .help(help("resume-from")),
+                Arg::new("init-post")
+                    .long("init-post")
+                    .takes_value(true)
+                    .requires("init-extract")
+                    .help(help("init-post")),
+                Arg::new("init-data")
+                    .long("init-data")
+                    .takes_value(true)
+                    .requires("init-post")
+                    .help(help("init-data")),
+                Arg::new("init-extract")
+                    .long("init-extract")
+                    .takes_value(true)
+                    .requires("init-post")
+                    .help(help("init-extract")),
+                Arg::new("print-resume-handle")
+                    .long("print-resume-handle")
+                    .conflicts_with_all(&[
+                        "no-temp",
+                        "resume-from",
+                        "local-prefix",
+                        "pieces",
+                        "smoke-test",
+                    ])
+                    .help(help("print-resume-handle")),
+                Arg::new("resume-handle")
+                    .long("resume-handle")
+                    .takes_value(true)
+                    .conflicts_with_all(&[
+                        "size",
+                        "uri",
+                        "file-path",
+                        "no-temp",
+                        "resume-from",
+                        "local-prefix",
+                        "pieces",
+                        "smoke-test",
+                    ])
+                    .help(help("resume-handle")),
+                Arg::new("local-prefix")
+                    .long("local-prefix")
+                    .takes_value(true)
+                    .conflicts_with("resume-from")
+                    .help(help("local-prefix")),
+                Arg::new("candidates")
+                    .long("candidates")
+                    .takes_value(true)
+                    .help(help("candidates")),
+                Arg::new("mirror")
+                    .long("mirror")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .conflicts_with("candidates")
+                    .help(help("mirror")),
+                Arg::new("benchmark-mirrors")
+                    .long("benchmark-mirrors")
+                    .takes_value(true)
+                    .help(help("benchmark-mirrors")),
+                Arg::new("select-by")
+                    .long("select-by")
+                    .takes_value(true)
+                    .default_value("ranges,size,latency")
+                    .help(help("select-by")),
+                Arg::new("expected-size")
+                    .long("expected-size")
+                    .takes_value(true)
+                    .help(help("expected-size")),
+                Arg::new("fail-if-smaller-than")
+                    .long("fail-if-smaller-than")
+                    .takes_value(true)
+                    .help(help("fail-if-smaller-than")),
+                Arg::new("multi-range")
+                    .long("multi-range")
+                    .takes_value(true)
+                    .conflicts_with_all(&[
+                        "temp-blocks",
+                        "continue",
+                        "print-resume-handle",
+                        "resume-handle",
+                    ])
+                    .help(help("multi-range")),
+                Arg::new("metrics-port")
+                    .long("metrics-port")
+                    .takes_value(true)
+                    .help(help("metrics-port")),
+                Arg::new("verify-signature")
+                    .long("verify-signature")
+                    .visible_alias("signature")
+                    .takes_value(true)
+                    .requires("gpg-key")
+                    .conflicts_with_all(&["pieces", "smoke-test"])
+                    .help(help("verify-signature")),
+                Arg::new("gpg-key")
+                    .long("gpg-key")
+                    .visible_alias("keyring")
+                    .takes_value(true)
+                    .requires("verify-signature")
+                    .help(help("gpg-key")),
+                Arg::new("checksum")
+                    .long("checksum")
+                    .takes_value(true)
+                    .conflicts_with_all(&["pieces", "smoke-test"])
+                    .help(help("checksum")),
+                Arg::new("auto-checksum")
+                    .long("auto-checksum")
+                    .conflicts_with_all(&["checksum", "pieces", "smoke-test"])
+                    .help(help("auto-checksum")),
+                Arg::new("extract")
+                    .long("extract")
+                    .conflicts_with_all(&["pieces", "smoke-test"])
+                    .help(help("extract")),
+                Arg::new("extract-dir")
+                    .long("extract-dir")
+                    .takes_value(true)
+                    .requires("extract")
+                    .help(help("extract-dir")),
+                Arg::new("chunks")
+                    .long("chunks")
+                    .takes_value(true)
+                    .help(help("chunks")),
+                Arg::new("chunk-size")
+                    .long("chunk-size")
+                    .takes_value(true)
+                    .default_value("2048,8192,65536")
+                    .help(help("chunk-size")),
+                Arg::new("tui").long("tui").help(help("tui")),
+                Arg::new("range-mismatch")
+                    .long("range-mismatch")
+                    .takes_value(true)
+                    .possible_values(["strict", "lenient"])
+                    .default_value("strict")
+                    .help(help("range-mismatch")),
+                Arg::new("pause-file")
+                    .long("pause-file")
+                    .takes_value(true)
+                    .help(help("pause-file")),
+                Arg::new("smoke-test")
+                    .long("smoke-test")
+                    .conflicts_with_all(&["resume-from", "local-prefix", "pieces"])
+                    .help(help("smoke-test")),
+                Arg::new("dry-run")
+                    .long("dry-run")
+                    .conflicts_with("smoke-test")
+                    .help(help("dry-run")),
+                Arg::new("start-at")
+                    .long("start-at")
+                    .takes_value(true)
+                    .help(help("start-at")),
+                Arg::new("add-extension")
+                    .long("add-extension")
+                    .help(help("add-extension")),
+                Arg::new("pieces")
+                    .long("pieces")
+                    .takes_value(true)
+                    .requires("piece-size")
+                    .conflicts_with_all(&["resume-from", "local-prefix"])
+                    .help(help("pieces")),
+                Arg::new("piece-hashes")
+                    .long("piece-hashes")
+                    .takes_value(true)
+                    .requires("piece-size")
+                    .conflicts_with("pieces")
+                    .help(help("piece-hashes")),
+                Arg::new("piece-size")
+                    .long("piece-size")
+                    .takes_value(true)
+                    .help(help("piece-size")),
             ])
-            .get_matches();
+            .subcommand(Command::new("size").about(help("size-command")).args(&[
+                Arg::new("uri").help(help("uri")).required(true),
+                Arg::new("human").long("human").help(help("human")),
+            ]))
+            .subcommand(
+                Command::new("merge").about(help("merge-command")).args(&[
+                    Arg::new("temp-dir")
+                        .long("temp-dir")
+                        .takes_value(true)
+                        .required(true)
+                        .help(help("temp-dir")),
+                    Arg::new("output")
+                        .long("output")
+                        .takes_value(true)
+                        .required(true)
+                        .help(help("output")),
+                    Arg::new("blocks")
+                        .long("blocks")
+                        .takes_value(true)
+                        .required(true)
+                        .help(help("blocks")),
+                ]),
+            )
+            .subcommand(
+                Command::new("history")
+                    .about(help("history-command"))
+                    .args(&[
+                        Arg::new("status")
+                            .long("status")
+                            .takes_value(true)
+                            .possible_values(history::STATUSES)
+                            .help(help("status")),
+                        Arg::new("search")
+                            .long("search")
+                            .takes_value(true)
+                            .help(help("search")),
+                        Arg::new("limit")
+                            .long("limit")
+                            .takes_value(true)
+                            .default_value("20")
+                            .help(help("limit")),
+                        Arg::new("json").long("json").help(help("history-json")),
+                    ]),
+            )
+            .subcommand(
+                Command::new("daemon").about(help("daemon-command")).args(&[
+                    Arg::new("rpc-listen-port")
+                        .long("rpc-listen-port")
+                        .takes_value(true)
+                        .default_value("6800")
+                        .help(help("rpc-listen-port")),
+                    Arg::new("rpc-secret")
+                        .long("rpc-secret")
+                        .takes_value(true)
+                        .help(help("rpc-secret")),
+                    Arg::new("dir")
+                        .long("dir")
+                        .takes_value(true)
+                        .default_value(".")
+                        .help(help("dir")),
+                    Arg::new("split")
+                        .long("split")
+                        .takes_value(true)
+                        .default_value("5")
+                        .help(help("split")),
+                    Arg::new("web-ui").long("web-ui").help(help("web-ui")),
+                ]),
+            )
+    }
+
+    fn from_matches(matches: ArgMatches) -> Result<Self> {
+        let resume_handle = match matches.value_of("resume-handle") {
+            None => None,
+            Some(t) => Some(t.parse::<Handle>()?),
+        };
+
+        let mut metalink = None;
+        let (args, action, temp_file_dir) = match matches.subcommand() {
+            Some(("size", args)) => {
+                let uri = args.value_of_t("uri")?;
+                let human = args.is_present("human");
+                (args, Action::Size { uri, human }, new_temp_file_dir())
+            }
+            Some(("merge", args)) => {
+                let blocks = args.value_of_t("blocks")?;
+                let file_path: String = args.value_of_t("output")?;
+                check_not_exists(&file_path)?;
+                let temp_file_dir = args.value_of_t("temp-dir")?;
+                (args, Action::Merge { blocks, file_path }, temp_file_dir)
+            }
+            Some(("daemon", args)) => {
+                let action = Action::Daemon {
+                    addr: listen_addr(args.value_of("rpc-listen-port").unwrap_or_default())?,
+                    secret: args.value_of("rpc-secret").map(String::from),
+                    dir: args.value_of_t("dir")?,
+                    split: connections(args, "split")?,
+                    web_ui: args.is_present("web-ui"),
+                };
+                (args, action, new_temp_file_dir())
+            }
+            Some(("history", args)) => {
+                let action = Action::History {
+                    status: args.value_of("status").map(String::from),
+                    search: args.value_of("search").map(String::from),
+                    limit: args.value_of_t("limit")?,
+                    json: args.is_present("json"),
+                };
+                (args, action, new_temp_file_dir())
+            }
+            _ if resume_handle.is_some() => {
+                let handle = resume_handle.as_ref().unwrap();
+                check_not_exists(&handle.file_path)?;
+                let action = Action::Download {
+                    size: handle.blocks,
+                    uri: handle.uri.clone(),
+                    output: OutputTarget::Path(handle.file_path.clone()),
+                };
+                (&matches, action, handle.temp_dir.clone())
+            }
+            _ if matches.is_present("url") => {
+                let size = connections(&matches, "size")?;
+                let mut pairs = Vec::new();
+                if let Some(uri) = matches.value_of("uri") {
+                    match matches.value_of("file-path") {
+                        Some(file_path) => pairs.push((uri, file_path)),
+                        None => return Err(anyhow!(Msg::BatchFilePathMissing(uri.to_string()))),
+                    }
+                }
+                let values: Vec<_> = matches.values_of("url").unwrap_or_default().collect();
+                pairs.extend(values.chunks(2).map(|t| (t[0], t[1])));
+                let mut targets: Vec<Target> = Vec::with_capacity(pairs.len());
+                for (uri, file_path) in pairs {
+                    let file_path = output_path(&matches, file_path);
+                    check_not_exists(&file_path)?;
+                    if targets.iter().any(|t| t.file_path == file_path) {
+                        return Err(anyhow!(Msg::DuplicateOutput(file_path)));
+                    }
+                    let uri = uri.parse()?;
+                    targets.push(Target {
+                        temp_file_dir: temp_file_dir(&matches, size, &uri, &file_path)?,
+                        uri,
+                        file_path,
+                    });
+                }
+                (
+                    &matches,
+                    Action::Batch { size, targets },
+                    new_temp_file_dir(),
+                )
+            }
+            _ => {
+                let size = connections(&matches, "size")?;
+                let value = matches.value_of("uri").unwrap_or_default();
+                let uri = if is_metalink(value) {
+                    let loaded = Metalink::load(Path::new(value))?;
+                    let uri = loaded.urls[0].clone();
+                    metalink = Some(loaded);
+                    uri
+                } else {
+                    value.parse()?
+                };
+                // 省略保存路径时，下载前无法确定文件名，续传目录由保存目录确定；Metalink 中的文件名优先
+                let file_path = match matches.value_of("file-path") {
+                    Some(t) => Some(t.to_string()),
+                    None => metalink.as_ref().and_then(|t| filename::sanitize(&t.name)),
+                };
+                let output = match file_path {
+                    Some(t) => {
+                        let file_path = output_path(&matches, &t);
+                        check_not_exists(&file_path)?;
+                        OutputTarget::Path(file_path)
+                    }
+                    None => OutputTarget::Infer(PathBuf::from(
+                        matches.value_of("output-dir").unwrap_or("."),
+                    )),
+                };
+                let temp_file_dir = match &output {
+                    OutputTarget::Path(t) => temp_file_dir(&matches, size, &uri, t)?,
+                    OutputTarget::Infer(dir) => {
+                        temp_file_dir(&matches, size, &uri, &dir.display().to_string())?
+                    }
+                };
+                let action = Action::Download { size, uri, output };
+                (&matches, action, temp_file_dir)
+            }
+        };
+
+        let host = args.value_of("host").map(String::from);
+
+        // SNI 优先使用 `--sni`，否则使用 `--host` 去掉端口后的主机名
+        let server_name = match (args.value_of("sni"), &host) {
+            (Some(sni), _) => Some(sni.to_string()),
+            (None, Some(host)) => Some(host.parse::<Authority>()?.host().to_string()),
+            (None, None) => None,
+        };
+
+        let headers = args
+            .values_of("header")
+            .into_iter()
+            .flatten()
+            .map(|t| t.parse().map(|Header(name, value)| (name, value)))
+            .collect::<Result<_>>()?;
+        let proxy_headers = args
+            .values_of("proxy-header")
+            .into_iter()
+            .flatten()
+            .map(|t| t.parse().map(|Header(name, value)| (name, value)))
+            .collect::<Result<_>>()?;
+        let proxy = Proxies::new(
+            args.value_of("proxy"),
+            args.value_of("no-proxy"),
+            proxy_headers,
+        )?;
+
+        let allowed_hosts = match args.value_of("allowed-hosts") {
+            None => Vec::new(),
+            Some(t) => t.split(',').map(|t| t.trim().to_string()).collect(),
+        };
 
-        let size = matches.value_of_t("size")?;
-        let uri = matches.value_of_t("uri")?;
-        let file_path = matches.value_of_t("file-path")?;
+        let auth = match (
+            args.value_of("user"),
+            args.value_of("token"),
+            args.value_of("keyring-service"),
+        ) {
+            (Some(t), _, _) => {
+                let (user, password) = t.split_once(':').unwrap_or((t, ""));
+                Some(Auth::Basic {
+                    user: user.to_string(),
+                    password: password.to_string(),
+                })
+            }
+            (_, Some(t), _) => Some(Auth::Bearer(t.to_string())),
+            (_, _, None) => None,
+            (_, _, Some(service)) => {
+                let user = match args.value_of("keyring-user") {
+                    Some(t) => t.to_string(),
+                    None => env::var("USER")
+                        .or_else(|_| env::var("USERNAME"))
+                        .unwrap_or_default(),
+                };
+                let scheme = args.value_of_t("keyring-auth")?;
+                Some(Auth::from_keyring(service, &user, scheme)?)
+            }
+        };
 
-        // 检查文件是否已存在
-        if Path::new(&file_path).exists() {
-            return Err(anyhow!("文件 `{}` 已存在", file_path));
+        // 未指定其他凭据时按请求的主机查找
+        let netrc = match (&auth, args.value_of("netrc-file")) {
+            (Some(_), _) => None,
+            (None, Some(path)) => Some(Netrc::load(Path::new(path))?),
+            (None, None) if args.is_present("netrc") => Some(Netrc::load(&Netrc::default_path())?),
+            (None, None) => None,
+        };
+
+        let start_at = match matches.value_of("start-at") {
+            None => None,
+            Some(t) => Some(start_at(t)?),
+        };
+        // `--max-time` 从开始下载时算起
+        let deadline =
+            seconds(args.value_of("max-time"))?.map(|t| start_at.unwrap_or_else(Instant::now) + t);
+        let max_concurrent_downloads = max_count(args, "max-concurrent-downloads")?;
+        let max_connections_total = max_count(args, "max-connections-total")?;
+        let timeout_backoff: f64 = args.value_of_t("timeout-backoff")?;
+        if !(timeout_backoff >= 1.0 && timeout_backoff.is_finite()) {
+            return Err(anyhow!(Msg::TimeoutBackoffTooSmall));
+        }
+        let switch_after = args.value_of_t("switch-after")?;
+        if switch_after == 0 {
+            return Err(anyhow!(Msg::InvalidSwitchAfter));
+        }
+        let retry = Retry {
+            attempts: args.value_of_t("retry")?,
+            base_delay: seconds(args.value_of("retry-delay"))?.unwrap_or_default(),
+            max_delay: seconds(args.value_of("retry-max-delay"))?.unwrap_or_default(),
+            max_overall: match args.value_of("max-overall-retries") {
+                None => None,
+                Some(t) => Some(t.parse()?),
+            },
+            deadline,
+            timeout: seconds(args.value_of("timeout"))?,
+            timeout_backoff,
+            timeout_cap: seconds(args.value_of("timeout-cap"))?,
+            switch_after,
+            max_retry_after: seconds(args.value_of("max-retry-after"))?.unwrap_or_default(),
+            overall: AtomicUsize::new(0),
+        };
+        let transports = if args.is_present("http2") {
+            "auto"
+        } else if args.is_present("http3") {
+            "h3,auto"
+        } else {
+            args.value_of("transports").unwrap_or_default()
+        };
+        let transports = transports
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<_>>()?;
+
+        let merge_buffer = args.value_of_t("merge-buffer")?;
+        if merge_buffer == 0 {
+            return Err(anyhow!(Msg::InvalidMergeBuffer));
+        }
+
+        let chmod = match args.value_of("chmod") {
+            None => None,
+            Some(t) => match u32::from_str_radix(t.trim_start_matches("0o"), 8) {
+                Ok(mode) if mode <= 0o7777 => Some(mode),
+                _ => return Err(anyhow!(Msg::InvalidMode(t.to_string()))),
+            },
+        };
+
+        let limit_rate = match args.value_of("limit-rate") {
+            None => None,
+            Some(t) => Some(limit::parse_rate(t)?),
+        };
+        let limit_schedule = match args.value_of("limit-schedule") {
+            None => None,
+            Some(t) => Some(t.parse()?),
+        };
+        let limit_rate_per_conn = match args.value_of("limit-rate-per-conn") {
+            None => None,
+            Some(t) => Some(limit::parse_rate(t)?),
+        };
+        let speed_limit = match args.value_of("speed-limit") {
+            None => None,
+            Some(t) => {
+                let time = seconds(args.value_of("speed-time"))?.unwrap_or_default();
+                if time.is_zero() {
+                    return Err(anyhow!(Msg::InvalidSpeedTime));
+                }
+                Some((limit::parse_rate(t)?, time))
+            }
+        };
+
+        let fsync = match args.value_of("fsync") {
+            Some("end") => Fsync::End,
+            Some("periodic") => {
+                Fsync::Periodic(seconds(args.value_of("fsync-interval"))?.unwrap_or_default())
+            }
+            _ => Fsync::Never,
+        };
+
+        let candidates = match matches.value_of("candidates") {
+            None => Vec::new(),
+            Some(t) => t
+                .split(',')
+                .map(|t| t.trim().parse())
+                .collect::<std::result::Result<_, _>>()?,
+        };
+        let mut mirrors = match (&metalink, &action) {
+            (Some(t), _) => t.urls.clone(),
+            (None, Action::Download { uri, .. }) => vec![uri.clone()],
+            _ => Vec::new(),
+        };
+        for t in matches.values_of("mirror").unwrap_or_default() {
+            mirrors.push(t.parse()?);
+        }
+        // 只有一个地址时照常下载
+        if mirrors.len() < 2 {
+            mirrors.clear();
         }
+        let benchmark_mirrors = match matches.value_of("benchmark-mirrors") {
+            None => None,
+            Some(t) => Some(t.parse()?),
+        };
+        let criteria = matches
+            .value_of("select-by")
+            .unwrap_or_default()
+            .split(',')
+            .map(str::parse)
+            .collect::<Result<_>>()?;
+        let expected_size = match matches.value_of("expected-size") {
+            None => metalink.as_ref().and_then(|t| t.size),
+            Some(t) => Some(t.parse()?),
+        };
 
-        let temp_file_dir = temp_dir().join(Uuid::new_v4().to_string());
+        let init = match matches.value_of("init-post") {
+            None => None,
+            Some(t) => Some(Init {
+                uri: t.parse()?,
+                data: matches
+                    .value_of("init-data")
+                    .unwrap_or_default()
+                    .to_string(),
+                extract: matches.value_of_t("init-extract")?,
+            }),
+        };
+
+        let min_size = match matches.value_of("fail-if-smaller-than") {
+            None => None,
+            Some(t) => Some(t.parse()?),
+        };
+        let multi_range = match matches.value_of("multi-range") {
+            None => None,
+            Some(t) => match t.parse() {
+                Ok(n) if n > 1 => Some(n),
+                _ => return Err(anyhow!(Msg::InvalidMultiRange(t.to_string()))),
+            },
+        };
+        let metrics_addr = match matches.value_of("metrics-port") {
+            None => None,
+            Some(t) => Some(listen_addr(t)?),
+        };
+        let signature = matches.value_of("verify-signature").map(|t| {
+            (
+                t.to_string(),
+                PathBuf::from(matches.value_of("gpg-key").unwrap_or_default()),
+            )
+        });
+        #[cfg(not(feature = "signature"))]
+        if signature.is_some() {
+            return Err(anyhow!(Msg::SignatureUnsupported));
+        }
+        #[cfg(not(feature = "archive"))]
+        if matches.is_present("extract") {
+            return Err(anyhow!(Msg::ArchiveUnsupported));
+        }
+        let checksum = match matches.value_of("checksum") {
+            None => metalink.as_ref().and_then(|t| t.checksum.clone()),
+            Some(t) => Some(t.parse()?),
+        };
+        let chunks = match matches.value_of("chunks") {
+            None => None,
+            Some(t) => Some((PathBuf::from(t), matches.value_of_t("chunk-size")?)),
+        };
+        let piece_size = || -> Result<usize> {
+            let piece_size: usize = matches.value_of_t("piece-size")?;
+            if piece_size == 0 {
+                return Err(anyhow!(Msg::InvalidPieceSize));
+            }
+            Ok(piece_size)
+        };
+        let pieces = match matches.value_of("pieces") {
+            None => None,
+            Some(t) => Some((t.parse()?, piece_size()?)),
+        };
+        let piece_hashes = match matches.value_of("piece-hashes") {
+            None => metalink.as_ref().and_then(|t| t.pieces.clone()),
+            Some(t) => Some(PieceHashes::load(Path::new(t), piece_size()?)?),
+        };
 
         Ok(Self {
-            size,
-            uri,
-            file_path,
+            action,
             temp_file_dir,
+            host,
+            server_name,
+            headers,
+            proxy,
+            allowed_hosts,
+            max_redirects: args.value_of_t("max-redirects")?,
+            auth,
+            netrc,
+            ssh_key: args.value_of("ssh-key").map(PathBuf::from),
+            retry,
+            connect_timeout: seconds(args.value_of("connect-timeout"))?,
+            read_timeout: seconds(args.value_of("read-timeout"))?,
+            transports,
+            backend: match args.value_of("backend") {
+                Some("azure") => Some(Backend::Azure),
+                Some("webdav") => Some(Backend::WebDav),
+                _ => None,
+            },
+            keep_partial: matches.is_present("keep-partial"),
+            verbose: args.is_present("verbose"),
+            quiet: args.is_present("quiet"),
+            stats: match args.value_of("stats") {
+                Some("json") => Stats::Json,
+                _ => Stats::Text,
+            },
+            log_level: match args.value_of("log-level") {
+                None => None,
+                Some(t) => Some(t.parse()?),
+            },
+            log_file: args.value_of("log-file").map(PathBuf::from),
+            progress_style: args.value_of_t("progress-style")?,
+            progress: match args.value_of("progress") {
+                Some("json") => Progress::Json,
+                Some("plain") => Progress::Plain,
+                _ => Progress::Bars,
+            },
+            print_path: args.is_present("print-path"),
+            on_complete: args.value_of("on-complete").map(String::from),
+            on_error: args.value_of("on-error").map(String::from),
+            notify: args.is_present("notify"),
+            notify_webhook: match args.value_of("notify-webhook") {
+                None => None,
+                Some(t) => Some(t.parse()?),
+            },
+            history_file: match args.value_of("history-file") {
+                _ if args.is_present("no-history") => None,
+                Some(t) => Some(PathBuf::from(t)),
+                None => Some(history::default_path()),
+            },
+            merge_buffer,
+            chmod,
+            fsync,
+            limit_rate,
+            limit_schedule,
+            limit_rate_per_conn,
+            max_concurrent_downloads,
+            max_connections_total,
+            speed_limit,
+            candidates,
+            mirrors,
+            benchmark_mirrors,
+            criteria,
+            expected_size,
+            // 续传句柄及 `--continue` 依赖块文件
+            temp_blocks: matches.is_present("temp-blocks")
+                || matches.is_present("continue")
+                || matches.is_present("print-resume-handle")
+                || resume_handle.is_some(),
+            no_temp: matches.is_present("no-temp"),
+            preallocate: !matches.is_present("no-preallocate"),
+            allow_partial: matches.is_present("allow-partial"),
+            continue_download: matches.is_present("continue"),
+            init,
+            resume_from: matches.value_of("resume-from").map(PathBuf::from),
+            print_resume_handle: matches.is_present("print-resume-handle"),
+            resume_handle,
+            min_size,
+            multi_range,
+            metrics_addr,
+            signature,
+            checksum,
+            auto_checksum: matches.is_present("auto-checksum"),
+            extract: matches.is_present("extract"),
+            extract_dir: matches.value_of("extract-dir").map(PathBuf::from),
+            chunks,
+            tui: matches.is_present("tui"),
+            local_prefix: matches.value_of("local-prefix").map(PathBuf::from),
+            add_extension: matches.is_present("add-extension"),
+            range_mismatch: match matches.value_of("range-mismatch") {
+                Some("lenient") => RangeMismatch::Lenient,
+                _ => RangeMismatch::Strict,
+            },
+            pause_file: matches.value_of("pause-file").map(PathBuf::from),
+            smoke_test: matches.is_present("smoke-test"),
+            dry_run: matches.is_present("dry-run"),
+            pieces,
+            piece_hashes,
+            start_at,
         })
     }
 }
+
+/// 解析大于 0 的连接数
+fn connections(args: &ArgMatches, name: &str) -> Result<usize> {
+    let value = args.value_of(name).unwrap_or_default();
+    match value.parse() {
+        Ok(0) | Err(_) => Err(anyhow!(Msg::InvalidConnections(value.to_string()))),
+        Ok(t) => Ok(t),
+    }
+}
+
+/// 解析大于 0 的数量上限
+fn max_count(args: &ArgMatches, name: &str) -> Result<Option<usize>> {
+    match args.value_of(name) {
+        None => Ok(None),
+        Some(t) => match t.parse() {
+            Ok(0) | Err(_) => Err(anyhow!(Msg::InvalidMaxCount {
+                option: name.to_string(),
+                value: t.to_string(),
+            })),
+            Ok(t) => Ok(Some(t)),
+        },
+    }
+}
+
+/// 解析 `--start-at`：RFC 3339 时间戳，或当地时间 `HH:MM[:SS]`，后者已过时为次日的该时间
+fn start_at(value: &str) -> Result<Instant> {
+    let invalid = || anyhow!(Msg::InvalidStartAt(value.to_string()));
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    let start = match OffsetDateTime::parse(value, &Rfc3339) {
+        Ok(t) => t,
+        Err(_) => {
+            let parts = value
+                .split(':')
+                .map(|t| t.parse::<u8>().map_err(|_| invalid()))
+                .collect::<Result<Vec<_>>>()?;
+            let (hour, minute, second) = match parts[..] {
+                [hour, minute] => (hour, minute, 0),
+                [hour, minute, second] => (hour, minute, second),
+                _ => return Err(invalid()),
+            };
+            let time = Time::from_hms(hour, minute, second).map_err(|_| invalid())?;
+            let start = now.replace_time(time);
+            if start <= now {
+                start + time::Duration::DAY
+            } else {
+                start
+            }
+        }
+    };
+    // 已过的时间戳立即开始
+    let delay = Duration::try_from(start - now).unwrap_or_default();
+    Ok(Instant::now() + delay)
+}
+
+/// 解析以秒为单位的时长
+fn seconds(value: Option<&str>) -> Result<Option<Duration>> {
+    match value {
+        None => Ok(None),
+        Some(t) => Ok(Some(Duration::try_from_secs_f64(t.parse()?)?)),
+    }
+}
+
+/// 检查文件是否已存在
+fn check_not_exists(file_path: &str) -> Result {
+    if Path::new(file_path).exists() {
+        return Err(anyhow!(Msg::FileExists(file_path.to_string())));
+    }
+    Ok(())
+}
+
+/// 监听的端口或 `地址:端口`，只给出端口时仅监听本机
+fn listen_addr(value: &str) -> Result<SocketAddr> {
+    Ok(match value.parse::<u16>() {
+        Ok(port) => SocketAddr::from(([127, 0, 0, 1], port)),
+        Err(_) => value.parse()?,
+    })
+}
+
+/// 本次下载使用的临时文件目录
+pub(crate) fn new_temp_file_dir() -> PathBuf {
+    temp_dir().join(Uuid::new_v4().to_string())
+}
+
+/// 指定 `--output-dir` 时，相对路径基于该目录
+fn output_path(matches: &ArgMatches, file_path: &str) -> String {
+    match matches.value_of("output-dir") {
+        Some(dir) if Path::new(file_path).is_relative() => {
+            Path::new(dir).join(file_path).display().to_string()
+        }
+        _ => file_path.to_string(),
+    }
+}
+
+/// 本地的 `.meta4` 或 `.metalink` 文件
+fn is_metalink(value: &str) -> bool {
+    let path = Path::new(value);
+    !value.contains("://")
+        && matches!(
+            path.extension().and_then(|t| t.to_str()),
+            Some("meta4" | "metalink")
+        )
+        && path.is_file()
+}
+
+/// 下载 `uri` 使用的临时文件目录
+fn temp_file_dir(matches: &ArgMatches, size: usize, uri: &Uri, file_path: &str) -> Result<PathBuf> {
+    if matches.is_present("continue") {
+        continue_temp_file_dir(size, uri, file_path)
+    } else {
+        Ok(new_temp_file_dir())
+    }
+}
+
+/// `--continue` 使用的临时文件目录，由块数、URI 及输出文件的绝对路径确定，重新运行时可以找到
+fn continue_temp_file_dir(size: usize, uri: &Uri, file_path: &str) -> Result<PathBuf> {
+    let key = format!(
+        "{}\n{}\n{}",
+        size,
+        uri,
+        std::path::absolute(file_path)?.display()
+    );
+    let digest: String = Sha256::digest(key.as_bytes())[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    Ok(temp_dir().join(format!("download-{}", digest)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// 解析给定的参数，不读取默认选项文件
+    fn parse(args: &[&str]) -> Result<Config> {
+        let args = std::iter::once(crate_name!()).chain(args.iter().copied());
+        Config::from_matches(Config::command().try_get_matches_from(args)?)
+    }
+
+    #[test]
+    fn connection_counts_must_be_positive() {
+        let uri = "http://127.0.0.1:9/a.bin";
+        for (args, value) in [
+            (&["0", uri, "a.bin"][..], "0"),
+            (&["many", uri, "a.bin"], "many"),
+            (&["0", "--url", uri, "a.bin"], "0"),
+            (&["daemon", "--split", "0"], "0"),
+        ] {
+            let error = parse(args).err().map(|e| e.to_string());
+            let expected = Msg::InvalidConnections(value.to_string()).to_string();
+            assert_eq!(error.as_deref(), Some(expected.as_str()), "{:?}", args);
+        }
+        assert!(parse(&["4", uri, "a.bin"]).is_ok());
+        assert!(parse(&["daemon", "--split", "1"]).is_ok());
+    }
+
+    #[test]
+    fn download_matches_command_line_defaults() {
+        let uri = "http://127.0.0.1:9/a.bin";
+        let built = Config::download(4, uri.parse().unwrap(), "a.bin".into()).unwrap();
+        let parsed = parse(&["4", uri, "a.bin"]).unwrap();
+        let (b, p) = (&built.retry, &parsed.retry);
+        assert_eq!(
+            (b.attempts, b.base_delay, b.max_delay, b.switch_after),
+            (p.attempts, p.base_delay, p.max_delay, p.switch_after)
+        );
+        assert_eq!(
+            (b.timeout_backoff, b.max_retry_after),
+            (p.timeout_backoff, p.max_retry_after)
+        );
+        assert_eq!(built.max_redirects, parsed.max_redirects);
+        assert_eq!(built.merge_buffer, parsed.merge_buffer);
+        assert_eq!(built.preallocate, parsed.preallocate);
+        assert!(built.fsync == parsed.fsync);
+        assert_eq!(built.range_mismatch, parsed.range_mismatch);
+        assert_eq!(built.progress, parsed.progress);
+        assert_eq!(built.stats, parsed.stats);
+        assert_eq!(built.transports.len(), parsed.transports.len());
+        assert_eq!(built.criteria.len(), parsed.criteria.len());
+        assert!(built.history_file.is_none());
+        assert!(Config::download(0, uri.parse().unwrap(), "a.bin".into()).is_err());
+    }
+}
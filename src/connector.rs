@@ -0,0 +1,163 @@
+use std::future::Future;
+use std::io;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper::client::connect::{Connected, Connection};
+use hyper::client::HttpConnector;
+use hyper::service::Service;
+use hyper::Uri;
+use hyper_tls::MaybeHttpsStream;
+use tokio::io::{AsyncRead, AsyncWrite, ReadBuf};
+use tokio::net::TcpStream;
+use tokio::time::timeout;
+use tokio_native_tls::{native_tls, TlsConnector};
+
+use crate::message::Msg;
+use crate::proxy::Proxies;
+use crate::Result;
+
+type BoxError = Box<dyn std::error::Error + Send + Sync>;
+
+/// 支持自定义 TLS SNI 及 HTTP 代理隧道的 HTTPS 连接器
+#[derive(Clone)]
+pub struct Connector {
+    http: HttpConnector,
+    tls: TlsConnector,
+    /// TLS 握手时使用的服务器名称，为空时使用 URI 中的主机名
+    server_name: Option<String>,
+    proxies: Proxies,
+    /// 建立连接（含代理隧道及 TLS 握手）的超时时间
+    connect_timeout: Option<Duration>,
+}
+
+impl Connector {
+    /// `alpn` 为 TLS 握手时协商的应用层协议，为空时不协商
+    pub fn new(
+        server_name: Option<String>,
+        alpn: &[&str],
+        proxies: Proxies,
+        connect_timeout: Option<Duration>,
+    ) -> Result<Self> {
+        let mut http = HttpConnector::new();
+        http.enforce_http(false);
+        let tls = native_tls::TlsConnector::builder()
+            .request_alpns(alpn)
+            .build()?
+            .into();
+        Ok(Self {
+            http,
+            tls,
+            server_name,
+            proxies,
+            connect_timeout,
+        })
+    }
+}
+
+/// 建立的连接，记录 TLS 握手时是否通过 ALPN 协商了 HTTP/2
+pub struct Stream {
+    inner: MaybeHttpsStream<TcpStream>,
+    h2: bool,
+}
+
+impl Connection for Stream {
+    fn connected(&self) -> Connected {
+        let connected = self.inner.connected();
+        if self.h2 {
+            connected.negotiated_h2()
+        } else {
+            connected
+        }
+    }
+}
+
+impl AsyncRead for Stream {
+    fn poll_read(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &mut ReadBuf<'_>,
+    ) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_read(cx, buf)
+    }
+}
+
+impl AsyncWrite for Stream {
+    fn poll_write(
+        mut self: Pin<&mut Self>,
+        cx: &mut Context<'_>,
+        buf: &[u8],
+    ) -> Poll<io::Result<usize>> {
+        Pin::new(&mut self.inner).poll_write(cx, buf)
+    }
+
+    fn poll_flush(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_flush(cx)
+    }
+
+    fn poll_shutdown(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<io::Result<()>> {
+        Pin::new(&mut self.inner).poll_shutdown(cx)
+    }
+}
+
+impl Service<Uri> for Connector {
+    type Response = Stream;
+    type Error = BoxError;
+    type Future =
+        Pin<Box<dyn Future<Output = std::result::Result<Self::Response, Self::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<std::result::Result<(), Self::Error>> {
+        self.http.poll_ready(cx).map_err(Into::into)
+    }
+
+    fn call(&mut self, uri: Uri) -> Self::Future {
+        let is_https = uri.scheme_str() == Some("https");
+        let server_name = match &self.server_name {
+            Some(name) => name.clone(),
+            None => uri
+                .host()
+                .unwrap_or("")
+                .trim_matches(|c| c == '[' || c == ']')
+                .to_owned(),
+        };
+        // 使用代理时先连接代理服务器，再通过 CONNECT 建立到源站的隧道
+        let proxy = self.proxies.select(&uri).cloned();
+        let connecting = match &proxy {
+            Some(proxy) => self.http.call(proxy.uri.clone()),
+            None => self.http.call(uri.clone()),
+        };
+        let tls = self.tls.clone();
+        let connect_timeout = self.connect_timeout;
+        let connect = async move {
+            let mut tcp = connecting.await?;
+            if let Some(proxy) = proxy {
+                proxy.tunnel(&mut tcp, &uri).await?;
+            }
+            let stream = if is_https {
+                let tls = tls.connect(&server_name, tcp).await?;
+                let alpn = tls.get_ref().negotiated_alpn().ok().flatten();
+                Stream {
+                    h2: alpn.as_deref() == Some(b"h2"),
+                    inner: tls.into(),
+                }
+            } else {
+                Stream {
+                    inner: MaybeHttpsStream::Http(tcp),
+                    h2: false,
+                }
+            };
+            Ok::<_, BoxError>(stream)
+        };
+        Box::pin(async move {
+            match connect_timeout {
+                None => connect.await,
+                Some(t) => match timeout(t, connect).await {
+                    Ok(result) => result,
+                    Err(_) => Err(anyhow!(Msg::ConnectTimeout(t)).into()),
+                },
+            }
+        })
+    }
+}
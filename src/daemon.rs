@@ -0,0 +1,704 @@
+//! 守护进程，通过兼容 aria2 的 JSON-RPC 添加及管理下载
+//!
+//! 在 `--rpc-listen-port` 上接受 `POST /jsonrpc`，支持 `aria2.addUri`、`aria2.tellStatus`、`aria2.pause`、
+//! `aria2.remove` 等常用方法及 `system.multicall`。与 aria2 一样，数值以字符串表示，暂停的下载列在
+//! `tellWaiting` 中；设置 `--rpc-secret` 时每次调用的第一个参数须为 `token:<secret>`。`--web-ui` 时在 `/` 上
+//! 提供网页，网页同样通过 JSON-RPC 查看及管理下载。`--max-concurrent-downloads` 时超出的下载排队等待，
+//! 状态为 `waiting`
+
+use std::convert::Infallible;
+use std::net::SocketAddr;
+use std::path::{Component, Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper::header::{
+    HeaderValue, ACCESS_CONTROL_ALLOW_HEADERS, ACCESS_CONTROL_ALLOW_METHODS,
+    ACCESS_CONTROL_ALLOW_ORIGIN, CONTENT_TYPE,
+};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Method, Request, Response, Server, StatusCode, Uri};
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use tokio::spawn;
+use tokio::task::JoinHandle;
+use tokio::time::{interval, sleep};
+use uuid::Uuid;
+
+use crate::config::{new_temp_file_dir, OutputTarget};
+use crate::http::{self, Job};
+use crate::interrupt;
+use crate::message::Msg;
+use crate::session::{Session, SESSION};
+use crate::Result;
+
+/// `--web-ui` 提供的网页
+const WEB_UI: &str = include_str!("daemon.html");
+
+/// 采样下载速度的间隔
+const SAMPLE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `system.listMethods` 返回的方法
+const METHODS: [&str; 18] = [
+    "aria2.addUri",
+    "aria2.remove",
+    "aria2.forceRemove",
+    "aria2.pause",
+    "aria2.forcePause",
+    "aria2.pauseAll",
+    "aria2.forcePauseAll",
+    "aria2.unpause",
+    "aria2.unpauseAll",
+    "aria2.tellStatus",
+    "aria2.tellActive",
+    "aria2.tellWaiting",
+    "aria2.tellStopped",
+    "aria2.getGlobalStat",
+    "aria2.purgeDownloadResult",
+    "aria2.removeDownloadResult",
+    "aria2.getVersion",
+    "system.multicall",
+];
+
+lazy_static! {
+    /// 按添加顺序排列的下载
+    static ref DOWNLOADS: Mutex<Vec<Arc<Download>>> = Mutex::new(Vec::new());
+}
+
+/// 下载的状态，名称与 aria2 一致
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+enum Status {
+    Active,
+    Waiting,
+    Paused,
+    Complete,
+    Error,
+    Removed,
+}
+
+impl Status {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Active => "active",
+            Self::Waiting => "waiting",
+            Self::Paused => "paused",
+            Self::Complete => "complete",
+            Self::Error => "error",
+            Self::Removed => "removed",
+        }
+    }
+
+    fn stopped(self) -> bool {
+        matches!(self, Self::Complete | Self::Error | Self::Removed)
+    }
+}
+
+struct Download {
+    gid: String,
+    uri: String,
+    dir: PathBuf,
+    split: usize,
+    transfer: Transfer,
+    status: Mutex<Status>,
+    error: Mutex<Option<String>>,
+    /// 上次采样时已下载的字节数及据此计算的速度（字节/秒）
+    sampled: AtomicU64,
+    speed: AtomicU64,
+}
+
+impl Download {
+    /// 记录的状态，正在排队的下载为 `Waiting`
+    fn status(&self) -> Status {
+        match *self.status.lock().unwrap() {
+            Status::Active if self.transfer.queued() => Status::Waiting,
+            status => status,
+        }
+    }
+}
+
+/// 守护进程添加的一个下载，可查询进度、暂停或移除
+struct Transfer {
+    job: Arc<Job>,
+}
+
+impl Transfer {
+    /// 已下载的字节数及资源大小，大小未知时为 0
+    fn progress(&self) -> (usize, usize) {
+        let bars = self.job.bars.lock().unwrap();
+        let downloaded = bars.iter().map(|t| t.position() as usize).sum();
+        (downloaded, self.job.resource_size.load(Ordering::Relaxed))
+    }
+
+    /// 是否仍在排队等待下载名额
+    fn queued(&self) -> bool {
+        self.job.queued.load(Ordering::Relaxed)
+    }
+
+    /// 输出文件路径，推断文件名之前为空
+    fn file_path(&self) -> String {
+        self.job.file.get().cloned().unwrap_or_default()
+    }
+
+    /// 暂停或继续各块，连接保持打开
+    fn set_paused(&self, paused: bool) {
+        self.job.paused.store(paused, Ordering::Relaxed);
+        for chunk in self.job.chunks.lock().unwrap().values() {
+            chunk.set_paused(paused);
+        }
+    }
+
+    /// 中止各块，下载随即失败
+    fn remove(&self) {
+        self.job.removed.store(true, Ordering::Relaxed);
+        for chunk in self.job.chunks.lock().unwrap().values() {
+            chunk.abort();
+        }
+    }
+}
+
+/// 在后台以 `size` 个连接下载 `uri`，结束时返回输出文件路径
+///
+/// 守护进程在处理 RPC 请求的任务中调用，该任务不在任何会话中，需传入守护进程的 `session`
+fn spawn_transfer(
+    session: Arc<Session>,
+    size: usize,
+    uri: Uri,
+    output: OutputTarget,
+) -> (Transfer, JoinHandle<Result<String>>) {
+    let job = Job::new(new_temp_file_dir());
+    let transfer = Transfer { job: job.clone() };
+    job.queued.store(true, Ordering::Relaxed);
+    let task = http::transfer(job, size, uri, output);
+    (transfer, spawn(SESSION.scope(session, task)))
+}
+
+/// 未在 `addUri` 的选项中指定时使用的设置
+struct Settings {
+    /// 添加的下载所属的会话
+    session: Arc<Session>,
+    secret: Option<String>,
+    dir: PathBuf,
+    split: usize,
+    web_ui: bool,
+}
+
+/// 在 `addr` 上提供 JSON-RPC，直至按下 Ctrl+C；之后等待各下载写完已收到的数据并保留续传记录
+pub async fn serve(
+    session: Arc<Session>,
+    addr: SocketAddr,
+    secret: Option<String>,
+    dir: PathBuf,
+    split: usize,
+    web_ui: bool,
+) -> Result {
+    let settings = Arc::new(Settings {
+        session,
+        secret,
+        dir,
+        split,
+        web_ui,
+    });
+    let server =
+        Server::try_bind(&addr)?.serve(make_service_fn(move |_| {
+            let settings = settings.clone();
+            async move {
+                Ok::<_, Infallible>(service_fn(move |request| handle(settings.clone(), request)))
+            }
+        }));
+    eprintln!("{}", Msg::DaemonListening(addr.to_string()));
+    if web_ui {
+        eprintln!("{}", Msg::WebUiListening(addr.to_string()));
+    }
+    spawn(sample());
+    match interrupt::guard(async { server.await.map_err(|e| anyhow!(e)) }).await {
+        Err(_) if interrupt::interrupted() => {}
+        result => return result,
+    }
+    while DOWNLOADS
+        .lock()
+        .unwrap()
+        .iter()
+        .any(|t| !t.status().stopped())
+    {
+        sleep(Duration::from_millis(100)).await;
+    }
+    Ok(())
+}
+
+/// 定期计算各下载的速度
+async fn sample() {
+    let mut ticker = interval(SAMPLE_INTERVAL);
+    loop {
+        ticker.tick().await;
+        for download in DOWNLOADS.lock().unwrap().iter() {
+            let (completed, _) = download.transfer.progress();
+            let last = download.sampled.swap(completed as u64, Ordering::Relaxed);
+            let speed =
+                (completed as u64).saturating_sub(last) as f64 / SAMPLE_INTERVAL.as_secs_f64();
+            download.speed.store(speed as u64, Ordering::Relaxed);
+        }
+    }
+}
+
+async fn handle(
+    settings: Arc<Settings>,
+    request: Request<Body>,
+) -> std::result::Result<Response<Body>, Infallible> {
+    let mut response = match (request.method(), request.uri().path()) {
+        // 浏览器中的前端跨域调用前先发送预检请求
+        (&Method::OPTIONS, _) => Response::new(Body::empty()),
+        (&Method::GET, "/") if settings.web_ui => {
+            let mut response = Response::new(Body::from(WEB_UI));
+            response.headers_mut().insert(
+                CONTENT_TYPE,
+                HeaderValue::from_static("text/html; charset=utf-8"),
+            );
+            response
+        }
+        (&Method::POST, "/jsonrpc") => match hyper::body::to_bytes(request.into_body()).await {
+            Ok(body) => {
+                let reply = match serde_json::from_slice::<Value>(&body) {
+                    Ok(Value::Array(calls)) => {
+                        Value::Array(calls.iter().map(|t| call(&settings, t)).collect())
+                    }
+                    Ok(t) => call(&settings, &t),
+                    Err(e) => reply(Value::Null, Err(anyhow!(e))),
+                };
+                let mut response = Response::new(Body::from(reply.to_string()));
+                response
+                    .headers_mut()
+                    .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+                response
+            }
+            Err(e) => {
+                let mut response = Response::new(Body::from(e.to_string()));
+                *response.status_mut() = StatusCode::BAD_REQUEST;
+                response
+            }
+        },
+        _ => {
+            let mut response = Response::new(Body::empty());
+            *response.status_mut() = StatusCode::NOT_FOUND;
+            response
+        }
+    };
+    let headers = response.headers_mut();
+    headers.insert(ACCESS_CONTROL_ALLOW_ORIGIN, HeaderValue::from_static("*"));
+    headers.insert(
+        ACCESS_CONTROL_ALLOW_METHODS,
+        HeaderValue::from_static("POST, OPTIONS"),
+    );
+    headers.insert(
+        ACCESS_CONTROL_ALLOW_HEADERS,
+        HeaderValue::from_static("Content-Type"),
+    );
+    Ok(response)
+}
+
+/// 处理一次调用，返回带有相同 `id` 的响应
+fn call(settings: &Settings, request: &Value) -> Value {
+    let id = request.get("id").cloned().unwrap_or(Value::Null);
+    let method = request
+        .get("method")
+        .and_then(Value::as_str)
+        .unwrap_or_default();
+    let params = match request.get("params") {
+        Some(Value::Array(t)) => t.clone(),
+        _ => Vec::new(),
+    };
+    reply(id, invoke(settings, method, params))
+}
+
+fn reply(id: Value, result: Result<Value>) -> Value {
+    match result {
+        Ok(t) => json!({ "jsonrpc": "2.0", "id": id, "result": t }),
+        Err(e) => json!({ "jsonrpc": "2.0", "id": id, "error": error(&e) }),
+    }
+}
+
+fn error(error: &anyhow::Error) -> Value {
+    json!({ "code": 1, "message": format!("{:#}", error) })
+}
+
+fn invoke(settings: &Settings, method: &str, mut params: Vec<Value>) -> Result<Value> {
+    match method {
+        // 各子调用自带令牌
+        "system.multicall" => {
+            let calls = match params.first() {
+                Some(Value::Array(t)) => t,
+                _ => return Err(anyhow!(Msg::RpcInvalidParams(method.to_string()))),
+            };
+            let results = calls
+                .iter()
+                .map(|t| {
+                    let method = t
+                        .get("methodName")
+                        .and_then(Value::as_str)
+                        .unwrap_or_default();
+                    let params = match t.get("params") {
+                        Some(Value::Array(t)) => t.clone(),
+                        _ => Vec::new(),
+                    };
+                    match invoke(settings, method, params) {
+                        Ok(t) => json!([t]),
+                        Err(e) => error(&e),
+                    }
+                })
+                .collect();
+            return Ok(Value::Array(results));
+        }
+        "system.listMethods" => return Ok(json!(METHODS)),
+        _ => {}
+    }
+    let token = params
+        .first()
+        .and_then(Value::as_str)
+        .and_then(|t| t.strip_prefix("token:"))
+        .map(String::from);
+    if token.is_some() {
+        params.remove(0);
+    }
+    if settings.secret.is_some() && token != settings.secret {
+        return Err(anyhow!(Msg::RpcUnauthorized));
+    }
+    let invalid = || anyhow!(Msg::RpcInvalidParams(method.to_string()));
+    let gid = || params.first().and_then(Value::as_str).ok_or_else(invalid);
+    let number = |i: usize| params.get(i).and_then(Value::as_i64).ok_or_else(invalid);
+    match method {
+        "aria2.addUri" => add_uri(settings, &params).map(Value::from),
+        "aria2.tellStatus" => Ok(status(&*find(gid()?)?, params.get(1))),
+        "aria2.tellActive" => Ok(list(|t| t == Status::Active, 0, -1, params.first())),
+        // 暂停的下载与 aria2 一样列在此处
+        "aria2.tellWaiting" => Ok(list(
+            |t| matches!(t, Status::Waiting | Status::Paused),
+            number(0)?,
+            number(1)?,
+            params.get(2),
+        )),
+        "aria2.tellStopped" => Ok(list(Status::stopped, number(0)?, number(1)?, params.get(2))),
+        "aria2.pause" | "aria2.forcePause" => set_paused(gid()?, true),
+        "aria2.unpause" => set_paused(gid()?, false),
+        "aria2.pauseAll" | "aria2.forcePauseAll" | "aria2.unpauseAll" => {
+            let paused = method != "aria2.unpauseAll";
+            let gids: Vec<_> = DOWNLOADS
+                .lock()
+                .unwrap()
+                .iter()
+                .filter(|t| !t.status().stopped())
+                .map(|t| t.gid.clone())
+                .collect();
+            for gid in gids {
+                set_paused(&gid, paused)?;
+            }
+            Ok(json!("OK"))
+        }
+        "aria2.remove" | "aria2.forceRemove" => {
+            let download = find(gid()?)?;
+            let mut status = download.status.lock().unwrap();
+            if status.stopped() {
+                return Err(anyhow!(Msg::RpcInvalidStatus {
+                    gid: download.gid.clone(),
+                    status: status.as_str().to_string(),
+                }));
+            }
+            *status = Status::Removed;
+            download.transfer.remove();
+            Ok(json!(download.gid))
+        }
+        "aria2.removeDownloadResult" => {
+            let download = find(gid()?)?;
+            if !download.status().stopped() {
+                return Err(anyhow!(Msg::RpcInvalidStatus {
+                    gid: download.gid.clone(),
+                    status: download.status().as_str().to_string(),
+                }));
+            }
+            DOWNLOADS
+                .lock()
+                .unwrap()
+                .retain(|t| !Arc::ptr_eq(t, &download));
+            Ok(json!("OK"))
+        }
+        "aria2.purgeDownloadResult" => {
+            DOWNLOADS.lock().unwrap().retain(|t| !t.status().stopped());
+            Ok(json!("OK"))
+        }
+        "aria2.getGlobalStat" => {
+            let downloads = DOWNLOADS.lock().unwrap();
+            let count = |f: fn(Status) -> bool| {
+                downloads
+                    .iter()
+                    .filter(|t| f(t.status()))
+                    .count()
+                    .to_string()
+            };
+            let speed: u64 = downloads.iter().map(|t| speed(t)).sum();
+            Ok(json!({
+                "downloadSpeed": speed.to_string(),
+                "uploadSpeed": "0",
+                "numActive": count(|t| t == Status::Active),
+                "numWaiting": count(|t| matches!(t, Status::Waiting | Status::Paused)),
+                "numStopped": count(Status::stopped),
+                "numStoppedTotal": count(Status::stopped),
+            }))
+        }
+        "aria2.getVersion" => Ok(json!({
+            "version": env!("CARGO_PKG_VERSION"),
+            "enabledFeatures": [],
+        })),
+        _ => Err(anyhow!(Msg::RpcUnknownMethod(method.to_string()))),
+    }
+}
+
+/// 添加下载并返回其 GID，只使用第一个地址；支持 `dir`、`out`、`split` 选项，`out` 须为 `dir` 下的相对路径
+fn add_uri(settings: &Settings, params: &[Value]) -> Result<String> {
+    let invalid = || anyhow!(Msg::RpcInvalidParams("aria2.addUri".to_string()));
+    let uri = params
+        .first()
+        .and_then(Value::as_array)
+        .and_then(|t| t.first())
+        .and_then(Value::as_str)
+        .ok_or_else(invalid)?;
+    let options = params.get(1);
+    let option = |name| options.and_then(|t| t.get(name)).and_then(Value::as_str);
+    let dir = option("dir").map_or_else(|| settings.dir.clone(), PathBuf::from);
+    let split = match option("split") {
+        None => settings.split,
+        Some(t) => match t.parse() {
+            Ok(0) | Err(_) => return Err(invalid()),
+            Ok(t) => t,
+        },
+    };
+    let output = match option("out") {
+        None => OutputTarget::Infer(dir.clone()),
+        Some(t) => {
+            // 只能保存到 `dir` 之下
+            let relative = Path::new(t)
+                .components()
+                .all(|t| matches!(t, Component::Normal(_)));
+            if t.is_empty() || !relative {
+                return Err(anyhow!(Msg::RpcInvalidOutput(t.to_string())));
+            }
+            let path = dir.join(t);
+            if path.exists() {
+                return Err(anyhow!(Msg::FileExists(path.display().to_string())));
+            }
+            OutputTarget::Path(path.display().to_string())
+        }
+    };
+    let (transfer, handle) =
+        spawn_transfer(settings.session.clone(), split, uri.parse::<Uri>()?, output);
+    let download = Arc::new(Download {
+        gid: Uuid::new_v4().to_simple().to_string()[..16].to_string(),
+        uri: uri.to_string(),
+        dir,
+        split,
+        transfer,
+        status: Mutex::new(Status::Active),
+        error: Mutex::new(None),
+        sampled: AtomicU64::new(0),
+        speed: AtomicU64::new(0),
+    });
+    DOWNLOADS.lock().unwrap().push(download.clone());
+    let gid = download.gid.clone();
+    spawn(async move {
+        let result = match handle.await {
+            Ok(t) => t,
+            Err(e) => Err(anyhow!(e)),
+        };
+        let mut status = download.status.lock().unwrap();
+        // 移除的下载失败是预期的，保持已移除的状态
+        match result {
+            _ if *status == Status::Removed => {}
+            Ok(_) => *status = Status::Complete,
+            Err(e) => {
+                *status = Status::Error;
+                *download.error.lock().unwrap() = Some(format!("{:#}", e));
+            }
+        }
+    });
+    Ok(gid)
+}
+
+fn find(gid: &str) -> Result<Arc<Download>> {
+    DOWNLOADS
+        .lock()
+        .unwrap()
+        .iter()
+        .find(|t| t.gid == gid)
+        .cloned()
+        .ok_or_else(|| anyhow!(Msg::RpcDownloadNotFound(gid.to_string())))
+}
+
+fn set_paused(gid: &str, paused: bool) -> Result<Value> {
+    let download = find(gid)?;
+    let mut status = download.status.lock().unwrap();
+    let (from, to) = match paused {
+        true => (Status::Active, Status::Paused),
+        false => (Status::Paused, Status::Active),
+    };
+    if *status != from {
+        return Err(anyhow!(Msg::RpcInvalidStatus {
+            gid: download.gid.clone(),
+            status: status.as_str().to_string(),
+        }));
+    }
+    *status = to;
+    download.transfer.set_paused(paused);
+    Ok(json!(download.gid))
+}
+
+fn speed(download: &Download) -> u64 {
+    match download.status() {
+        Status::Active => download.speed.load(Ordering::Relaxed),
+        _ => 0,
+    }
+}
+
+/// 状态满足 `filter` 的下载，`offset` 为负数时从末尾起倒序列出；`num` 为负数时不限数量
+fn list(filter: fn(Status) -> bool, offset: i64, num: i64, keys: Option<&Value>) -> Value {
+    let downloads: Vec<_> = DOWNLOADS
+        .lock()
+        .unwrap()
+        .iter()
+        .filter(|t| filter(t.status()))
+        .cloned()
+        .collect();
+    let num = usize::try_from(num).unwrap_or(usize::MAX);
+    let selected: Vec<_> = if offset >= 0 {
+        downloads.iter().skip(offset as usize).take(num).collect()
+    } else {
+        let skip = (-offset - 1) as usize;
+        downloads.iter().rev().skip(skip).take(num).collect()
+    };
+    Value::Array(selected.into_iter().map(|t| status(t, keys)).collect())
+}
+
+/// `tellStatus` 的结果，`keys` 非空时只保留其中的字段
+fn status(download: &Download, keys: Option<&Value>) -> Value {
+    let status = download.status();
+    let (mut completed, total) = download.transfer.progress();
+    if status == Status::Complete {
+        completed = total;
+    }
+    let connections = match status {
+        Status::Active => download.split,
+        _ => 0,
+    };
+    let mut value = json!({
+        "gid": download.gid,
+        "status": status.as_str(),
+        "totalLength": total.to_string(),
+        "completedLength": completed.to_string(),
+        "uploadLength": "0",
+        "downloadSpeed": speed(download).to_string(),
+        "uploadSpeed": "0",
+        "connections": connections.to_string(),
+        "dir": download.dir.display().to_string(),
+        "files": [{
+            "index": "1",
+            "path": download.transfer.file_path(),
+            "length": total.to_string(),
+            "completedLength": completed.to_string(),
+            "selected": "true",
+            "uris": [{ "uri": download.uri, "status": "used" }],
+        }],
+    });
+    if let (Some(object), Some(error)) = (value.as_object_mut(), &*download.error.lock().unwrap()) {
+        object.insert("errorCode".to_string(), json!("1"));
+        object.insert("errorMessage".to_string(), json!(error));
+    }
+    let keys: Vec<&str> = keys
+        .and_then(Value::as_array)
+        .map(|t| t.iter().filter_map(Value::as_str).collect())
+        .unwrap_or_default();
+    if let (Some(object), false) = (value.as_object_mut(), keys.is_empty()) {
+        object.retain(|key, _| keys.contains(&key.as_str()));
+    }
+    value
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::config::Config;
+
+    #[test]
+    fn add_uri_rejects_invalid_options() {
+        let config = Config::download(1, "http://127.0.0.1/".parse().unwrap(), "unused".into());
+        let settings = Settings {
+            session: Session::new(config.unwrap()).unwrap(),
+            secret: None,
+            dir: PathBuf::from("downloads"),
+            split: 5,
+            web_ui: false,
+        };
+        for (options, expected) in [
+            (json!({ "split": "0" }), "aria2.addUri"),
+            (json!({ "split": "-1" }), "aria2.addUri"),
+            (json!({ "split": "many" }), "aria2.addUri"),
+            (json!({ "out": "/etc/passwd" }), "/etc/passwd"),
+            (json!({ "out": "../escape.bin" }), "../escape.bin"),
+            (json!({ "out": "a/../../escape.bin" }), "a/../../escape.bin"),
+            (json!({ "out": "./" }), "./"),
+            (json!({ "out": "" }), "``"),
+        ] {
+            let params = vec![json!(["http://127.0.0.1:9/a.bin"]), options.clone()];
+            let error = add_uri(&settings, &params).unwrap_err().to_string();
+            assert!(error.contains(expected), "{:?}: {}", options, error);
+        }
+    }
+
+    #[tokio::test]
+    async fn add_uri_then_tell_status_until_complete() {
+        const SIZE: usize = 100_000;
+        let service = make_service_fn(|_| async {
+            Ok::<_, Infallible>(service_fn(|_| async {
+                Ok::<_, Infallible>(Response::new(Body::from(vec![7; SIZE])))
+            }))
+        });
+        let server = Server::bind(&([127, 0, 0, 1], 0).into()).serve(service);
+        let uri = format!("http://{}/a.bin", server.local_addr());
+        spawn(server);
+
+        let dir = std::env::temp_dir().join(format!("download-daemon-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut config = Config::download(1, uri.parse().unwrap(), "unused".into()).unwrap();
+        config.quiet = true;
+        let settings = Settings {
+            session: Session::new(config).unwrap(),
+            secret: None,
+            dir: dir.clone(),
+            split: 2,
+            web_ui: false,
+        };
+        let request = |method: &str, params: Value| {
+            let reply = call(
+                &settings,
+                &json!({ "jsonrpc": "2.0", "id": 1, "method": method, "params": params }),
+            );
+            assert_eq!(reply["error"], Value::Null, "{}", reply);
+            reply["result"].clone()
+        };
+        let gid = request("aria2.addUri", json!([[uri], { "out": "a.bin" }]));
+        let keys = json!(["status", "totalLength", "completedLength"]);
+        let mut status = Value::Null;
+        for _ in 0..100 {
+            status = request("aria2.tellStatus", json!([gid, keys]));
+            if status["status"] != "active" && status["status"] != "waiting" {
+                break;
+            }
+            sleep(Duration::from_millis(100)).await;
+        }
+        let expected = json!({
+            "status": "complete",
+            "totalLength": SIZE.to_string(),
+            "completedLength": SIZE.to_string(),
+        });
+        assert_eq!(status, expected);
+        assert_eq!(std::fs::read(dir.join("a.bin")).unwrap(), vec![7; SIZE]);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,266 @@
+//! 从 TOML 配置文件读取参数的默认值
+//!
+//! 键名与命令行选项的长名相同，如 `retry = 5`、`proxy = "http://127.0.0.1:3128"`，
+//! 开关写作 `keep-partial = true`，可重复的选项写作字符串数组。`size` 为并发任务数量，
+//! 命令行中省略 `<size>` 时使用。命令行中已指定的选项覆盖配置文件中的值
+
+use std::fs::read_to_string;
+use std::path::Path;
+
+use anyhow::{anyhow, Context};
+use clap::{Arg, Command};
+use toml::{Table, Value};
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 未指定 `--config` 时读取的配置文件
+const DEFAULT_FILE: &str = "download.toml";
+
+/// 将配置文件中的值插入命令行参数，返回合并后的参数
+pub fn merge(command: &Command, mut args: Vec<String>) -> Result<Vec<String>> {
+    let path = match config_path(&args) {
+        Some(t) => t,
+        None if Path::new(DEFAULT_FILE).is_file() => DEFAULT_FILE.to_string(),
+        None => return Ok(args),
+    };
+    let table: Table = read_to_string(&path)
+        .map_err(anyhow::Error::from)
+        .and_then(|t| Ok(t.parse()?))
+        .with_context(|| Msg::InvalidConfigFile(path.clone()))?;
+
+    // 子命令的参数需写在子命令名之后
+    let first = first_positional(command, &args);
+    let subcommand = first.and_then(|i| command.find_subcommand(&args[i]).map(|t| (i, t)));
+    let (at, accepted): (usize, Vec<&Arg>) = match subcommand {
+        Some((i, sub)) => (
+            i + 1,
+            sub.get_arguments()
+                .chain(command.get_arguments().filter(|t| t.is_global_set()))
+                .collect(),
+        ),
+        None => (1, command.get_arguments().collect()),
+    };
+
+    let mut inserted = Vec::new();
+    for (key, value) in &table {
+        if key == "size" || key == "config" {
+            continue;
+        }
+        let arg = match command
+            .get_arguments()
+            .chain(command.get_subcommands().flat_map(Command::get_arguments))
+            .find(|t| t.get_long() == Some(key.as_str()))
+        {
+            Some(t) => t,
+            None => return Err(anyhow!(Msg::UnknownConfigKey(key.clone()))),
+        };
+        // 当前命令不接受或命令行中已指定的选项
+        if !accepted.iter().any(|t| t.get_id() == arg.get_id()) || given(&args, key) {
+            continue;
+        }
+        // 每次需要多个值的选项无法写作单个字符串
+        if arg.get_num_vals().unwrap_or(1) > 1 {
+            return Err(anyhow!(Msg::InvalidConfigValue(key.clone())));
+        }
+        let values = match value {
+            Value::Array(t) => t.iter().collect(),
+            t => vec![t],
+        };
+        for value in values {
+            match value {
+                Value::Boolean(true) if !arg.is_takes_value_set() => {
+                    inserted.push(format!("--{}", key))
+                }
+                Value::Boolean(false) if !arg.is_takes_value_set() => {}
+                Value::String(t) => inserted.push(format!("--{}={}", key, t)),
+                Value::Integer(t) => inserted.push(format!("--{}={}", key, t)),
+                Value::Float(t) => inserted.push(format!("--{}={}", key, t)),
+                _ => return Err(anyhow!(Msg::InvalidConfigValue(key.clone()))),
+            }
+        }
+    }
+
+    // 省略 `<size>` 时插入配置文件中的并发任务数量
+    if let (None, Some(size)) = (subcommand, table.get("size")) {
+        let size = match size {
+            Value::Integer(t) => t.to_string(),
+            _ => return Err(anyhow!(Msg::InvalidConfigValue("size".to_string()))),
+        };
+        let omitted = match first {
+            None => true,
+            Some(i) => args[i].parse::<usize>().is_err(),
+        };
+        if omitted {
+            args.insert(first.unwrap_or(args.len()), size);
+        }
+    }
+    args.splice(at..at, inserted);
+    Ok(args)
+}
+
+/// 命令行中 `--config` 的值
+fn config_path(args: &[String]) -> Option<String> {
+    let mut args = args.iter().skip(1);
+    while let Some(t) = args.next() {
+        if t == "--config" {
+            return args.next().cloned();
+        }
+        if let Some(value) = t.strip_prefix("--config=") {
+            return Some(value.to_string());
+        }
+    }
+    None
+}
+
+/// 命令行中是否已指定 `--<long>`
+fn given(args: &[String], long: &str) -> bool {
+    args.iter().skip(1).any(|t| {
+        t.strip_prefix("--")
+            .map(|t| t.split_once('=').map_or(t, |(name, _)| name) == long)
+            .unwrap_or(false)
+    })
+}
+
+/// 第一个位置参数的下标，跳过各选项及其值
+fn first_positional(command: &Command, args: &[String]) -> Option<usize> {
+    let mut i = 1;
+    while i < args.len() {
+        let t = &args[i];
+        if t == "--" {
+            return (i + 1 < args.len()).then_some(i + 1);
+        }
+        if let Some(long) = t.strip_prefix("--") {
+            if !long.contains('=') {
+                let takes = command
+                    .get_arguments()
+                    .find(|arg| arg.get_long() == Some(long))
+                    .filter(|arg| arg.is_takes_value_set())
+                    .map(|arg| arg.get_num_vals().unwrap_or(1));
+                i += takes.unwrap_or(0);
+            }
+        } else if !t.starts_with('-') || t == "-" {
+            return Some(i);
+        }
+        i += 1;
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::{remove_file, write};
+
+    use super::*;
+
+    fn command() -> Command<'static> {
+        Command::new("download")
+            .arg(Arg::new("size"))
+            .arg(Arg::new("uri"))
+            .arg(Arg::new("retry").long("retry").takes_value(true))
+            .arg(Arg::new("keep-partial").long("keep-partial"))
+            .arg(
+                Arg::new("header")
+                    .long("header")
+                    .takes_value(true)
+                    .multiple_occurrences(true)
+                    .global(true),
+            )
+            .subcommand(
+                Command::new("daemon").arg(
+                    Arg::new("rpc-listen-port")
+                        .long("rpc-listen-port")
+                        .takes_value(true),
+                ),
+            )
+    }
+
+    fn merge_with(config: &str, args: &[&str]) -> Result<Vec<String>> {
+        let path =
+            std::env::temp_dir().join(format!("download-defaults-{}.toml", std::process::id()));
+        write(&path, config).unwrap();
+        let mut argv = vec![
+            "download".to_string(),
+            format!("--config={}", path.display()),
+        ];
+        argv.extend(args.iter().map(|t| t.to_string()));
+        let merged = merge(&command(), argv);
+        remove_file(&path).unwrap();
+        merged.map(|t| {
+            t.into_iter()
+                .skip(1)
+                .filter(|t| !t.starts_with("--config="))
+                .collect()
+        })
+    }
+
+    #[test]
+    fn merge_inserts_values_not_given() {
+        let config = r#"
+            size = 4
+            retry = 5
+            keep-partial = true
+            header = ["a: 1", "b: 2"]
+            rpc-listen-port = 6800
+        "#;
+        for (args, expected) in [
+            (
+                &["http://a/b"][..],
+                &[
+                    "--header=a: 1",
+                    "--header=b: 2",
+                    "--keep-partial",
+                    "--retry=5",
+                    "4",
+                    "http://a/b",
+                ][..],
+            ),
+            (
+                &["--retry", "1", "8", "http://a/b"],
+                &[
+                    "--header=a: 1",
+                    "--header=b: 2",
+                    "--keep-partial",
+                    "--retry",
+                    "1",
+                    "8",
+                    "http://a/b",
+                ],
+            ),
+            (
+                &["daemon"],
+                &[
+                    "daemon",
+                    "--header=a: 1",
+                    "--header=b: 2",
+                    "--rpc-listen-port=6800",
+                ],
+            ),
+        ] {
+            let merged = merge_with(config, args).unwrap();
+            assert!(merged == expected, "{:?} {:?}", args, merged);
+        }
+    }
+
+    #[test]
+    fn merge_rejects_unknown_keys_and_values() {
+        for config in ["speed = 1", "retry = { a = 1 }", "size = \"4\""] {
+            assert!(merge_with(config, &["http://a/b"]).is_err(), "{}", config);
+        }
+        assert!(merge_with("retry = [", &[]).is_err());
+    }
+
+    #[test]
+    fn first_positional_skips_option_values() {
+        for (args, expected) in [
+            (&["download", "--retry", "3", "4", "u"][..], Some(3)),
+            (&["download", "--retry=3", "--keep-partial", "u"], Some(3)),
+            (&["download", "--", "-u"], Some(2)),
+            (&["download", "--retry", "3"], None),
+        ] {
+            let args: Vec<String> = args.iter().map(|t| t.to_string()).collect();
+            let found = first_positional(&command(), &args);
+            assert!(found == expected, "{:?} {:?}", args, found);
+        }
+    }
+}
@@ -0,0 +1,30 @@
+//! 桌面通知
+//!
+//! 启用 `notify` 功能并指定 `--notify` 时，每个下载结束后发送一条桌面通知
+
+#[cfg(not(feature = "notify"))]
+use anyhow::anyhow;
+
+#[cfg(not(feature = "notify"))]
+use crate::message::Msg;
+use crate::Result;
+
+/// 发送标题为 `summary`、正文为 `body` 的通知，在阻塞线程中等待系统的通知服务返回
+#[cfg(feature = "notify")]
+pub async fn send(summary: String, body: String) -> Result {
+    tokio::task::spawn_blocking(move || {
+        notify_rust::Notification::new()
+            .appname(clap::crate_name!())
+            .summary(&summary)
+            .body(&body)
+            .show()
+            .map(|_| ())
+    })
+    .await??;
+    Ok(())
+}
+
+#[cfg(not(feature = "notify"))]
+pub async fn send(_summary: String, _body: String) -> Result {
+    Err(anyhow!(Msg::NotifyUnsupported))
+}
@@ -0,0 +1,55 @@
+use hyper::Uri;
+
+use crate::config::Config;
+use crate::http::{self, ProgressCallback};
+use crate::Result;
+
+/// 在其他程序中使用的下载器
+///
+/// 每次运行使用各自的配置、连接及认证状态，可以重复运行，也可以同时运行多个
+///
+/// ```no_run
+/// # async fn example() -> download::Result {
+/// download::Downloader::new("https://example.com/file.bin".parse()?, "file.bin")
+///     .connections(8)
+///     .progress(|downloaded, total| println!("{}/{}", downloaded, total))
+///     .run()
+///     .await
+/// # }
+/// ```
+pub struct Downloader {
+    uri: Uri,
+    output: String,
+    connections: usize,
+    progress: Option<ProgressCallback>,
+}
+
+impl Downloader {
+    pub fn new(uri: Uri, output: impl Into<String>) -> Self {
+        Self {
+            uri,
+            output: output.into(),
+            connections: 4,
+            progress: None,
+        }
+    }
+
+    /// 并发连接数，默认为 4
+    pub fn connections(mut self, connections: usize) -> Self {
+        self.connections = connections;
+        self
+    }
+
+    /// 设置进度回调，参数为已下载的字节数及资源大小
+    pub fn progress(mut self, progress: impl Fn(u64, u64) + Send + Sync + 'static) -> Self {
+        self.progress = Some(Box::new(progress));
+        self
+    }
+
+    /// 开始下载，完成后返回；不显示进度条及日志
+    pub async fn run(self) -> Result {
+        let mut config = Config::download(self.connections, self.uri, self.output)?;
+        config.quiet = true;
+        http::run_download(config, self.progress).await
+    }
+}
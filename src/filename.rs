@@ -0,0 +1,71 @@
+//! 未指定保存路径时推断文件名
+
+use hyper::Uri;
+
+use crate::proxy::percent_decode;
+
+/// 从 `Content-Disposition` 头中取出文件名，`filename*`（RFC 6266）优先于 `filename`
+pub fn from_content_disposition(value: &str) -> Option<String> {
+    let mut plain = None;
+    let mut extended = None;
+    for param in value.split(';').skip(1) {
+        let (name, value) = match param.split_once('=') {
+            Some((name, value)) => (name.trim().to_ascii_lowercase(), value.trim()),
+            None => continue,
+        };
+        match name.as_str() {
+            // `<charset>'<language>'<percent-encoded>`，只支持 UTF-8 及 ASCII
+            "filename*" => {
+                let mut parts = value.splitn(3, '\'');
+                if let (Some(charset), Some(_), Some(encoded)) =
+                    (parts.next(), parts.next(), parts.next())
+                {
+                    if charset.eq_ignore_ascii_case("utf-8")
+                        || charset.eq_ignore_ascii_case("us-ascii")
+                    {
+                        extended = String::from_utf8(percent_decode(encoded)).ok();
+                    }
+                }
+            }
+            "filename" => {
+                plain = Some(match value.strip_prefix('"') {
+                    Some(t) => unquote(t),
+                    None => value.to_string(),
+                })
+            }
+            _ => {}
+        }
+    }
+    extended.or(plain).and_then(|t| sanitize(&t))
+}
+
+/// 取出 URI 路径的最后一段
+pub fn from_uri(uri: &Uri) -> Option<String> {
+    let segment = uri.path().rsplit('/').next()?;
+    sanitize(&String::from_utf8_lossy(&percent_decode(segment)))
+}
+
+/// 去掉引号字符串的转义及结尾的引号
+fn unquote(s: &str) -> String {
+    let mut text = String::with_capacity(s.len());
+    let mut chars = s.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => break,
+            '\\' => text.extend(chars.next()),
+            c => text.push(c),
+        }
+    }
+    text
+}
+
+/// 只保留最后一段路径，避免写入其他目录
+pub fn sanitize(name: &str) -> Option<String> {
+    let name = name.rsplit(['/', '\\']).next()?;
+    let name: String = name.chars().filter(|c| !c.is_control()).collect();
+    let name = name.trim();
+    match name {
+        "" | "." | ".." => None,
+        t => Some(t.to_string()),
+    }
+}
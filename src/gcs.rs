@@ -0,0 +1,319 @@
+//! 下载 `gs://<bucket>/<object>`
+//!
+//! 转为 JSON API 的 `alt=media` 地址，照常以 range 请求并发下载。访问令牌按 application default credentials
+//! 的顺序获取，过期前在后台刷新
+
+use std::env;
+use std::fs::read_to_string;
+use std::path::PathBuf;
+use std::sync::{Arc, RwLock, Weak};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::{anyhow, Context};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper::body::to_bytes;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Request, Uri};
+use rsa::pkcs1v15::SigningKey;
+use rsa::pkcs8::DecodePrivateKey;
+use rsa::signature::{SignatureEncoding, Signer};
+use rsa::RsaPrivateKey;
+use serde_json::{json, Value};
+use sha2::Sha256;
+use tokio::spawn;
+use tokio::sync::OnceCell;
+use tokio::time::sleep;
+
+use crate::message::Msg;
+use crate::proxy::percent_decode;
+use crate::transport::HttpClient;
+use crate::util::percent_encode;
+use crate::Result;
+
+const TOKEN_URI: &str = "https://oauth2.googleapis.com/token";
+const METADATA_TOKEN_URI: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+const SCOPE: &str = "https://www.googleapis.com/auth/devstorage.read_only";
+/// 令牌过期前多久刷新
+const REFRESH_MARGIN: Duration = Duration::from_secs(300);
+/// 刷新失败后重试的间隔
+const RETRY_INTERVAL: Duration = Duration::from_secs(30);
+
+/// 一次运行中的访问令牌，首次解析 `gs://` 地址时获取
+#[derive(Default)]
+pub struct State {
+    account: OnceCell<Arc<Account>>,
+}
+
+/// 获取访问令牌的方式
+enum Source {
+    /// `GOOGLE_OAUTH_ACCESS_TOKEN` 指定的令牌，不刷新
+    Static(String),
+    /// `gcloud auth application-default login` 生成的用户凭据
+    AuthorizedUser {
+        client_id: String,
+        client_secret: String,
+        refresh_token: String,
+        token_uri: String,
+    },
+    ServiceAccount {
+        client_email: String,
+        private_key: String,
+        token_uri: String,
+    },
+    /// GCE 等环境的元数据服务器
+    Metadata,
+}
+
+struct Account {
+    /// JSON API 所在的主机，只有发往该主机的请求附加令牌
+    host: String,
+    source: Option<Source>,
+    /// 使用 `STORAGE_EMULATOR_HOST` 且没有凭据时为空
+    authorization: RwLock<Option<HeaderValue>>,
+}
+
+impl Source {
+    /// 按 application default credentials 的顺序查找
+    fn find() -> Result<Option<Self>> {
+        if let Ok(token) = env::var("GOOGLE_OAUTH_ACCESS_TOKEN") {
+            return Ok(Some(Self::Static(token)));
+        }
+        let path = match env::var_os("GOOGLE_APPLICATION_CREDENTIALS") {
+            Some(path) => Some(PathBuf::from(path)),
+            None => well_known_file().filter(|t| t.exists()),
+        };
+        let path = match path {
+            Some(t) => t,
+            None => return Ok(Some(Self::Metadata)),
+        };
+        let invalid = || Msg::InvalidGcsCredentials(path.display().to_string());
+        let value: Value = serde_json::from_str(&read_to_string(&path).with_context(invalid)?)
+            .with_context(invalid)?;
+        let text = |key: &str| value.get(key).and_then(Value::as_str).map(String::from);
+        let token_uri = text("token_uri").unwrap_or_else(|| TOKEN_URI.to_string());
+        let source = match value.get("type").and_then(Value::as_str) {
+            Some("authorized_user") => Self::AuthorizedUser {
+                client_id: text("client_id").ok_or_else(|| anyhow!(invalid()))?,
+                client_secret: text("client_secret").ok_or_else(|| anyhow!(invalid()))?,
+                refresh_token: text("refresh_token").ok_or_else(|| anyhow!(invalid()))?,
+                token_uri,
+            },
+            Some("service_account") => Self::ServiceAccount {
+                client_email: text("client_email").ok_or_else(|| anyhow!(invalid()))?,
+                private_key: text("private_key").ok_or_else(|| anyhow!(invalid()))?,
+                token_uri,
+            },
+            _ => return Err(anyhow!(invalid())),
+        };
+        Ok(Some(source))
+    }
+
+    /// 获取访问令牌及其有效期
+    async fn token(&self, client: &dyn HttpClient) -> Result<(String, Option<Duration>)> {
+        let request = match self {
+            Self::Static(token) => return Ok((token.clone(), None)),
+            Self::AuthorizedUser {
+                client_id,
+                client_secret,
+                refresh_token,
+                token_uri,
+            } => form_request(
+                token_uri,
+                &[
+                    ("grant_type", "refresh_token"),
+                    ("client_id", client_id),
+                    ("client_secret", client_secret),
+                    ("refresh_token", refresh_token),
+                ],
+            )?,
+            Self::ServiceAccount {
+                client_email,
+                private_key,
+                token_uri,
+            } => {
+                let assertion = jwt(client_email, private_key, token_uri)?;
+                form_request(
+                    token_uri,
+                    &[
+                        ("grant_type", "urn:ietf:params:oauth:grant-type:jwt-bearer"),
+                        ("assertion", &assertion),
+                    ],
+                )?
+            }
+            Self::Metadata => Request::get(METADATA_TOKEN_URI)
+                .header("Metadata-Flavor", "Google")
+                .body(Body::empty())?,
+        };
+        let response = client.request(request).await.map_err(|e| match self {
+            Self::Metadata => anyhow!(Msg::GcsCredentialsMissing),
+            _ => e,
+        })?;
+        let status = response.status();
+        let body = to_bytes(response.into_body()).await?;
+        if !status.is_success() {
+            let body = String::from_utf8_lossy(&body).into_owned();
+            return Err(anyhow!(Msg::GcsTokenFailed(status.to_string(), body)));
+        }
+        let value: Value = serde_json::from_slice(&body)?;
+        let token = value
+            .get("access_token")
+            .and_then(Value::as_str)
+            .ok_or_else(|| anyhow!(Msg::GcsTokenFailed(status.to_string(), value.to_string())))?;
+        let expires_in = value.get("expires_in").and_then(Value::as_u64);
+        Ok((token.to_string(), expires_in.map(Duration::from_secs)))
+    }
+}
+
+impl State {
+    /// 把 `gs://<bucket>/<object>` 转为 JSON API 地址，并获取访问令牌
+    ///
+    /// 设置 `STORAGE_EMULATOR_HOST` 时使用模拟器的 HTTP 地址，没有凭据时不附加令牌
+    pub async fn resolve(&self, uri: &Uri, client: Arc<dyn HttpClient>) -> Result<Uri> {
+        let invalid = || anyhow!(Msg::InvalidGcsUri(uri.to_string()));
+        let bucket = uri.host().ok_or_else(invalid)?;
+        let object = uri.path().trim_start_matches('/');
+        if object.is_empty() {
+            return Err(invalid());
+        }
+        let emulator = env::var("STORAGE_EMULATOR_HOST").ok();
+        let base = match &emulator {
+            Some(host) if host.contains("://") => host.trim_end_matches('/').to_string(),
+            Some(host) => format!("http://{}", host.trim_end_matches('/')),
+            None => "https://storage.googleapis.com".to_string(),
+        };
+        let resolved: Uri = format!(
+            "{}/storage/v1/b/{}/o/{}?alt=media",
+            base,
+            bucket,
+            // 对象名中的 `/` 也需编码
+            percent_encode(&percent_decode(object), b"")
+        )
+        .parse()?;
+        let host = resolved.authority().ok_or_else(invalid)?.to_string();
+        // 并发解析时只获取一次令牌，由完成初始化的调用启动刷新
+        let mut refresh_after = None;
+        let account = self
+            .account
+            .get_or_try_init(|| async {
+                let source = match Source::find() {
+                    // 模拟器不需要认证，找不到凭据时忽略
+                    Ok(Some(Source::Metadata)) | Err(_) if emulator.is_some() => None,
+                    t => t?,
+                };
+                let authorization = match &source {
+                    None => None,
+                    Some(source) => {
+                        let (token, expires_in) = source.token(client.as_ref()).await?;
+                        refresh_after = expires_in;
+                        Some(bearer(&token)?)
+                    }
+                };
+                Ok::<_, anyhow::Error>(Arc::new(Account {
+                    host,
+                    source,
+                    authorization: RwLock::new(authorization),
+                }))
+            })
+            .await?;
+        if let Some(expires_in) = refresh_after {
+            spawn(refresh(Arc::downgrade(account), client, expires_in));
+        }
+        Ok(resolved)
+    }
+
+    /// 发往 JSON API 的请求需附加的 `Authorization` 请求头
+    pub fn authorization(&self, uri: &Uri) -> Option<HeaderValue> {
+        let account = self.account.get()?;
+        if uri.authority().map(|t| t.as_str()) != Some(account.host.as_str()) {
+            return None;
+        }
+        account.authorization.read().unwrap().clone()
+    }
+}
+
+/// 在令牌过期前刷新，失败时稍后重试；运行结束、不再使用该令牌后停止
+async fn refresh(account: Weak<Account>, client: Arc<dyn HttpClient>, mut expires_in: Duration) {
+    loop {
+        sleep(expires_in.saturating_sub(REFRESH_MARGIN)).await;
+        let account = match account.upgrade() {
+            Some(t) => t,
+            None => return,
+        };
+        let source = match &account.source {
+            Some(t) => t,
+            None => return,
+        };
+        expires_in = match source.token(client.as_ref()).await {
+            Ok((token, Some(expires_in))) => {
+                if let Ok(value) = bearer(&token) {
+                    *account.authorization.write().unwrap() = Some(value);
+                }
+                expires_in
+            }
+            Ok((_, None)) => return,
+            Err(_) => REFRESH_MARGIN + RETRY_INTERVAL,
+        };
+    }
+}
+
+fn bearer(token: &str) -> Result<HeaderValue> {
+    Ok(HeaderValue::from_str(&format!("Bearer {}", token))?)
+}
+
+/// 服务账号以 RS256 签名的 JWT 换取访问令牌
+fn jwt(client_email: &str, private_key: &str, token_uri: &str) -> Result<String> {
+    let now = SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let header = json!({ "alg": "RS256", "typ": "JWT" });
+    let claims = json!({
+        "iss": client_email,
+        "scope": SCOPE,
+        "aud": token_uri,
+        "iat": now,
+        "exp": now + 3600,
+    });
+    let message = format!(
+        "{}.{}",
+        URL_SAFE_NO_PAD.encode(header.to_string()),
+        URL_SAFE_NO_PAD.encode(claims.to_string())
+    );
+    let key = RsaPrivateKey::from_pkcs8_pem(private_key)?;
+    let signature = SigningKey::<Sha256>::new(key).sign(message.as_bytes());
+    Ok(format!(
+        "{}.{}",
+        message,
+        URL_SAFE_NO_PAD.encode(signature.to_bytes())
+    ))
+}
+
+fn form_request(uri: &str, fields: &[(&str, &str)]) -> Result<Request<Body>> {
+    let body = fields
+        .iter()
+        .map(|(name, value)| {
+            format!(
+                "{}={}",
+                percent_encode(name.as_bytes(), b""),
+                percent_encode(value.as_bytes(), b"")
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("&");
+    Ok(Request::post(uri)
+        .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+        .body(Body::from(body))?)
+}
+
+/// gcloud 保存 application default credentials 的位置
+fn well_known_file() -> Option<PathBuf> {
+    let dir = match env::var_os("APPDATA") {
+        Some(t) if cfg!(windows) => PathBuf::from(t).join("gcloud"),
+        _ => PathBuf::from(env::var_os("HOME")?)
+            .join(".config")
+            .join("gcloud"),
+    };
+    Some(dir.join("application_default_credentials.json"))
+}
@@ -0,0 +1,145 @@
+use std::fmt::{self, Display, Formatter};
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use hyper::Uri;
+use sha2::{Digest, Sha256};
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 续传句柄的前缀及格式版本
+const PREFIX: &str = "dlr1";
+/// 校验值保留的摘要字节数
+const CHECKSUM_LEN: usize = 8;
+
+/// 续传句柄，记录继续下载所需的全部状态
+///
+/// 格式为 `dlr1.<base64url 编码的内容>.<校验值>`，内容是每行一项的 `key=value` 文本，
+/// 解码后可直接阅读
+pub struct Handle {
+    pub uri: Uri,
+    /// 资源大小
+    pub size: usize,
+    /// 块数
+    pub blocks: usize,
+    /// 块文件所在的临时目录
+    pub temp_dir: PathBuf,
+    /// 输出文件的绝对路径
+    pub file_path: String,
+}
+
+impl Handle {
+    fn content(&self) -> String {
+        format!(
+            "uri={}\nsize={}\nblocks={}\ntemp_dir={}\noutput={}\n",
+            self.uri,
+            self.size,
+            self.blocks,
+            self.temp_dir.display(),
+            self.file_path
+        )
+    }
+}
+
+fn checksum(content: &str) -> String {
+    Sha256::digest(content.as_bytes())[..CHECKSUM_LEN]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+impl Display for Handle {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let content = self.content();
+        write!(
+            f,
+            "{}.{}.{}",
+            PREFIX,
+            URL_SAFE_NO_PAD.encode(&content),
+            checksum(&content)
+        )
+    }
+}
+
+impl FromStr for Handle {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!(Msg::InvalidResumeHandle);
+        let mut parts = s.trim().split('.');
+        let (prefix, content, sum) = match (parts.next(), parts.next(), parts.next(), parts.next())
+        {
+            (Some(prefix), Some(content), Some(sum), None) => (prefix, content, sum),
+            _ => return Err(invalid()),
+        };
+        if prefix != PREFIX {
+            return Err(invalid());
+        }
+        let content = URL_SAFE_NO_PAD
+            .decode(content)
+            .ok()
+            .and_then(|t| String::from_utf8(t).ok())
+            .ok_or_else(invalid)?;
+        if checksum(&content) != sum {
+            return Err(invalid());
+        }
+        let mut fields = content.lines().filter_map(|t| t.split_once('='));
+        let mut field = |key: &str| match fields.next() {
+            Some((k, v)) if k == key => Ok(v.to_string()),
+            _ => Err(invalid()),
+        };
+        Ok(Self {
+            uri: field("uri")?.parse()?,
+            size: field("size")?.parse()?,
+            blocks: field("blocks")?.parse()?,
+            temp_dir: PathBuf::from(field("temp_dir")?),
+            file_path: field("output")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn handle_round_trips_and_rejects_tampering() {
+        let handle = Handle {
+            uri: "https://example.com/a.bin?x=1".parse().unwrap(),
+            size: 1000003,
+            blocks: 4,
+            temp_dir: PathBuf::from("/tmp/download-1234"),
+            file_path: "/home/user/a.bin".to_string(),
+        };
+        let text = handle.to_string();
+        assert!(text.starts_with("dlr1."), "{}", text);
+        let parsed: Handle = text.parse().unwrap();
+        assert_eq!(parsed.to_string(), text);
+        assert_eq!(parsed.uri, handle.uri);
+        assert_eq!((parsed.size, parsed.blocks), (1000003, 4));
+        assert_eq!(parsed.temp_dir, handle.temp_dir);
+        assert_eq!(parsed.file_path, handle.file_path);
+
+        let (head, sum) = text.rsplit_once('.').unwrap();
+        // 替换内容而沿用原来的校验值
+        let tampered = Handle {
+            blocks: 8,
+            ..parsed
+        }
+        .to_string();
+        let content = tampered.split('.').nth(1).unwrap();
+        for s in [
+            String::new(),
+            format!("dlr2{}", &text[4..]),
+            format!("{}.{}", head, "0".repeat(sum.len())),
+            format!("dlr1.{}.{}", content, sum),
+            format!("{}.extra", text),
+        ] {
+            assert!(s.parse::<Handle>().is_err(), "{:?}", s);
+        }
+    }
+}
@@ -0,0 +1,281 @@
+//! 下载历史
+//!
+//! 启用 `history` 功能时，每次下载结束后将地址、保存路径、大小、耗时、校验过的摘要及结果写入本地的 SQLite
+//! 数据库，`download history` 按结果或关键字列出
+
+use std::env;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::Ordering;
+#[cfg(feature = "history")]
+use std::time::UNIX_EPOCH;
+use std::time::{Duration, Instant, SystemTime};
+
+#[cfg(not(feature = "history"))]
+use anyhow::anyhow;
+use anyhow::Error;
+use hyper::Uri;
+#[cfg(feature = "history")]
+use indicatif::HumanBytes;
+#[cfg(feature = "history")]
+use serde_json::json;
+#[cfg(feature = "history")]
+use time::{OffsetDateTime, UtcOffset};
+
+use crate::http::{absolute, log, Job};
+use crate::interrupt;
+use crate::message::Msg;
+use crate::session::session;
+use crate::util::home;
+use crate::Result;
+
+/// 一次下载的记录
+#[cfg_attr(not(feature = "history"), allow(dead_code))]
+pub struct Entry {
+    /// 开始下载的时间
+    pub started: SystemTime,
+    pub url: String,
+    pub path: String,
+    pub size: usize,
+    pub duration: Duration,
+    /// 校验通过的摘要，如 `sha256:<hex>`
+    pub checksum: Option<String>,
+    /// `STATUSES` 之一
+    pub status: String,
+    pub error: Option<String>,
+}
+
+/// 下载的结果，`download history --status` 按此筛选
+pub const STATUSES: [&str; 3] = ["complete", "failed", "interrupted"];
+
+/// 默认的数据库路径：`$XDG_DATA_HOME/download/history.sqlite3`，未设置时位于 `~/.local/share` 下
+pub fn default_path() -> PathBuf {
+    let data = match env::var_os("XDG_DATA_HOME") {
+        Some(t) => PathBuf::from(t),
+        None => home().join(".local").join("share"),
+    };
+    data.join("download").join("history.sqlite3")
+}
+
+#[cfg(feature = "history")]
+fn open(path: &Path) -> Result<rusqlite::Connection> {
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    let connection = rusqlite::Connection::open(path)?;
+    // 多个进程同时写入时等待锁
+    connection.busy_timeout(Duration::from_secs(5))?;
+    connection.execute_batch(
+        "CREATE TABLE IF NOT EXISTS downloads (
+            id INTEGER PRIMARY KEY,
+            started INTEGER NOT NULL,
+            url TEXT NOT NULL,
+            path TEXT NOT NULL,
+            size INTEGER NOT NULL,
+            duration REAL NOT NULL,
+            checksum TEXT,
+            status TEXT NOT NULL,
+            error TEXT
+        )",
+    )?;
+    Ok(connection)
+}
+
+/// 写入一条记录
+#[cfg(feature = "history")]
+pub fn record(path: &Path, entry: &Entry) -> Result {
+    let started = entry
+        .started
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs() as i64;
+    open(path)?.execute(
+        "INSERT INTO downloads (started, url, path, size, duration, checksum, status, error)
+        VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+        rusqlite::params![
+            started,
+            entry.url,
+            entry.path,
+            entry.size as i64,
+            entry.duration.as_secs_f64(),
+            entry.checksum,
+            entry.status,
+            entry.error,
+        ],
+    )?;
+    Ok(())
+}
+
+/// 未启用 `history` 功能时不记录
+#[cfg(not(feature = "history"))]
+pub fn record(_path: &Path, _entry: &Entry) -> Result {
+    Ok(())
+}
+
+/// 将下载的结果写入下载历史，`--dry-run`、`--smoke-test` 及 `--no-history` 时不记录；写入失败不影响下载
+pub fn record_download(
+    job: &Job,
+    uri: &Uri,
+    file_path: &str,
+    start: Instant,
+    error: Option<&Error>,
+) {
+    let session = session();
+    let path = match &session.config.history_file {
+        Some(t) if !session.config.dry_run && !session.config.smoke_test => t,
+        _ => return,
+    };
+    let status = match error {
+        None => "complete",
+        Some(_) if interrupt::interrupted() => "interrupted",
+        Some(_) => "failed",
+    };
+    let entry = Entry {
+        started: SystemTime::now() - start.elapsed(),
+        url: uri.to_string(),
+        path: absolute(file_path),
+        size: job.resource_size.load(Ordering::Relaxed),
+        duration: start.elapsed(),
+        checksum: job.checksum.get().cloned(),
+        status: status.to_string(),
+        error: error.map(|e| format!("{:#}", e)),
+    };
+    if let Err(e) = record(path, &entry) {
+        log(Msg::HistoryFailed(format!("{:#}", e)).to_string());
+    }
+}
+
+/// 从新到旧输出至多 `limit` 条记录，`status` 筛选结果，`search` 筛选地址或路径中含有该文本的记录；`json` 时每行
+/// 输出一个 JSON 对象
+#[cfg(feature = "history")]
+pub fn list(
+    path: &Path,
+    status: Option<&str>,
+    search: Option<&str>,
+    limit: usize,
+    json: bool,
+) -> Result {
+    let connection = open(path)?;
+    let mut statement = connection.prepare(
+        "SELECT started, url, path, size, duration, checksum, status, error FROM downloads
+        WHERE (?1 IS NULL OR status = ?1)
+            AND (?2 IS NULL OR instr(url, ?2) > 0 OR instr(path, ?2) > 0)
+        ORDER BY id DESC LIMIT ?3",
+    )?;
+    let rows = statement.query_map(rusqlite::params![status, search, limit as i64], |row| {
+        Ok(Entry {
+            started: UNIX_EPOCH + Duration::from_secs(row.get::<_, i64>(0)?.max(0) as u64),
+            url: row.get(1)?,
+            path: row.get(2)?,
+            size: row.get::<_, i64>(3)? as usize,
+            duration: Duration::from_secs_f64(row.get::<_, f64>(4)?.max(0.0)),
+            checksum: row.get(5)?,
+            status: row.get(6)?,
+            error: row.get(7)?,
+        })
+    })?;
+    for row in rows {
+        let entry = row?;
+        let started = format_time(entry.started);
+        if json {
+            println!(
+                "{}",
+                json!({
+                    "started": started,
+                    "url": entry.url,
+                    "path": entry.path,
+                    "size": entry.size,
+                    "duration": entry.duration.as_secs_f64(),
+                    "checksum": entry.checksum,
+                    "status": entry.status,
+                    "error": entry.error,
+                })
+            );
+            continue;
+        }
+        println!(
+            "{}  {:<11}  {:>11}  {:>8.1}s  {} -> {}",
+            started,
+            entry.status,
+            HumanBytes(entry.size as u64).to_string(),
+            entry.duration.as_secs_f64(),
+            entry.url,
+            entry.path
+        );
+        if let Some(checksum) = &entry.checksum {
+            println!("    {}", checksum);
+        }
+        if let Some(error) = &entry.error {
+            println!("    {}", error);
+        }
+    }
+    Ok(())
+}
+
+#[cfg(not(feature = "history"))]
+pub fn list(
+    _path: &Path,
+    _status: Option<&str>,
+    _search: Option<&str>,
+    _limit: usize,
+    _json: bool,
+) -> Result {
+    Err(anyhow!(Msg::HistoryUnsupported))
+}
+
+/// 以当地时间输出 `YYYY-MM-DD HH:MM:SS`，无法获取时区时使用 UTC
+#[cfg(feature = "history")]
+fn format_time(time: SystemTime) -> String {
+    let utc = OffsetDateTime::from(time);
+    let t = utc.to_offset(UtcOffset::local_offset_at(utc).unwrap_or(UtcOffset::UTC));
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+        t.year(),
+        t.month() as u8,
+        t.day(),
+        t.hour(),
+        t.minute(),
+        t.second()
+    )
+}
+
+#[cfg(all(test, feature = "history"))]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn record_appends_entries() {
+        let path = env::temp_dir().join(format!("download-history-{}.sqlite3", std::process::id()));
+        for (url, status, error) in [
+            ("http://example.com/a.bin", "complete", None),
+            (
+                "http://example.com/b.bin",
+                "failed",
+                Some("timed out".to_string()),
+            ),
+        ] {
+            let entry = Entry {
+                started: SystemTime::now(),
+                url: url.to_string(),
+                path: "/tmp/a.bin".to_string(),
+                size: 100,
+                duration: Duration::from_millis(1500),
+                checksum: None,
+                status: status.to_string(),
+                error,
+            };
+            record(&path, &entry).unwrap();
+        }
+        let rows: Vec<(String, String, Option<String>)> = open(&path)
+            .unwrap()
+            .prepare("SELECT url, status, error FROM downloads ORDER BY id")
+            .unwrap()
+            .query_map([], |t| Ok((t.get(0)?, t.get(1)?, t.get(2)?)))
+            .unwrap()
+            .collect::<rusqlite::Result<_>>()
+            .unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[0].1, "complete");
+        assert_eq!(rows[1].2.as_deref(), Some("timed out"));
+    }
+}
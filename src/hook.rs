@@ -0,0 +1,100 @@
+//! 下载结束后执行的命令
+//!
+//! `--on-complete` 在下载成功后执行，`--on-error` 在失败后执行。命令通过 shell 运行，下载的信息以环境变量传入；
+//! 命令的标准输出重定向到标准错误，标准输出仍只有 `--print-path` 的路径及 JSON 事件
+
+use std::io::stderr;
+use std::process::Stdio;
+use std::sync::atomic::Ordering;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use hyper::Uri;
+use tokio::process::Command;
+
+use crate::http::{absolute, log, Job};
+use crate::message::Msg;
+use crate::session::session;
+use crate::Result;
+
+/// 以环境变量 `env` 执行 `command`，等待其退出，退出状态非 0 时返回错误
+pub async fn run(command: &str, env: &[(&str, String)]) -> Result {
+    let mut shell = if cfg!(windows) {
+        let mut shell = Command::new("cmd");
+        shell.arg("/C");
+        shell
+    } else {
+        let mut shell = Command::new("sh");
+        shell.arg("-c");
+        shell
+    };
+    let status = shell
+        .arg(command)
+        .envs(env.iter().map(|(key, value)| (key, value)))
+        .stdin(Stdio::null())
+        .stdout(Stdio::from(stderr()))
+        .status()
+        .await?;
+    if !status.success() {
+        // 被信号终止时没有退出码
+        let code = status
+            .code()
+            .map_or_else(|| status.to_string(), |t| t.to_string());
+        return Err(anyhow!(Msg::HookExited(code)));
+    }
+    Ok(())
+}
+
+/// 执行 `--on-complete` 或 `--on-error`
+pub async fn after_download(
+    job: &Job,
+    uri: &Uri,
+    file_path: &str,
+    elapsed: Duration,
+    error: Option<&Error>,
+) {
+    let session = session();
+    let command = match error {
+        None => &session.config.on_complete,
+        Some(_) => &session.config.on_error,
+    };
+    let command = match command {
+        Some(t) => t,
+        None => return,
+    };
+    let mut env = vec![
+        ("DOWNLOAD_PATH", absolute(file_path)),
+        ("DOWNLOAD_URL", uri.to_string()),
+        (
+            "DOWNLOAD_SIZE",
+            job.resource_size.load(Ordering::Relaxed).to_string(),
+        ),
+        ("DOWNLOAD_DURATION", format!("{:.3}", elapsed.as_secs_f64())),
+    ];
+    if let Some(e) = error {
+        env.push(("DOWNLOAD_ERROR", format!("{:#}", e)));
+    }
+    if let Err(e) = run(command, &env).await {
+        log(Msg::HookFailed {
+            command: command.clone(),
+            error: format!("{:#}", e),
+        }
+        .to_string());
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn run_passes_env_and_reports_exit_code() {
+        let env = [("DOWNLOAD_SIZE", "42".to_string())];
+        assert!(run(r#"test "$DOWNLOAD_SIZE" = 42"#, &env).await.is_ok());
+        let error = run("exit 3", &env).await.unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(Msg::HookExited(code)) if code == "3"
+        ));
+    }
+}
@@ -1,187 +1,3558 @@
-use std::time::Instant;
-
-use anyhow::anyhow;
-use hyper::body::HttpBody;
-use hyper::client::HttpConnector;
-use hyper::header::{ACCEPT_RANGES, CONTENT_LENGTH};
-use hyper::{Body, Client, Method, Request, Response};
-use hyper_tls::HttpsConnector;
-use indicatif::{MultiProgress, ProgressBar, ProgressStyle};
+use std::collections::HashMap;
+use std::future::{poll_fn, Future};
+use std::io::ErrorKind;
+use std::path::{Path, PathBuf};
+use std::pin::Pin;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex, OnceLock};
+use std::task::Poll;
+use std::time::{Duration, Instant, SystemTime};
+
+use anyhow::{anyhow, Context, Error};
+use fs4::tokio::AsyncFileExt;
+use hyper::body::{to_bytes, Bytes, HttpBody};
+use hyper::header::{
+    HeaderMap, ACCEPT_RANGES, AUTHORIZATION, CONTENT_DISPOSITION, CONTENT_LENGTH, CONTENT_RANGE,
+    CONTENT_TYPE, ETAG, HOST, IF_RANGE, LAST_MODIFIED, LOCATION, PROXY_AUTHORIZATION, RANGE,
+    RETRY_AFTER,
+};
+use hyper::http::request::Builder;
+use hyper::{Body, Method, Request, Response, StatusCode, Uri};
+use indicatif::{HumanBytes, MultiProgress, ProgressBar, ProgressDrawTarget, ProgressStyle};
 use lazy_static::lazy_static;
-use tokio::fs::{create_dir, remove_dir_all, File, OpenOptions};
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use serde_json::{json, Value};
+use tokio::fs::{
+    create_dir, metadata, read, remove_dir_all, remove_file, rename, File, OpenOptions,
+};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
 use tokio::spawn;
+use tokio::sync::{OnceCell, OwnedSemaphorePermit, Semaphore};
 use tokio::task::JoinHandle;
+use tokio::task_local;
+use tokio::time::{sleep, timeout, timeout_at};
+use tracing::{debug, info, info_span, warn, Instrument};
 
-use crate::config::Config;
+use crate::archive::{self, Format};
+use crate::candidate::{self, Candidate};
+use crate::checksum::{self, hash_file, Checksum};
+use crate::chunker::{self, Chunker};
+use crate::config::{Action, Config, Fsync, OutputTarget, Progress, RangeMismatch, Stats};
+use crate::daemon;
+use crate::desktop;
+use crate::filename;
+use crate::ftp;
+use crate::handle::Handle;
+use crate::history;
+use crate::hook;
+use crate::interrupt;
+use crate::limit::{self, Bucket, LowSpeed};
+use crate::logging;
+use crate::message::Msg;
+use crate::metrics;
+use crate::mime;
+use crate::multipart;
+use crate::pause;
+use crate::piece;
+use crate::prefix;
+use crate::progress;
+use crate::scheduler::{self, acquire_connection};
+use crate::session::{session, Session, SESSION};
+use crate::sftp;
+use crate::sidecar::{self, Autosave, Sidecar};
+use crate::signature::Verifier;
+use crate::smoke;
+use crate::transport::HttpClient;
+use crate::tui::{self, Chunk};
+use crate::webdav;
 use crate::Result;
 
 lazy_static! {
-    static ref CONFIG: Config = Config::get().unwrap();
     static ref PROGRESS: MultiProgress = MultiProgress::new();
+}
+
+/// 多个镜像时每个连接对应的块数，块越多越能让较快的镜像多下载
+const BLOCKS_PER_MIRROR_CONNECTION: usize = 4;
+/// 镜像连续失败多少次后在本次运行中不再使用
+const MIRROR_MAX_ERRORS: usize = 5;
+
+/// 绘制进度条时显示在最上方的总进度条
+static TOTAL_BAR: OnceLock<ProgressBar> = OnceLock::new();
+/// 进度条没有新数据时的刷新间隔，停滞的连接的速度随之下降
+const BAR_TICK: Duration = Duration::from_secs(1);
+/// 通知 `--notify-webhook` 的超时
+const WEBHOOK_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// 下载单个资源的状态，批量下载时每个资源各有一份
+pub(crate) struct Job {
+    /// 块文件所在的临时文件目录
+    pub(crate) temp_dir: PathBuf,
+    /// 资源大小，用于检查 `Content-Range` 中的总大小
+    pub(crate) resource_size: AtomicUsize,
+    /// `--allow-partial` 时放弃下载的范围 `[start, end)`
+    missing: Mutex<Vec<(usize, usize)>>,
+    /// 是否从已有的 `.part` 文件续传，失败时需保留该文件
+    resumed_part: AtomicBool,
+    /// 直接写入输出文件时记录的下载进度
+    pub(crate) sidecar: OnceLock<Sidecar>,
+    /// 直接写入的输出文件，各块共用一个句柄，首次请求时打开
+    output: OnceCell<Arc<std::fs::File>>,
+    /// 可分段下载的镜像
+    mirrors: OnceLock<Vec<Mirror>>,
+    /// 多个镜像时各块共用的连接，按排队顺序领取
+    slots: OnceLock<Arc<Semaphore>>,
+    /// 输出文件路径，用于标识进度事件
+    pub(crate) file: OnceLock<String>,
+    /// 按任务号记录的块，重新请求剩余部分时沿用
+    pub(crate) chunks: Mutex<HashMap<usize, Arc<Chunk>>>,
+    /// 各块的进度条，用于守护进程查询已下载的字节数
+    pub(crate) bars: Mutex<Vec<ProgressBar>>,
+    /// 守护进程中暂停或移除了该下载，之后创建的块沿用该状态
+    pub(crate) paused: AtomicBool,
+    pub(crate) removed: AtomicBool,
+    /// 守护进程中的下载正在排队等待下载名额
+    pub(crate) queued: AtomicBool,
+    /// 校验通过的摘要，记录到下载历史
+    pub(crate) checksum: OnceLock<String>,
+    /// `--auto-checksum` 找到或探测时响应头声明的完整资源摘要，优先于 trailer 中的摘要
+    declared: OnceLock<Checksum>,
+    /// 已写入的字节数，续传时不含已有的部分
+    downloaded: AtomicUsize,
+    /// `Downloader` 设置的进度回调
+    progress: OnceLock<ProgressCallback>,
+}
+
+/// 进度回调，参数为已下载的字节数及资源大小
+pub(crate) type ProgressCallback = Box<dyn Fn(u64, u64) + Send + Sync>;
+
+/// 镜像及其使用情况
+struct Mirror {
+    uri: Uri,
+    /// 正在从中下载的块数
+    active: AtomicUsize,
+    /// 所有块合计的连续失败次数，成功一次即清零
+    errors: AtomicUsize,
+    blacklisted: AtomicBool,
+    /// `--benchmark-mirrors` 测得的速度（字节/秒），未测速时为 1
+    speed: f64,
+}
+
+impl Mirror {
+    fn new(uri: Uri) -> Self {
+        Self {
+            uri,
+            speed: 1.0,
+            active: AtomicUsize::new(0),
+            errors: AtomicUsize::new(0),
+            blacklisted: AtomicBool::new(false),
+        }
+    }
+
+    fn usable(&self) -> bool {
+        !self.blacklisted.load(Ordering::Relaxed)
+    }
+}
+
+/// 块占用的连接及镜像，丢弃时归还
+struct MirrorSlot {
+    _permit: OwnedSemaphorePermit,
+    job: Arc<Job>,
+    index: usize,
+}
+
+impl Drop for MirrorSlot {
+    fn drop(&mut self) {
+        if let Some(mirrors) = self.job.mirrors.get() {
+            mirrors[self.index].active.fetch_sub(1, Ordering::Relaxed);
+        }
+    }
+}
+
+impl Job {
+    pub(crate) fn new(temp_dir: PathBuf) -> Arc<Self> {
+        Arc::new(Self {
+            temp_dir,
+            resource_size: AtomicUsize::new(0),
+            missing: Mutex::new(Vec::new()),
+            resumed_part: AtomicBool::new(false),
+            sidecar: OnceLock::new(),
+            output: OnceCell::new(),
+            mirrors: OnceLock::new(),
+            slots: OnceLock::new(),
+            file: OnceLock::new(),
+            chunks: Mutex::new(HashMap::new()),
+            bars: Mutex::new(Vec::new()),
+            paused: AtomicBool::new(false),
+            removed: AtomicBool::new(false),
+            queued: AtomicBool::new(false),
+            checksum: OnceLock::new(),
+            declared: OnceLock::new(),
+            downloaded: AtomicUsize::new(0),
+            progress: OnceLock::new(),
+        })
+    }
+
+    /// 直接写入的输出文件 `path` 的句柄，各块共用，不必每次请求都重新打开
+    async fn output_file(&self, path: &Path) -> Result<Arc<std::fs::File>> {
+        let file = self.output.get_or_try_init(|| async {
+            let file = OpenOptions::new().write(true).open(path).await?;
+            Ok::<_, Error>(Arc::new(file.into_std().await))
+        });
+        Ok(Arc::clone(file.await?))
+    }
+
+    fn chunk(&self, task: usize) -> Arc<Chunk> {
+        let mut chunks = self.chunks.lock().unwrap();
+        chunks
+            .entry(task)
+            .or_insert_with(|| {
+                let chunk = Chunk::default();
+                chunk.set_paused(self.paused.load(Ordering::Relaxed));
+                if self.removed.load(Ordering::Relaxed) {
+                    chunk.abort();
+                }
+                Arc::new(chunk)
+            })
+            .clone()
+    }
+
+    fn has_mirrors(&self) -> bool {
+        self.mirrors.get().is_some_and(|t| t.len() > 1)
+    }
+
+    /// 领取一个连接并选择镜像，没有多个镜像时使用 `uri`
+    ///
+    /// `current` 仍可用时继续使用，否则在未列入黑名单且不在 `avoid` 中的镜像里选择正在下载的块数相对速度最少的一个
+    async fn acquire_mirror(
+        self: &Arc<Self>,
+        uri: &Uri,
+        current: Option<usize>,
+        avoid: &[usize],
+    ) -> Result<(Uri, Option<MirrorSlot>)> {
+        let (mirrors, slots) = match (self.mirrors.get(), self.slots.get()) {
+            (Some(mirrors), Some(slots)) if mirrors.len() > 1 => (mirrors, slots),
+            _ => return Ok((uri.clone(), None)),
+        };
+        let permit = slots.clone().acquire_owned().await?;
+        let mut candidates: Vec<usize> = (0..mirrors.len())
+            .filter(|&i| mirrors[i].usable() && !avoid.contains(&i))
+            .collect();
+        // 可选的镜像都已避开时不再避开
+        if candidates.is_empty() {
+            candidates = (0..mirrors.len())
+                .filter(|&i| mirrors[i].usable())
+                .collect();
+        }
+        let index = match current {
+            Some(i) if candidates.contains(&i) => i,
+            _ => candidates
+                .into_iter()
+                .min_by(|&a, &b| {
+                    let load = |i: usize| {
+                        (mirrors[i].active.load(Ordering::Relaxed) + 1) as f64 / mirrors[i].speed
+                    };
+                    load(a).total_cmp(&load(b))
+                })
+                .unwrap_or(0),
+        };
+        mirrors[index].active.fetch_add(1, Ordering::Relaxed);
+        let slot = MirrorSlot {
+            _permit: permit,
+            job: self.clone(),
+            index,
+        };
+        Ok((mirrors[index].uri.clone(), Some(slot)))
+    }
+
+    /// 记录镜像上一次请求的结果，连续失败过多时列入黑名单，但至少保留一个镜像
+    fn report_mirror(&self, index: usize, ok: bool) {
+        let mirrors = match self.mirrors.get() {
+            Some(t) => t,
+            None => return,
+        };
+        let mirror = &mirrors[index];
+        if ok {
+            mirror.errors.store(0, Ordering::Relaxed);
+            return;
+        }
+        let errors = mirror.errors.fetch_add(1, Ordering::Relaxed) + 1;
+        let others = mirrors.iter().filter(|t| t.usable()).count() > 1;
+        if errors >= MIRROR_MAX_ERRORS
+            && others
+            && !mirror.blacklisted.swap(true, Ordering::Relaxed)
+        {
+            log(Msg::MirrorBlacklisted {
+                uri: mirror.uri.to_string(),
+                errors,
+            }
+            .to_string());
+        }
+    }
+
+    /// 是否还有未列入黑名单且不在 `avoid` 中的镜像
+    fn other_mirror(&self, avoid: &[usize]) -> bool {
+        self.mirrors.get().is_some_and(|mirrors| {
+            (0..mirrors.len()).any(|i| mirrors[i].usable() && !avoid.contains(&i))
+        })
+    }
+}
+
+task_local! {
+    /// 当前任务所属的下载，启动块下载任务时需一并传入
+    static JOB: Arc<Job>;
+    /// 当前任务下载的块，读取响应体时检查是否在仪表盘中暂停
+    static CHUNK: Arc<Chunk>;
+}
+
+pub(crate) fn job() -> Arc<Job> {
+    JOB.with(Arc::clone)
+}
+
+/// 在后台运行属于 `job` 的任务，新任务沿用当前的会话
+pub(crate) fn spawn_job<F>(job: Arc<Job>, future: F) -> JoinHandle<F::Output>
+where
+    F: Future + Send + 'static,
+    F::Output: Send + 'static,
+{
+    spawn(SESSION.scope(session(), JOB.scope(job, future)))
+}
+
+/// 以 `config` 单独下载一个资源，供 `Downloader` 使用；`progress` 随写入的字节数调用
+///
+/// 不启动限速、指标及进度显示等进程级的服务，也不输出统计
+pub(crate) async fn run_download(config: Config, progress: Option<ProgressCallback>) -> Result {
+    // `Config::download` 只生成下载操作
+    let (size, uri, output) = match &config.action {
+        Action::Download { size, uri, output } => (*size, uri.clone(), output.clone()),
+        _ => unreachable!(),
+    };
+    let job = Job::new(config.temp_file_dir.clone());
+    if let Some(progress) = progress {
+        let _ = job.progress.set(progress);
+    }
+    let task = transfer(job, size, uri, output);
+    SESSION.scope(Session::new(config)?, task).await?;
+    Ok(())
+}
+
+/// 在 `job` 中下载 `uri`，返回的任务须在会话中运行
+pub(crate) fn transfer(
+    job: Arc<Job>,
+    size: usize,
+    uri: Uri,
+    output: OutputTarget,
+) -> impl Future<Output = Result<String>> {
+    JOB.scope(job.clone(), transfer_job(job, size, uri, output))
+}
+
+/// 排队等待下载名额后以 `size` 个连接下载 `uri`，返回输出文件路径；失败时与批量下载一样清理或保留续传记录
+async fn transfer_job(
+    job: Arc<Job>,
+    size: usize,
+    uri: Uri,
+    output: OutputTarget,
+) -> Result<String> {
+    let _download = interrupt::guard(scheduler::acquire_download()).await?;
+    job.queued.store(false, Ordering::Relaxed);
+    // 排队期间被移除的下载不再开始
+    if job.removed.load(Ordering::Relaxed) {
+        return Err(anyhow!(Msg::Aborted));
+    }
+    let mut file_path = match &output {
+        OutputTarget::Path(t) => t.clone(),
+        OutputTarget::Infer(_) => String::new(),
+    };
+    let started = Instant::now();
+    // `--max-time` 的截止时间从运行开始时算起，不适用于守护进程之后添加的下载
+    let result = download(size, &uri, &output, &mut file_path).await;
+    history::record_download(&job, &uri, &file_path, started, result.as_ref().err());
+    if let Err(e) = result {
+        if !file_path.is_empty() {
+            clean_partial(size, &file_path).await?;
+        }
+        notify(&job, &uri, &file_path, started.elapsed(), Some(&e)).await;
+        return Err(e);
+    }
+    notify(&job, &uri, &file_path, started.elapsed(), None).await;
+    Ok(file_path)
+}
+
+/// 不再绘制进度条
+pub(crate) fn hide_progress() {
+    PROGRESS.set_draw_target(ProgressDrawTarget::hidden());
+}
+
+/// 构建请求，附加自定义请求头
+pub(crate) fn request_builder(method: Method, uri: &Uri) -> Builder {
+    let session = session();
+    let signature = session.authorization(&method, uri);
+    let mut builder = Request::builder().method(method).uri(uri);
+    if let Some(host) = &session.config.host {
+        builder = builder.header(HOST, host);
+    }
+    if let Some(auth) = &session.config.auth {
+        builder = builder.header(AUTHORIZATION, auth.header_value());
+    } else if let Some(netrc) = &session.config.netrc {
+        if let Some(auth) = uri.host().and_then(|t| netrc.auth(t)) {
+            builder = builder.header(AUTHORIZATION, auth.header_value());
+        }
+    }
+    if let Some(headers) = builder.headers_mut() {
+        for (i, (name, value)) in session.config.headers.iter().enumerate() {
+            // 替换同名的默认请求头，重复指定时保留各个值
+            if session.config.headers[..i].iter().any(|(t, _)| t == name) {
+                headers.append(name, value.clone());
+            } else {
+                headers.insert(name, value.clone());
+            }
+        }
+        // S3 的签名、GCS 的令牌及 WebDAV 地址中的凭据替换其他认证方式
+        for (name, value) in signature {
+            headers.insert(name, value);
+        }
+    }
+    builder
+}
 
-    /// HTTPS 客户端
-    static ref CLIENT: Client<HttpsConnector<HttpConnector>> =
-        Client::builder().build(HttpsConnector::new());
+/// 构建请求体为空的请求，Azure 的 Shared Key 签名包含 `Range` 等请求头，在最后计算
+pub(crate) fn build(builder: Builder) -> Result<Request<Body>> {
+    let mut request = builder.body(Body::empty())?;
+    session().azure.sign(&mut request);
+    Ok(request)
 }
 
-fn add_bar(size: u64, message: String, template: &str) -> Result<ProgressBar> {
-    let bar = PROGRESS.add(ProgressBar::new(size));
+/// 创建进度条，下载块的进度条带有 `chunk`，计入仪表盘的总进度与下载速度
+///
+/// `--quiet` 或 `--progress` 不为 `bar` 时不绘制，进度仍记录在进度条中
+fn add_bar(
+    size: u64,
+    message: String,
+    template: &str,
+    chunk: Option<Arc<Chunk>>,
+) -> Result<ProgressBar> {
+    let session = session();
+    let bar = if tui::active() {
+        let bar = ProgressBar::with_draw_target(Some(size), ProgressDrawTarget::hidden());
+        tui::add(&bar, chunk);
+        bar
+    } else if session.config.quiet || session.config.progress != Progress::Bars {
+        ProgressBar::with_draw_target(Some(size), ProgressDrawTarget::hidden())
+    } else {
+        PROGRESS.add(ProgressBar::new(size))
+    };
     bar.set_style(
         ProgressStyle::default_bar()
             .template(template)?
-            .progress_chars("#>-"),
+            .progress_chars(session.config.progress_style.progress_chars()),
     );
     bar.set_message(message);
     Ok(bar)
 }
 
+/// 输出日志，仪表盘运行时写入其日志面板，`--quiet` 时不输出；同时记录到 `--log-file`
+pub(crate) fn log(line: String) {
+    let session = session();
+    info!("{}", line);
+    if session.config.quiet {
+        return;
+    }
+    if tui::active() {
+        tui::log(line);
+    } else {
+        PROGRESS.suspend(|| eprintln!("{}", line));
+    }
+}
+
 /// 下载文件进度条样式
-fn add_download_bar(size: u64, task_index: usize) -> Result<ProgressBar> {
-    add_bar(
+pub(crate) fn add_download_bar(size: u64, task_index: usize) -> Result<ProgressBar> {
+    let session = session();
+    let bar = add_bar(
         size,
-        format!("任务 {} 下载中", task_index),
-        "[{bar:50.cyan/blue}] [{msg}] [{bytes}/{total_bytes}] ({eta})",
-    )
+        Msg::TaskDownloading(task_index).to_string(),
+        session.config.progress_style.download_template(),
+        Some(job().chunk(task_index)),
+    )?;
+    if progress::active() {
+        let file = job().file.get().cloned().unwrap_or_default();
+        progress::add(&bar, file, task_index);
+    }
+    job().bars.lock().unwrap().push(bar.clone());
+    if !bar.is_hidden() {
+        bar.enable_steady_tick(BAR_TICK);
+        update_total_bar()?;
+    }
+    Ok(bar)
+}
+
+/// 在最上方显示所有连接的总进度及总速度，批量下载时随资源增加更新总大小
+fn update_total_bar() -> Result {
+    let session = session();
+    let (downloaded, size) = metrics::downloaded();
+    let bar = match TOTAL_BAR.get() {
+        Some(t) => t,
+        None => {
+            let bar = PROGRESS.insert(0, ProgressBar::new(size));
+            bar.set_style(
+                ProgressStyle::default_bar()
+                    .template(session.config.progress_style.total_template())?,
+            );
+            bar.set_message(Msg::TotalSpeed.to_string());
+            bar.enable_steady_tick(BAR_TICK);
+            TOTAL_BAR.get_or_init(|| bar)
+        }
+    };
+    bar.set_length(size);
+    bar.set_position(downloaded);
+    Ok(())
+}
+
+/// 记录写入的字节数
+pub(crate) fn add_bytes(len: usize) {
+    metrics::add_bytes(len);
+    let job = job();
+    let downloaded = job.downloaded.fetch_add(len, Ordering::Relaxed) + len;
+    if let Some(progress) = job.progress.get() {
+        let size = job.resource_size.load(Ordering::Relaxed);
+        progress(downloaded as u64, size as u64);
+    }
+    if let Some(bar) = TOTAL_BAR.get() {
+        bar.inc(len as u64);
+    }
+}
+
+fn finish_total_bar() {
+    if let Some(bar) = TOTAL_BAR.get() {
+        bar.finish();
+    }
+}
+
+/// `--progress json` 时输出进度事件
+fn emit(event: &str, fields: Value) {
+    let session = session();
+    if session.config.progress == Progress::Json {
+        progress::emit(event, fields);
+    }
 }
 
 /// 合并文件进度条样式
 fn add_merge_bar(size: u64) -> Result<ProgressBar> {
-    add_bar(
+    let session = session();
+    let bar = add_bar(
         size,
-        "合并文件中".into(),
-        "[{bar:50.magenta/cyan}] [{msg}] ({eta})",
-    )
+        Msg::Merging.to_string(),
+        session.config.progress_style.merge_template(),
+        None,
+    )?;
+    if progress::active() {
+        let file = job().file.get().cloned().unwrap_or_default();
+        progress::add_merge(&bar, file);
+    }
+    Ok(bar)
+}
+
+/// 发送请求并跟随重定向，返回最终 URI 与响应
+pub(crate) async fn follow(
+    method: Method,
+    mut uri: Uri,
+    range: Option<&str>,
+) -> Result<(Uri, Response<Body>)> {
+    let session = session();
+    for _ in 0..=session.config.max_redirects {
+        let mut builder = request_builder(method.clone(), &uri);
+        if let Some(range) = range {
+            builder = builder.header(RANGE, range);
+        }
+        let response = interrupt::guard(send(session.clients[0].as_ref(), build(builder)?)).await?;
+        if !response.status().is_redirection() {
+            return Ok((uri, response));
+        }
+        let location = match response.headers().get(LOCATION) {
+            None => return Ok((uri, response)),
+            Some(t) => t.to_str()?,
+        };
+        uri = resolve_location(&uri, location)?;
+        check_redirect_host(&uri)?;
+        if session.config.verbose {
+            log(Msg::Redirecting(uri.to_string()).to_string());
+        }
+    }
+    Err(anyhow!(Msg::TooManyRedirects(session.config.max_redirects)))
+}
+
+/// 发送请求，`--verbose` 时像 `curl -v` 一样输出请求行、请求头及响应的状态行、响应头
+pub(crate) async fn send(
+    client: &dyn HttpClient,
+    request: Request<Body>,
+) -> Result<Response<Body>> {
+    let session = session();
+    if session.config.verbose {
+        let uri = request.uri();
+        let target = uri.path_and_query().map(|t| t.as_str()).unwrap_or("/");
+        let mut lines = vec![format!(
+            "> {} {} {:?}",
+            request.method(),
+            target,
+            request.version()
+        )];
+        // `Host` 由 hyper 根据地址补充
+        if let (false, Some(authority)) = (request.headers().contains_key(HOST), uri.authority()) {
+            lines.push(format!("> host: {}", authority));
+        }
+        lines.extend(dump_headers('>', request.headers()));
+        log(lines.join("\n"));
+    }
+    let response = client.request(request).await?;
+    if session.config.verbose {
+        let mut lines = vec![format!("< {:?} {}", response.version(), response.status())];
+        lines.extend(dump_headers('<', response.headers()));
+        log(lines.join("\n"));
+    }
+    Ok(response)
+}
+
+/// 每行一个头部，不输出认证信息
+fn dump_headers(prefix: char, headers: &HeaderMap) -> impl Iterator<Item = String> + '_ {
+    headers.iter().map(move |(name, value)| {
+        let value = if name == AUTHORIZATION || name == PROXY_AUTHORIZATION {
+            "<redacted>".into()
+        } else {
+            String::from_utf8_lossy(value.as_bytes())
+        };
+        format!("{} {}: {}", prefix, name, value)
+    })
+}
+
+/// 检查重定向目标的主机是否在 `--allowed-hosts` 中，未指定时允许所有主机
+pub(crate) fn check_redirect_host(uri: &Uri) -> Result {
+    let session = session();
+    let host = uri.host().unwrap_or_default();
+    if session.config.allowed_hosts.is_empty()
+        || session
+            .config
+            .allowed_hosts
+            .iter()
+            .any(|t| t.eq_ignore_ascii_case(host))
+    {
+        return Ok(());
+    }
+    Err(anyhow!(Msg::RedirectHostNotAllowed(uri.to_string())))
+}
+
+/// 将 `Location` 解析为绝对 URI
+pub(crate) fn resolve_location(base: &Uri, location: &str) -> Result<Uri> {
+    let location: Uri = location.parse()?;
+    if location.scheme().is_some() {
+        return Ok(location);
+    }
+    let mut parts = location.into_parts();
+    parts.scheme = base.scheme().cloned();
+    parts.authority = base.authority().cloned();
+    Ok(Uri::from_parts(parts)?)
+}
+
+/// 资源探测结果
+struct Probe {
+    /// 跟随重定向后的 URI
+    uri: Uri,
+    content_length: usize,
+    /// 是否支持 range 请求
+    accept_ranges: bool,
+    content_type: Option<String>,
+    /// `Content-Disposition` 中的文件名
+    file_name: Option<String>,
+    /// `ETag` 及 `Last-Modified`
+    validators: (Option<String>, Option<String>),
+    /// 响应头声明的完整资源摘要
+    checksum: Option<Checksum>,
+}
+
+/// 探测资源大小及是否支持 range 请求
+///
+/// HEAD 请求失败、缺少 `Content-Length` 或未声明 `Accept-Ranges` 时，回退到 `Range: bytes=0-0` 的 GET 请求
+async fn probe(uri: &Uri) -> Result<Probe> {
+    let session = session();
+    let webdav = session.is_webdav(uri);
+    let resolved = session.resolve(uri).await?;
+    let uri = &resolved;
+    // 连接器不限制协议，其他协议会被当作 HTTP 请求
+    if let Some(scheme) = uri.scheme_str() {
+        if !matches!(scheme, "http" | "https" | "ftp" | "sftp") {
+            return Err(anyhow!(Msg::SchemeUnsupported(scheme.to_string())));
+        }
+    }
+    if is_file_transfer(uri) {
+        let (content_length, accept_ranges) = if uri.scheme_str() == Some("sftp") {
+            // SFTP 总是可以从任意偏移读取
+            let probe = sftp::probe(
+                uri,
+                session.config.connect_timeout,
+                session.config.ssh_key.as_deref(),
+            );
+            (interrupt::guard(probe).await?, true)
+        } else {
+            interrupt::guard(ftp::probe(uri, session.config.connect_timeout)).await?
+        };
+        return Ok(Probe {
+            uri: uri.clone(),
+            content_length,
+            accept_ranges,
+            content_type: None,
+            file_name: None,
+            validators: (None, None),
+            checksum: None,
+        });
+    }
+    // WebDAV 服务器通常支持 range 请求，忽略时由下载过程中的检查回退到单连接下载
+    if webdav {
+        if let Some(properties) = webdav::propfind(uri).await? {
+            if let Some(content_length) = properties.content_length {
+                return Ok(Probe {
+                    uri: uri.clone(),
+                    content_length,
+                    accept_ranges: true,
+                    content_type: properties.content_type,
+                    file_name: None,
+                    validators: (properties.etag, properties.last_modified),
+                    checksum: None,
+                });
+            }
+        }
+    }
+    let mut uri = uri.clone();
+    match follow(Method::HEAD, uri.clone(), None).await {
+        Ok((final_uri, response)) => {
+            let headers = response.headers();
+            // 未声明 `Accept-Ranges` 的服务器也可能支持 range 请求，由 GET 请求确认
+            if response.status().is_success() && headers.contains_key(ACCEPT_RANGES) {
+                if let Some(t) = headers.get(CONTENT_LENGTH) {
+                    return Ok(Probe {
+                        content_length: t.to_str()?.parse()?,
+                        accept_ranges: accept_ranges(headers)?,
+                        content_type: content_type(headers),
+                        file_name: file_name(headers),
+                        validators: validators(headers),
+                        checksum: declared_checksum(headers, true),
+                        uri: final_uri,
+                    });
+                }
+            }
+            uri = final_uri;
+        }
+        Err(e) if interrupt::interrupted() => return Err(e),
+        // 部分服务器对 HEAD 请求直接断开连接，GET 请求仍然失败时报告其错误
+        Err(e) => {
+            if session.config.verbose {
+                log(Msg::HeadFailed(format!("{:#}", e)).to_string());
+            }
+        }
+    }
+
+    let (uri, response) = follow(Method::GET, uri, Some("bytes=0-0")).await?;
+    let headers = response.headers();
+    match response.status() {
+        StatusCode::PARTIAL_CONTENT => {
+            let content_range = match headers.get(CONTENT_RANGE) {
+                None => return Err(anyhow!(Msg::HeaderMissing(CONTENT_RANGE.to_string()))),
+                Some(t) => t.to_str()?,
+            };
+            match parse_content_range(content_range) {
+                Some((_, _, content_length)) => Ok(Probe {
+                    uri,
+                    content_length,
+                    accept_ranges: true,
+                    content_type: content_type(headers),
+                    file_name: file_name(headers),
+                    validators: validators(headers),
+                    checksum: declared_checksum(headers, false),
+                }),
+                None => Err(anyhow!(Msg::InvalidContentRange(content_range.to_string()))),
+            }
+        }
+        // 服务器忽略了 range 请求，返回完整响应
+        StatusCode::OK => match headers.get(CONTENT_LENGTH) {
+            None => Err(anyhow!(Msg::HeaderMissing(CONTENT_LENGTH.to_string()))),
+            Some(t) => Ok(Probe {
+                content_length: t.to_str()?.parse()?,
+                accept_ranges: false,
+                content_type: content_type(headers),
+                file_name: file_name(headers),
+                validators: validators(headers),
+                checksum: declared_checksum(headers, true),
+                uri,
+            }),
+        },
+        status => Err(anyhow!(Msg::RequestFailed(status.to_string()))),
+    }
+}
+
+/// 429 及 503 响应带有 `Retry-After` 时，返回包含等待时间的错误
+pub(crate) fn check_retry_after(response: &Response<Body>) -> Result {
+    let status = response.status();
+    if status != StatusCode::TOO_MANY_REQUESTS && status != StatusCode::SERVICE_UNAVAILABLE {
+        return Ok(());
+    }
+    let value = match response.headers().get(RETRY_AFTER).map(|t| t.to_str()) {
+        Some(Ok(t)) => t.trim(),
+        _ => return Ok(()),
+    };
+    // 秒数或 HTTP 日期
+    let delay = match value.parse() {
+        Ok(secs) => Duration::from_secs(secs),
+        Err(_) => match httpdate::parse_http_date(value) {
+            Ok(t) => t.duration_since(SystemTime::now()).unwrap_or_default(),
+            Err(_) => return Ok(()),
+        },
+    };
+    Err(anyhow!(Msg::RetryAfter {
+        status: status.to_string(),
+        delay,
+    }))
+}
+
+/// 检查响应头是否声明支持 `bytes` range 请求
+fn accept_ranges(headers: &HeaderMap) -> Result<bool> {
+    Ok(match headers.get(ACCEPT_RANGES) {
+        None => false,
+        Some(t) => t.to_str()? == "bytes",
+    })
+}
+
+fn content_type(headers: &HeaderMap) -> Option<String> {
+    Some(headers.get(CONTENT_TYPE)?.to_str().ok()?.to_string())
+}
+
+fn validators(headers: &HeaderMap) -> (Option<String>, Option<String>) {
+    let text = |name| Some(headers.get(name)?.to_str().ok()?.to_string());
+    (text(ETAG), text(LAST_MODIFIED))
+}
+
+fn file_name(headers: &HeaderMap) -> Option<String> {
+    filename::from_content_disposition(headers.get(CONTENT_DISPOSITION)?.to_str().ok()?)
+}
+
+/// 完整资源的摘要：`Repr-Digest`、`Digest`，以及 `full` 即响应对应完整资源时的 `Content-MD5`
+fn declared_checksum(headers: &HeaderMap, full: bool) -> Option<Checksum> {
+    let header = |name: &str| headers.get(name).and_then(|t| t.to_str().ok());
+    header("repr-digest")
+        .or_else(|| header("digest"))
+        .and_then(Checksum::parse_header)
+        .or_else(|| {
+            header("content-md5")
+                .filter(|_| full)
+                .and_then(Checksum::parse_content_md5)
+        })
+}
+
+/// 解析 `bytes <start>-<end>/<total>` 格式的 `Content-Range`
+pub fn parse_content_range(value: &str) -> Option<(usize, usize, usize)> {
+    let (range, total) = value.strip_prefix("bytes ")?.split_once('/')?;
+    let (start, end) = range.split_once('-')?;
+    Some((start.parse().ok()?, end.parse().ok()?, total.parse().ok()?))
 }
 
 /// 下载文件
-fn download_block(
+pub(crate) fn download_block(
+    uri: Uri,
     index: (usize, usize),
     start: usize,
     block_size: usize,
+    output: Option<PathBuf>,
     bar: ProgressBar,
-) -> JoinHandle<Result> {
-    spawn(async move {
-        let request = Request::builder()
-            .method(Method::GET)
-            .header(
-                "range",
-                format!("bytes={}-{}", start, start + block_size - 1),
-            )
-            .uri(&CONFIG.uri)
-            .body(Body::empty())?;
-        let response = CLIENT.request(request).await?;
-        write_file(response, index.0, &bar).await?;
-        bar.finish_with_message(format!("任务 {} 下载完成", index.1));
-        Ok(())
-    })
+) -> JoinHandle<Result<Option<Checksum>>> {
+    let session = session();
+    let span = info_span!("chunk", task = index.1, start, size = block_size);
+    let chunk = job().chunk(index.1);
+    chunk.set_start(start);
+    let task = async move {
+        let chunk = CHUNK.with(Arc::clone);
+        let _connection = acquire_connection().await?;
+        let _active = metrics::ActiveBlock::new();
+        let started = Instant::now();
+        let mut attempt = 0;
+        // 按 `Retry-After` 已等待的合计时间
+        let mut waited = Duration::ZERO;
+        // 已写入的字节数，重试时从此处继续请求；按 sidecar 续传时从记录的位置开始
+        let mut written = match (&output, job().sidecar.get()) {
+            (Some(_), Some(sidecar)) => sidecar.progress(index.0).load(Ordering::Relaxed),
+            _ => 0,
+        };
+        let resumed = written;
+        // 当前使用的镜像及在其上连续失败的次数，刚换下的镜像，以及重试次数用尽后放弃的镜像
+        let mut mirror = None;
+        let mut failures = 0;
+        let mut switched = None;
+        let mut abandoned = Vec::new();
+        let checksum = loop {
+            let transport = session
+                .config
+                .retry
+                .transport(attempt, session.clients.len());
+            let avoid: Vec<usize> = abandoned.iter().copied().chain(switched).collect();
+            let (uri, slot) = job().acquire_mirror(&uri, mirror, &avoid).await?;
+            debug!(%uri, attempt, transport, offset = start + written, "requesting");
+            let request = request_block(
+                session.clients[transport].as_ref(),
+                &uri,
+                index.0,
+                (start, block_size),
+                output.as_deref(),
+                &mut written,
+                &bar,
+            );
+            let result = chunk
+                .guard(async {
+                    match session.config.retry.timeout(attempt) {
+                        None => request.await,
+                        Some(t) => timeout(t, request)
+                            .await
+                            .unwrap_or_else(|_| Err(anyhow!(Msg::RequestTimeout(t)))),
+                    }
+                })
+                .await;
+            // 等待重试期间不占用连接
+            mirror = slot.as_ref().map(|t| t.index);
+            drop(slot);
+            // 片摘要不一致时与请求失败一样重试，从该片开始重新下载
+            let result = match result {
+                Ok(checksum) => {
+                    let block = (start, block_size);
+                    let pieces = piece::verify(index, block, output.as_deref(), &mut written);
+                    pieces.await.map(|_| checksum)
+                }
+                Err(e) => Err(e),
+            };
+            match result {
+                Ok(checksum) => {
+                    if let Some(i) = mirror {
+                        job().report_mirror(i, true);
+                    }
+                    debug!(attempt, bytes = written, "done");
+                    let file = job().file.get().cloned().unwrap_or_default();
+                    metrics::add_task(file, index.1, written - resumed, started.elapsed());
+                    break checksum;
+                }
+                Err(e) if interrupt::interrupted() => {
+                    bar.abandon_with_message(Msg::TaskInterrupted(index.1).to_string());
+                    return Err(e);
+                }
+                // 在仪表盘中中止的块与重试次数用尽时一样处理
+                Err(e) if tui::aborted(&e) => {
+                    bar.abandon_with_message(Msg::TaskAborted(index.1).to_string());
+                    if session.config.allow_partial {
+                        job()
+                            .missing
+                            .lock()
+                            .unwrap()
+                            .push((start + written, start + block_size));
+                        log(Msg::TaskAbandoned {
+                            task: index.1,
+                            error: format!("{:#}", e),
+                        }
+                        .to_string());
+                        return Ok(None);
+                    }
+                    return Err(anyhow!(Msg::TaskAborted(index.1)));
+                }
+                // 由调用方改为通过单个连接下载
+                Err(e) if ranges_ignored(&e) => {
+                    bar.finish_and_clear();
+                    return Err(e);
+                }
+                Err(e) => {
+                    warn!(%uri, attempt, bytes = written, error = format!("{:#}", e), "failed");
+                    if let Some(delay) = session.config.retry.retry_after(&e, &mut waited) {
+                        bar.set_message(
+                            Msg::TaskWaiting {
+                                task: index.1,
+                                delay,
+                            }
+                            .to_string(),
+                        );
+                        interrupt::sleep(delay).await?;
+                        continue;
+                    }
+                    let used = mirror;
+                    if let Some(i) = used {
+                        job().report_mirror(i, false);
+                        failures += 1;
+                        // 在同一镜像上连续失败 `--switch-after` 次后换用其他镜像
+                        if failures >= session.config.retry.switch_after {
+                            failures = 0;
+                            switched = mirror.take();
+                        }
+                    }
+                    attempt += 1;
+                    let delay = match session.config.retry.backoff(attempt, e) {
+                        Ok(t) => t,
+                        Err(e) => {
+                            // 重试次数用尽后换用其他镜像，重新计算重试次数
+                            abandoned.extend(used);
+                            if used.is_some() && job().other_mirror(&abandoned) {
+                                log(Msg::MirrorFailover {
+                                    task: index.1,
+                                    error: format!("{:#}", e),
+                                }
+                                .to_string());
+                                attempt = 0;
+                                failures = 0;
+                                mirror = None;
+                                switched = None;
+                                continue;
+                            }
+                            // 放弃该块，其余部分留作空洞
+                            if session.config.allow_partial {
+                                job()
+                                    .missing
+                                    .lock()
+                                    .unwrap()
+                                    .push((start + written, start + block_size));
+                                log(Msg::TaskAbandoned {
+                                    task: index.1,
+                                    error: format!("{:#}", e),
+                                }
+                                .to_string());
+                                bar.abandon_with_message(Msg::TaskFailed(index.1).to_string());
+                                return Ok(None);
+                            }
+                            return Err(e);
+                        }
+                    };
+                    metrics::add_retry();
+                    chunk.retried();
+                    let next = session
+                        .config
+                        .retry
+                        .transport(attempt, session.clients.len());
+                    if next != transport && session.config.verbose {
+                        log(Msg::TaskSwitchTransport {
+                            task: index.1,
+                            transport: session.config.transports[next].to_string(),
+                        }
+                        .to_string());
+                    }
+                    bar.set_message(
+                        Msg::TaskRetrying {
+                            task: index.1,
+                            attempt,
+                            attempts: session.config.retry.attempts,
+                        }
+                        .to_string(),
+                    );
+                    debug!(attempt, ?delay, "retrying");
+                    interrupt::sleep(delay).await?;
+                }
+            }
+        };
+        bar.finish_with_message(Msg::TaskDone(index.1).to_string());
+        Ok(checksum)
+    };
+    spawn_job(job(), CHUNK.scope(chunk, task.instrument(span)))
 }
 
-/// 写入文件
-async fn write_file(mut response: Response<Body>, index: usize, bar: &ProgressBar) -> Result {
-    let path_buf = CONFIG.temp_file_dir.join(index.to_string());
-    // 数据流方式读取响应体
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(path_buf)
-        .await?;
-    while let Some(next) = response.data().await {
-        let bytes = next?;
-        bar.inc(bytes.len() as u64);
-        file.write_all(&bytes).await?;
+/// 请求块中尚未下载的部分，返回响应 trailer 中声明的完整资源摘要
+///
+/// 指定 `output` 时按偏移写入各任务共用的输出文件，否则追加到临时文件目录中的块文件
+async fn request_block(
+    client: &dyn HttpClient,
+    uri: &Uri,
+    index: usize,
+    (start, block_size): (usize, usize),
+    output: Option<&Path>,
+    written: &mut usize,
+    bar: &ProgressBar,
+) -> Result<Option<Checksum>> {
+    let (path_buf, mut file, offset) = match output {
+        Some(path) => {
+            let file = job().output_file(path).await?;
+            let offset = (start + *written) as u64;
+            let file = BlockFile::Positional { file, offset };
+            (path.to_path_buf(), file, offset)
+        }
+        None => {
+            let path_buf = job().temp_dir.join(index.to_string());
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&path_buf)
+                .await?;
+            // 以临时文件的长度为准，避免超时打断写入后计数不准确
+            *written = file.metadata().await?.len() as usize;
+            (path_buf, BlockFile::Append(file), *written as u64)
+        }
+    };
+    bar.set_position(*written as u64);
+    if *written >= block_size {
+        return Ok(None);
     }
-    Ok(())
+    let job = job();
+    let sidecar = job.sidecar.get().filter(|_| output.is_some());
+    let progress = sidecar.map(|t| t.progress(index));
+    // 服务器从请求起点之后开始返回时，先按偏移写入返回的部分，再请求其前的空缺；
+    // `later` 记录空缺之后已写入的连续部分的起点及长度
+    let mut later: Option<(usize, usize)> = None;
+    let mut checksum = None;
+    let result = async {
+        loop {
+            let end = later.map_or(start + block_size, |(from, _)| from);
+            let requested = (start + *written, end - 1);
+            let (response, from) = if is_file_transfer(uri) {
+                let body = retrieve(uri, requested.0);
+                (Response::new(interrupt::guard(body).await?), requested.0)
+            } else {
+                request_range(client, uri, requested, sidecar).await?
+            };
+            if from > requested.0 {
+                let (len, t) =
+                    write_later(response, (from, end), start, output, &path_buf, bar).await?;
+                later = match later {
+                    Some((_, rest)) if from + len == end => Some((from, len + rest)),
+                    _ => Some((from, len)),
+                };
+                if let Some(t) = t {
+                    checksum = aggregate_checksum(checksum.take(), t)?;
+                }
+                continue;
+            }
+            // 块文件以追加方式打开，空缺之后已有内容时改为按偏移写入空缺
+            if later.is_some() && output.is_none() {
+                let std_file = OpenOptions::new().write(true).open(&path_buf).await?;
+                let file_offset = *written as u64;
+                file = BlockFile::Positional {
+                    file: Arc::new(std_file.into_std().await),
+                    offset: file_offset,
+                };
+            }
+            let before = *written;
+            let limit = end - requested.0;
+            let skip = requested.0 - from;
+            let trailers =
+                write_file(response, &mut file, written, bar, (skip, limit), progress).await?;
+            let len = (*written - before) as u64;
+            if let Some(t) = check_trailers(trailers, &path_buf, offset, len).await? {
+                checksum = aggregate_checksum(checksum.take(), t)?;
+            }
+            if start + *written == end {
+                if let Some((_, len)) = later.take() {
+                    *written += len;
+                    if let Some(progress) = progress {
+                        progress.store(*written, Ordering::Relaxed);
+                    }
+                }
+            }
+            if *written < block_size {
+                return Err(anyhow!(Msg::ResponseTooShort {
+                    task: index + 1,
+                    written: *written,
+                    expected: block_size,
+                }));
+            }
+            return Ok(checksum.take());
+        }
+    }
+    .await;
+    // 块文件的长度即已写入的字节数，失败时丢弃空缺之后写入的部分
+    if result.is_err() && output.is_none() && later.is_some() {
+        let file = OpenOptions::new().write(true).open(&path_buf).await?;
+        file.set_len(*written as u64).await?;
+    }
+    result
 }
 
-/// 合并文件
-async fn merge_file(size: u64) -> Result {
-    let bar = add_merge_bar(size)?;
-    let mut file = OpenOptions::new()
-        .create(true)
-        .append(true)
-        .open(&CONFIG.file_path)
-        .await?;
-    for i in 0..CONFIG.size {
-        let mut block_file = File::open(CONFIG.temp_file_dir.join(i.to_string())).await?;
-
-        let size = block_file.metadata().await?.len();
-        const BUF_SIZE: u64 = 1024;
-        let count = size / BUF_SIZE;
-        let first_buf_size = size % BUF_SIZE;
-
-        async fn write_block(
-            block_file: &mut File,
-            file: &mut File,
-            bar: &ProgressBar,
-            buffer: &mut [u8],
-        ) -> Result {
-            block_file.read_exact(buffer).await?;
-            bar.inc(buffer.len() as u64);
-            file.write_all(buffer).await?;
-            Ok(())
+/// 将从 `from` 开始返回的响应按偏移写入资源的 `[from, end)` 部分，返回写入的字节数及 trailer 中的完整资源摘要
+///
+/// 不更新已写入的字节数，空缺补齐后由调用方一并计入
+async fn write_later(
+    response: Response<Body>,
+    (from, end): (usize, usize),
+    block_start: usize,
+    output: Option<&Path>,
+    path_buf: &Path,
+    bar: &ProgressBar,
+) -> Result<(usize, Option<Checksum>)> {
+    // 直接写入时按输出文件中的偏移写入，块文件从块的起点开始
+    let (file, base) = match output {
+        Some(path) => (job().output_file(path).await?, 0),
+        None => {
+            let file = OpenOptions::new().write(true).open(path_buf).await?;
+            (Arc::new(file.into_std().await), block_start)
+        }
+    };
+    let offset = (from - base) as u64;
+    let mut file = BlockFile::Positional { file, offset };
+    let mut len = 0;
+    let trailers = write_file(response, &mut file, &mut len, bar, (0, end - from), None).await?;
+    let checksum = check_trailers(trailers, path_buf, offset, len as u64).await?;
+    Ok((len, checksum))
+}
+
+/// 请求资源的 `[from, to]` 部分，返回响应及响应内容在资源中的起点
+async fn request_range(
+    client: &dyn HttpClient,
+    uri: &Uri,
+    requested: (usize, usize),
+    sidecar: Option<&Sidecar>,
+) -> Result<(Response<Body>, usize)> {
+    let session = session();
+    let mut builder = request_builder(Method::GET, uri)
+        .header(RANGE, format!("bytes={}-{}", requested.0, requested.1));
+    let if_range = sidecar.and_then(Sidecar::validator);
+    if let Some(validator) = if_range {
+        builder = builder.header(IF_RANGE, validator);
+    }
+    let request = build(builder)?;
+    let response = interrupt::guard(send(client, request)).await?;
+    check_retry_after(&response)?;
+    // 返回完整内容时，校验值变化说明资源已经变化，否则是服务器忽略了 range 请求，写入块中会损坏文件
+    if response.status() == StatusCode::OK {
+        let validators = validators(response.headers());
+        return match sidecar {
+            Some(t) if if_range.is_some() && !t.same_validators(&validators) => {
+                Err(anyhow!(Msg::ResourceChanged))
+            }
+            _ => Err(anyhow!(Msg::RangeIgnored)),
+        };
+    }
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(anyhow!(Msg::RequestFailed(response.status().to_string())));
+    }
+    let content_range = match response.headers().get(CONTENT_RANGE) {
+        None => return Err(anyhow!(Msg::HeaderMissing(CONTENT_RANGE.to_string()))),
+        Some(t) => t.to_str()?.to_string(),
+    };
+    let actual = parse_content_range(&content_range)
+        .ok_or_else(|| anyhow!(Msg::InvalidContentRange(content_range.clone())))?;
+    let total = job().resource_size.load(Ordering::Relaxed);
+    let start = range_start(requested, total, actual, session.config.range_mismatch)?;
+    Ok((response, start))
+}
+
+/// 不经过 HTTP 的 FTP 及 SFTP
+pub(crate) fn is_file_transfer(uri: &Uri) -> bool {
+    matches!(uri.scheme_str(), Some("ftp" | "sftp"))
+}
+
+/// 通过 FTP 或 SFTP 从 `offset` 开始读取资源，读取到末尾为止
+pub(crate) async fn retrieve(uri: &Uri, offset: usize) -> Result<Body> {
+    let session = session();
+    match uri.scheme_str() {
+        Some("sftp") => {
+            let key = session.config.ssh_key.as_deref();
+            sftp::retrieve(uri, offset, session.config.connect_timeout, key).await
+        }
+        _ => ftp::retrieve(uri, offset, session.config.connect_timeout).await,
+    }
+}
+
+/// 是否因服务器忽略 range 请求而失败
+fn ranges_ignored(error: &Error) -> bool {
+    matches!(error.downcast_ref(), Some(Msg::RangeIgnored))
+}
+
+/// 对照请求的 `[from, to]` 检查响应的 `Content-Range`，返回响应内容在资源中的起点
+///
+/// 宽松模式下信任与请求有重叠的范围：开头多出的部分跳过，末尾多出的部分截断，
+/// 起点晚于请求起点时按该起点写入并由调用方请求其前的空缺；资源总长度不一致说明资源已经变化，仍然报错
+pub(crate) fn range_start(
+    (from, to): (usize, usize),
+    total: usize,
+    (start, end, actual_total): (usize, usize, usize),
+    mode: RangeMismatch,
+) -> Result<usize> {
+    let matches = match mode {
+        RangeMismatch::Strict => (start, end, actual_total) == (from, to, total),
+        RangeMismatch::Lenient => actual_total == total && start <= to && from <= end,
+    };
+    if !matches {
+        return Err(anyhow!(Msg::ContentRangeMismatch {
+            requested: format!("bytes {}-{}/{}", from, to, total),
+            actual: format!("bytes {}-{}/{}", start, end, actual_total),
+        }));
+    }
+    Ok(start)
+}
+
+/// 响应体的写入目标
+enum BlockFile {
+    /// 顺序写入，如块文件或单连接下载的输出文件
+    Append(File),
+    /// 从 `offset` 起按位置写入，不改变共用文件的读写位置，各任务互不影响
+    Positional {
+        file: Arc<std::fs::File>,
+        offset: u64,
+    },
+}
+
+impl BlockFile {
+    async fn write_all(&mut self, bytes: Bytes) -> Result {
+        match self {
+            Self::Append(file) => file.write_all(&bytes).await?,
+            Self::Positional { file, offset } => {
+                let (file, at) = (Arc::clone(file), *offset);
+                let len = bytes.len() as u64;
+                tokio::task::spawn_blocking(move || write_all_at(&file, &bytes, at)).await??;
+                *offset += len;
+            }
+        }
+        Ok(())
+    }
+
+    /// 按位置写入时每次写入返回前已交给操作系统，无需额外处理
+    async fn flush(&mut self) -> Result {
+        if let Self::Append(file) = self {
+            file.flush().await?;
+        }
+        Ok(())
+    }
+
+    async fn sync_all(&mut self) -> Result {
+        match self {
+            Self::Append(file) => file.sync_all().await?,
+            Self::Positional { file, .. } => {
+                let file = Arc::clone(file);
+                tokio::task::spawn_blocking(move || file.sync_all()).await??;
+            }
         }
+        Ok(())
+    }
+}
+
+#[cfg(unix)]
+fn write_all_at(file: &std::fs::File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    use std::os::unix::fs::FileExt;
+    file.write_all_at(buf, offset)
+}
+
+/// 其他平台不支持按位置写入，在锁内移动读写位置后写入，避免各任务的写入交错
+#[cfg(not(any(unix, windows)))]
+fn write_all_at(file: &std::fs::File, buf: &[u8], offset: u64) -> std::io::Result<()> {
+    use std::io::{Seek, Write};
 
-        // 第一个块获取 `余数` 个字节
-        let mut buffer = vec![0; first_buf_size as usize];
-        write_block(&mut block_file, &mut file, &bar, &mut buffer).await?;
+    static LOCK: Mutex<()> = Mutex::new(());
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let mut file = file;
+    file.seek(SeekFrom::Start(offset))?;
+    file.write_all(buf)
+}
 
-        // 剩余块获取 `BUF_SIZE` 个字节
-        let mut buffer = [0; BUF_SIZE as usize];
-        for _ in 0..count {
-            write_block(&mut block_file, &mut file, &bar, &mut buffer).await?;
+#[cfg(windows)]
+fn write_all_at(file: &std::fs::File, mut buf: &[u8], mut offset: u64) -> std::io::Result<()> {
+    use std::os::windows::fs::FileExt;
+    while !buf.is_empty() {
+        match file.seek_write(buf, offset)? {
+            0 => return Err(ErrorKind::WriteZero.into()),
+            n => {
+                buf = &buf[n..];
+                offset += n as u64;
+            }
         }
     }
-    bar.finish_with_message("合并文件完成");
-    // 删除临时文件目录
-    remove_dir_all(&CONFIG.temp_file_dir).await?;
     Ok(())
 }
 
-pub async fn run() -> Result {
-    let start = Instant::now();
-    let request = Request::builder()
-        .method(Method::HEAD)
-        .uri(&CONFIG.uri)
-        .body(Body::empty())?;
-    let response = CLIENT.request(request).await?;
-    let headers = response.headers();
-    let content_length = match headers.get(CONTENT_LENGTH) {
-        None => return Err(anyhow!("{CONTENT_LENGTH} 为空")),
-        Some(t) => t.to_str()?.parse::<usize>()?,
+/// 跳过响应体开头的 `skip` 个字节，最多写入 `limit` 个字节，返回响应体之后的 trailer
+///
+/// 指定 `progress` 时定期写完已收到的数据，再记录已写入的字节数
+async fn write_file(
+    mut response: Response<Body>,
+    file: &mut BlockFile,
+    written: &mut usize,
+    bar: &ProgressBar,
+    (mut skip, mut limit): (usize, usize),
+    progress: Option<&AtomicUsize>,
+) -> Result<Option<HeaderMap>> {
+    let session = session();
+    let mut synced = Instant::now();
+    let mut recorded = Instant::now();
+    let record = |written: usize| {
+        if let Some(progress) = progress {
+            progress.store(written, Ordering::Relaxed);
+        }
     };
-    match headers.get(ACCEPT_RANGES) {
-        None => return Err(anyhow!("不支持 {ACCEPT_RANGES} 请求")),
-        Some(t) => {
-            if t.to_str()? != "bytes" {
-                return Err(anyhow!("不支持 {ACCEPT_RANGES} 请求"));
+    // 每个响应独占一个连接，各用一个令牌桶
+    let bucket = session.config.limit_rate_per_conn.map(Bucket::new);
+    let mut low_speed = low_speed();
+    // 数据流方式读取响应体
+    while let Some(next) = next_data(&mut response, low_speed.as_mut()).await {
+        // 出错时先写完已收到的数据
+        let mut bytes = match next {
+            Ok(t) => t,
+            Err(e) => {
+                file.flush().await?;
+                record(*written);
+                return Err(e);
             }
+        };
+        let n = skip.min(bytes.len());
+        skip -= n;
+        let mut bytes = bytes.split_off(n);
+        // 服务器返回的范围超出请求时丢弃其余部分
+        let truncated = bytes.len() > limit;
+        bytes.truncate(limit);
+        let len = bytes.len();
+        limit -= len;
+        limit::take(len, bucket.as_ref()).await;
+        bar.inc(len as u64);
+        file.write_all(bytes).await?;
+        *written += len;
+        add_bytes(len);
+        if let Fsync::Periodic(interval) = session.config.fsync {
+            if synced.elapsed() >= interval {
+                file.sync_all().await?;
+                synced = Instant::now();
+            }
+        }
+        if progress.is_some() && recorded.elapsed() >= sidecar::SAVE_INTERVAL {
+            file.flush().await?;
+            record(*written);
+            recorded = Instant::now();
         }
+        let chunk = CHUNK.try_with(Arc::clone).ok();
+        pause::wait(std::slice::from_ref(bar), chunk.as_deref()).await;
+        if truncated {
+            file.flush().await?;
+            record(*written);
+            return Ok(None);
+        }
+    }
+    file.flush().await?;
+    record(*written);
+    Ok(response.trailers().await?)
+}
+
+/// 指定 `--speed-limit` 时，每个响应各自检测速度
+pub(crate) fn low_speed() -> Option<LowSpeed> {
+    let session = session();
+    session
+        .config
+        .speed_limit
+        .map(|(limit, time)| LowSpeed::new(limit, time))
+}
+
+/// 读取响应体的下一段，超过 `--read-timeout` 没有收到数据或速度过低时失败
+pub(crate) async fn next_data(
+    response: &mut Response<Body>,
+    low_speed: Option<&mut LowSpeed>,
+) -> Option<Result<Bytes>> {
+    let low_speed = match low_speed {
+        None => return read_data(response).await,
+        Some(t) => t,
     };
-    create_dir(&CONFIG.temp_file_dir).await?;
+    loop {
+        // 一直没有数据时也需在周期结束时检查
+        let checked = match timeout(low_speed.remaining(), read_data(response)).await {
+            Ok(Some(Ok(bytes))) => match low_speed.update(bytes.len()) {
+                Ok(()) => return Some(Ok(bytes)),
+                Err(e) => Err(e),
+            },
+            Ok(next) => return next,
+            Err(_) => low_speed.update(0),
+        };
+        if let Err(e) = checked {
+            return Some(Err(e));
+        }
+    }
+}
 
-    // 单个任务下载的数据大小
-    let block_size = content_length / CONFIG.size;
+/// 等待响应体的下一段，按下 Ctrl+C 时返回错误
+async fn read_data(response: &mut Response<Body>) -> Option<Result<Bytes>> {
+    let session = session();
+    let read = async {
+        let next = match session.config.read_timeout {
+            None => response.data().await,
+            Some(t) => match timeout(t, response.data()).await {
+                Ok(next) => next,
+                Err(_) => return Some(Err(anyhow!(Msg::ReadTimeout(t)))),
+            },
+        };
+        next.map(|t| Ok(t?))
+    };
+    match interrupt::guard(async { Ok(read.await) }).await {
+        Ok(next) => next,
+        Err(e) => Some(Err(e)),
+    }
+}
 
-    // 第一个块获取 `block_size + 余数` 个字节
-    let first_attach = content_length % CONFIG.size;
-    let first_block_size = block_size + first_attach;
-    let first_bar = add_download_bar(first_block_size as u64, 1)?;
-    let mut handles = vec![download_block((0, 1), 0, first_block_size, first_bar)];
+/// 校验 trailer 中本次响应内容的摘要，返回完整资源的摘要
+///
+/// hyper 在 HTTP/1.1 下会丢弃 trailer，仅 HTTP/2 响应可以获取
+async fn check_trailers(
+    trailers: Option<HeaderMap>,
+    path_buf: &Path,
+    offset: u64,
+    len: u64,
+) -> Result<Option<Checksum>> {
+    let session = session();
+    let trailers = match trailers {
+        None => return Ok(None),
+        Some(t) => t,
+    };
+    if session.config.verbose {
+        for (name, value) in &trailers {
+            log(format!(
+                "< {}: {}",
+                name,
+                value.to_str().unwrap_or_default()
+            ));
+        }
+    }
+    let header = |name: &str| trailers.get(name).and_then(|t| t.to_str().ok());
 
-    let block_size_u64 = block_size as u64;
-    // 剩余块获取 `block_size` 个字节
-    for i in 1..CONFIG.size {
-        let task_index = i + 1;
-        let bar = add_download_bar(block_size_u64, task_index)?;
-        let start = i * block_size + first_attach;
-        handles.push(download_block((i, task_index), start, block_size, bar));
+    // `Content-Digest` 是本次响应内容（即块中写入部分）的摘要
+    if let Some(checksum) = header("content-digest").and_then(Checksum::parse_header) {
+        let actual = hash_file(path_buf, offset, len, checksum.hasher()).await?;
+        checksum.verify(&actual)?;
     }
-    // 等待所有任务结束
-    for handle in handles {
-        handle.await??;
+    // `Repr-Digest`、`Digest` 与 `Content-MD5` 是完整资源的摘要，合并后校验
+    Ok(header("repr-digest")
+        .or_else(|| header("digest"))
+        .and_then(Checksum::parse_header)
+        .or_else(|| header("content-md5").and_then(Checksum::parse_content_md5)))
+}
+
+/// 汇总各任务 trailer 中的完整资源摘要，保留最强的算法
+fn aggregate_checksum(current: Option<Checksum>, next: Checksum) -> Result<Option<Checksum>> {
+    match current {
+        Some(t) if t.algorithm == next.algorithm && t.value != next.value => Err(anyhow!(
+            Msg::TrailerChecksumConflict(t.to_string(), next.to_string())
+        )),
+        Some(t) if t.algorithm >= next.algorithm => Ok(Some(t)),
+        _ => Ok(Some(next)),
+    }
+}
+
+/// 合并文件，指定 `checksum` 或 `--checksum` 时在合并过程中计算并校验摘要
+async fn merge_file(
+    size: u64,
+    blocks: usize,
+    file_path: &str,
+    checksum: Option<&Checksum>,
+) -> Result {
+    let session = session();
+    let sizes: Vec<usize> = plan_blocks(0, size as usize, blocks)
+        .into_iter()
+        .map(|(_, block_size)| block_size)
+        .collect();
+    check_block_files(&sizes).await?;
+    emit("merge", json!({ "file": file_path, "size": size }));
+    let started = Instant::now();
+    let bar = add_merge_bar(size)?;
+    let declared = job().declared.get().cloned();
+    let checksum = session
+        .config
+        .checksum
+        .as_ref()
+        .or(declared.as_ref())
+        .or(checksum);
+    let mut hasher = checksum.map(Checksum::hasher);
+    let mut chunker = session
+        .config
+        .chunks
+        .as_ref()
+        .map(|(_, size)| Chunker::new(*size));
+    // 先合并到 `.part` 文件，完成后再重命名
+    merge_blocks(blocks, Path::new(&part_path(file_path)), |data| {
+        bar.inc(data.len() as u64);
+        if let Some(hasher) = &mut hasher {
+            hasher.update(data);
+        }
+        if let Some(chunker) = &mut chunker {
+            chunker.update(data);
+        }
+    })
+    .await?;
+    if let (Some(checksum), Some(hasher)) = (checksum, hasher) {
+        checksum.verify(&hasher.finalize())?;
+        let _ = job().checksum.set(checksum.to_string());
+    }
+    if let (Some((path, _)), Some(chunker)) = (&session.config.chunks, chunker) {
+        chunker::write_chunks(path, &chunker.finish()).await?;
+    }
+    bar.finish_with_message(Msg::MergeDone.to_string());
+    metrics::add_merge_time(started.elapsed());
+    finish_file(part_path(file_path), file_path).await
+}
+
+/// 写入前确认临时文件目录中的块文件齐全且大小依次为 `sizes`，避免产生合并了一半的输出文件
+pub(crate) async fn check_block_files(sizes: &[usize]) -> Result {
+    for (i, &block_size) in sizes.iter().enumerate() {
+        let path_buf = job().temp_dir.join(i.to_string());
+        let len = match metadata(&path_buf).await {
+            Ok(t) => t.len(),
+            Err(_) => return Err(anyhow!(Msg::BlockMissing(path_buf.display().to_string()))),
+        };
+        if len != block_size as u64 {
+            return Err(anyhow!(Msg::BlockSizeWrong {
+                index: i,
+                size: len,
+                expected: block_size as u64,
+            }));
+        }
+    }
+    Ok(())
+}
+
+/// 将临时文件目录中的前 `blocks` 个块文件依次写入 `path`，每读到一段数据调用一次 `on_data`
+pub(crate) async fn merge_blocks(
+    blocks: usize,
+    path: &Path,
+    mut on_data: impl FnMut(&[u8]),
+) -> Result {
+    let session = session();
+    let mut file = OpenOptions::new()
+        .create(true)
+        .write(true)
+        .truncate(true)
+        .open(path)
+        .await?;
+    // 所有块共用同一个缓冲区，内存占用与文件大小及块数无关
+    let mut buffer = vec![0; session.config.merge_buffer];
+    for i in 0..blocks {
+        let mut block_file = File::open(job().temp_dir.join(i.to_string())).await?;
+        copy_block(&mut block_file, &mut file, &mut buffer, &mut on_data).await?;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// 经由 `buffer` 将块文件流式写入输出文件，每读到一段数据调用一次 `on_data`
+async fn copy_block(
+    from: &mut (impl AsyncRead + Unpin),
+    to: &mut (impl AsyncWrite + Unpin),
+    buffer: &mut [u8],
+    mut on_data: impl FnMut(&[u8]),
+) -> Result<u64> {
+    let mut copied = 0;
+    loop {
+        let n = from.read(buffer).await?;
+        if n == 0 {
+            return Ok(copied);
+        }
+        on_data(&buffer[..n]);
+        to.write_all(&buffer[..n]).await?;
+        copied += n as u64;
+    }
+}
+
+/// 将下载完成的文件重命名为输出文件
+pub(crate) async fn finish_file(from: impl AsRef<Path>, file_path: &str) -> Result {
+    let session = session();
+    if let Some(min_size) = session.config.min_size {
+        let size = metadata(&from).await?.len();
+        if size < min_size {
+            return Err(anyhow!(Msg::FileTooSmall { size, min_size }));
+        }
+    }
+    if session.config.fsync != Fsync::Never {
+        OpenOptions::new()
+            .write(true)
+            .open(&from)
+            .await?
+            .sync_all()
+            .await?;
+    }
+    rename(from, file_path).await?;
+    if let Some(mode) = session.config.chmod {
+        chmod(file_path, mode).await?;
+    }
+    Ok(())
+}
+
+/// 设置输出文件权限
+#[cfg(unix)]
+async fn chmod(file_path: &str, mode: u32) -> Result {
+    use std::fs::Permissions;
+    use std::os::unix::fs::PermissionsExt;
+
+    use tokio::fs::set_permissions;
+
+    set_permissions(file_path, Permissions::from_mode(mode)).await?;
+    Ok(())
+}
+
+/// 设置输出文件权限
+#[cfg(not(unix))]
+async fn chmod(_file_path: &str, _mode: u32) -> Result {
+    eprintln!("{}", Msg::ChmodUnsupported);
+    Ok(())
+}
+
+/// 检查块文件是否齐全且大小合理，返回合并后的文件大小
+///
+/// 除第一个块额外包含余数外，其余块的大小应当相同
+async fn check_blocks(blocks: usize) -> Result<u64> {
+    let mut sizes = Vec::with_capacity(blocks);
+    for i in 0..blocks {
+        let path_buf = job().temp_dir.join(i.to_string());
+        match metadata(&path_buf).await {
+            Ok(t) => sizes.push(t.len()),
+            Err(_) => return Err(anyhow!(Msg::BlockMissing(path_buf.display().to_string()))),
+        }
+    }
+    if job().temp_dir.join(blocks.to_string()).exists() {
+        return Err(anyhow!(Msg::TooManyBlocks(blocks)));
+    }
+    if let Some(&block_size) = sizes.get(1) {
+        for (i, &size) in sizes.iter().enumerate().skip(1) {
+            if size != block_size {
+                return Err(anyhow!(Msg::BlockSizeMismatch {
+                    index: i,
+                    size,
+                    expected: block_size,
+                }));
+            }
+        }
+        if sizes[0] < block_size || sizes[0] - block_size >= blocks as u64 {
+            return Err(anyhow!(Msg::BlockSizeInvalid(sizes[0])));
+        }
+    }
+    Ok(sizes.iter().sum())
+}
+
+/// 合并中的输出文件路径
+fn part_path(file_path: &str) -> String {
+    format!("{}.part", file_path)
+}
+
+/// 各块直接写入的预分配输出文件路径
+///
+/// 预分配的文件长度与已下载的字节数无关，不能与 `.part` 文件同名，否则会被当作单流下载续传
+fn prealloc_path(file_path: &str) -> String {
+    format!("{}.prealloc", file_path)
+}
+
+/// 下载失败时清理临时文件，指定 `--keep-partial` 时保留并输出恢复提示
+async fn clean_partial(size: usize, file_path: &str) -> Result {
+    let session = session();
+    // 续传的文件由用户提供，始终保留，同时保留记录的进度
+    if let Some(partial) = &session.config.resume_from {
+        if let Some(sidecar) = job().sidecar.get() {
+            sidecar.save().await?;
+            eprintln!("{}", Msg::KeptSidecar(sidecar.path().display().to_string()));
+        }
+        eprintln!("{}", Msg::KeptResumeFile(partial.display().to_string()));
+        return Ok(());
+    }
+    let part_path = part_path(file_path);
+    let keep_partial = session.config.keep_partial
+        || session.config.continue_download
+        || session.config.print_resume_handle
+        || session.config.resume_handle.is_some();
+    let prealloc_path = prealloc_path(file_path);
+    let job = job();
+    let sidecar = job.sidecar.get();
+    if let Some(sidecar) = sidecar.filter(|_| keep_partial || interrupt::interrupted()) {
+        sidecar.save().await?;
+        eprintln!("{}", Msg::KeptSidecar(sidecar.path().display().to_string()));
+    }
+    // 没有 sidecar 的直接写入输出文件未记录各块的进度，中断后无法续传；`.part` 及块文件保留
+    if interrupt::interrupted() && !keep_partial {
+        let mut kept = vec![&part_path];
+        if sidecar.is_some() {
+            kept.push(&prealloc_path);
+        } else if Path::new(&prealloc_path).exists() {
+            remove_file(&prealloc_path).await?;
+            eprintln!("{}", Msg::ContinueHint);
+        }
+        keep_files(size, file_path, &kept);
+        return Ok(());
+    }
+    if job.resumed_part.load(Ordering::Relaxed) {
+        if let Some(sidecar) = sidecar.filter(|_| !keep_partial) {
+            sidecar.save().await?;
+            eprintln!("{}", Msg::KeptSidecar(sidecar.path().display().to_string()));
+        }
+        eprintln!("{}", Msg::KeptPartFile(part_path));
+        return Ok(());
+    }
+    if keep_partial {
+        keep_files(size, file_path, &[&part_path, &prealloc_path]);
+        return Ok(());
+    }
+    if job.temp_dir.exists() {
+        remove_dir_all(&job.temp_dir).await?;
+    }
+    for path in [&part_path, &prealloc_path] {
+        if Path::new(path).exists() {
+            remove_file(path).await?;
+        }
+    }
+    if let Some(sidecar) = sidecar {
+        sidecar.remove().await?;
+    }
+    Ok(())
+}
+
+/// 输出保留的部分文件及临时文件目录，以及合并块文件的命令
+fn keep_files(size: usize, file_path: &str, paths: &[&String]) {
+    for path in paths {
+        if Path::new(path).exists() {
+            eprintln!("{}", Msg::KeptPartFile(path.to_string()));
+        }
+    }
+    if job().temp_dir.exists() {
+        let temp_dir = job().temp_dir.display().to_string();
+        eprintln!("{}", Msg::KeptTempDir(temp_dir.clone()));
+        eprintln!(
+            "{}",
+            Msg::MergeHint {
+                temp_dir,
+                output: file_path.to_string(),
+                blocks: size,
+            }
+        );
+    }
+}
+
+pub async fn run() -> Result {
+    let config = Config::get()?;
+    logging::init(config.log_level, config.log_file.as_deref())?;
+    SESSION.scope(Session::new(config)?, run_action()).await
+}
+
+/// 执行命令行参数指定的操作
+async fn run_action() -> Result {
+    let session = session();
+    match &session.config.action {
+        Action::Size { uri, human } => with_deadline(print_size(uri, *human)).await,
+        Action::Daemon {
+            addr,
+            secret,
+            dir,
+            split,
+            web_ui,
+        } => {
+            start_services()?;
+            hide_progress();
+            let (secret, dir) = (secret.clone(), dir.clone());
+            daemon::serve(session.clone(), *addr, secret, dir, *split, *web_ui).await
+        }
+        Action::History {
+            status,
+            search,
+            limit,
+            json,
+        } => {
+            let path = session
+                .config
+                .history_file
+                .clone()
+                .unwrap_or_else(history::default_path);
+            history::list(&path, status.as_deref(), search.as_deref(), *limit, *json)
+        }
+        Action::Merge { blocks, file_path } => {
+            let job = Job::new(session.config.temp_file_dir.clone());
+            JOB.scope(job, async {
+                let size = check_blocks(*blocks).await?;
+                merge_file(size, *blocks, file_path, None).await
+            })
+            .await?;
+            print_path(file_path)
+        }
+        Action::Download { size, uri, output } => {
+            // 先取得签名并读取公钥，签名无法获取或公钥有误时不必下载
+            let verifier = match &session.config.signature {
+                Some((location, key)) if !session.config.dry_run => {
+                    let signature = fetch_signature(location)
+                        .await
+                        .with_context(|| Msg::SignatureFetchFailed(location.clone()))?;
+                    Some(Verifier::load(signature, location, key).await?)
+                }
+                _ => None,
+            };
+            start_services()?;
+            wait_for_start().await?;
+            let start = Instant::now();
+            let job = Job::new(session.config.temp_file_dir.clone());
+            let mut file_path = match output {
+                OutputTarget::Path(t) => t.clone(),
+                OutputTarget::Infer(_) => String::new(),
+            };
+            let result = JOB
+                .scope(
+                    job.clone(),
+                    with_deadline(download(*size, uri, output, &mut file_path)),
+                )
+                .await;
+            tui::stop()?;
+            progress::stop();
+            finish_total_bar();
+            if let Err(e) = result {
+                emit_error(&file_path, &e);
+                history::record_download(&job, uri, &file_path, start, Some(&e));
+                print_interrupted();
+                // 推断出文件名之前失败时尚未创建任何文件
+                if !file_path.is_empty() {
+                    JOB.scope(job.clone(), clean_partial(*size, &file_path))
+                        .await?;
+                }
+                notify(&job, uri, &file_path, start.elapsed(), Some(&e)).await;
+                return Err(e);
+            }
+            if session.config.smoke_test || session.config.dry_run {
+                return Ok(());
+            }
+            if let Some(verifier) = verifier {
+                if let Err(e) = verifier.verify(Path::new(&file_path)).await {
+                    remove_file(&file_path).await?;
+                    emit_error(&file_path, &e);
+                    history::record_download(&job, uri, &file_path, start, Some(&e));
+                    notify(&job, uri, &file_path, start.elapsed(), Some(&e)).await;
+                    return Err(e);
+                }
+                log(Msg::SignatureVerified.to_string());
+            }
+            if session.config.extract {
+                if let Err(e) = extract_archive(&file_path).await {
+                    emit_error(&file_path, &e);
+                    history::record_download(&job, uri, &file_path, start, Some(&e));
+                    notify(&job, uri, &file_path, start.elapsed(), Some(&e)).await;
+                    return Err(e);
+                }
+            }
+            history::record_download(&job, uri, &file_path, start, None);
+            emit_complete(&file_path, start);
+            print_elapsed(start);
+            print_path(&file_path)?;
+            notify(&job, uri, &file_path, start.elapsed(), None).await;
+            Ok(())
+        }
+        Action::Batch { size, targets } => {
+            // 未指定 `--max-connections-total` 时所有资源共用 `<size>` 个连接
+            scheduler::init(
+                session.config.max_concurrent_downloads,
+                Some(session.config.max_connections_total.unwrap_or(*size)),
+            );
+            start_services()?;
+            wait_for_start().await?;
+            let start = Instant::now();
+            let handles: Vec<_> = targets
+                .iter()
+                .map(|target| {
+                    let job = Job::new(target.temp_file_dir.clone());
+                    let (size, uri) = (*size, target.uri.clone());
+                    let file_path = target.file_path.clone();
+                    let session = session.clone();
+                    spawn_job(job.clone(), async move {
+                        let output = OutputTarget::Path(file_path.clone());
+                        let mut file_path = file_path;
+                        // 从领取到下载名额时算起
+                        let mut started = Instant::now();
+                        let result = with_deadline(async {
+                            let _download = interrupt::guard(scheduler::acquire_download()).await?;
+                            started = Instant::now();
+                            download(size, &uri, &output, &mut file_path).await
+                        })
+                        .await;
+                        // 解压失败时保留已下载的压缩包
+                        let result = match result {
+                            Ok(()) if session.config.extract && !session.config.dry_run => {
+                                extract_archive(&file_path).await
+                            }
+                            t => t,
+                        };
+                        history::record_download(
+                            &job,
+                            &uri,
+                            &file_path,
+                            started,
+                            result.as_ref().err(),
+                        );
+                        (job, file_path, result, started.elapsed())
+                    })
+                })
+                .collect();
+            let mut results = Vec::with_capacity(handles.len());
+            for handle in handles {
+                results.push(handle.await?);
+            }
+            tui::stop()?;
+            progress::stop();
+            finish_total_bar();
+            print_interrupted();
+            let mut failed = 0;
+            for (job, file_path, result, _) in &results {
+                if let Err(e) = result {
+                    failed += 1;
+                    emit_error(file_path, e);
+                    eprintln!(
+                        "{}",
+                        Msg::BatchItemFailed {
+                            file_path: file_path.clone(),
+                            error: format!("{:#}", e),
+                        }
+                    );
+                    JOB.scope(job.clone(), clean_partial(*size, file_path))
+                        .await?;
+                }
+            }
+            // `--dry-run` 时没有下载任何文件
+            for (_, file_path, result, _) in &results {
+                if result.is_ok() && !session.config.dry_run {
+                    emit_complete(file_path, start);
+                }
+            }
+            print_elapsed(start);
+            for (_, file_path, result, _) in &results {
+                if result.is_ok() && !session.config.dry_run {
+                    print_path(file_path)?;
+                }
+            }
+            for ((job, file_path, result, elapsed), target) in results.iter().zip(targets) {
+                notify(job, &target.uri, file_path, *elapsed, result.as_ref().err()).await;
+            }
+            if failed > 0 {
+                return Err(anyhow!(Msg::BatchFailed {
+                    failed,
+                    total: results.len(),
+                }));
+            }
+            Ok(())
+        }
+    }
+}
+
+/// 启动指标服务、仪表盘及暂停监听
+fn start_services() -> Result {
+    let session = session();
+    interrupt::watch();
+    scheduler::init(
+        session.config.max_concurrent_downloads,
+        session.config.max_connections_total,
+    );
+    match (&session.config.limit_schedule, session.config.limit_rate) {
+        (Some(schedule), rate) => limit::start_schedule(schedule.clone(), rate),
+        (None, Some(rate)) => limit::set_global(rate),
+        (None, None) => {}
+    }
+    if let Some(addr) = session.config.metrics_addr {
+        metrics::serve(addr)?;
+    }
+    metrics::sample_peak();
+    // 指定 `--progress` 时不启动仪表盘
+    if session.config.progress != Progress::Bars {
+        progress::start(session.config.progress);
+    } else if session.config.tui && !session.config.quiet {
+        tui::start()?;
+    }
+    pause::watch(session.config.pause_file.clone())
+}
+
+/// `--start-at` 时等待到开始时间，绘制进度条时每秒更新倒计时；`--dry-run` 时不等待。按下 Ctrl+C 时
+/// 停止仪表盘等并返回错误，此时尚未创建任何文件
+async fn wait_for_start() -> Result {
+    let session = session();
+    let start = match session.config.start_at {
+        Some(t) if !session.config.dry_run => t,
+        _ => return Ok(()),
+    };
+    let countdown = || {
+        // 向上取整，到点时恰好为 0
+        let remaining = (start.saturating_duration_since(Instant::now())
+            + Duration::from_millis(999))
+        .as_secs();
+        let text = format!(
+            "{:02}:{:02}:{:02}",
+            remaining / 3600,
+            remaining / 60 % 60,
+            remaining % 60
+        );
+        Msg::StartCountdown(text).to_string()
+    };
+    let bar = if session.config.quiet || session.config.progress != Progress::Bars || tui::active()
+    {
+        log(countdown());
+        None
+    } else {
+        let style = ProgressStyle::default_spinner().template("{msg}")?;
+        Some(PROGRESS.add(ProgressBar::new_spinner().with_style(style)))
+    };
+    let result = loop {
+        let remaining = start.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            break Ok(());
+        }
+        if let Some(bar) = &bar {
+            bar.set_message(countdown());
+        }
+        // 对齐到整秒，倒计时逐秒递减
+        let tick = Duration::from_nanos(remaining.subsec_nanos() as u64);
+        let tick = if tick.is_zero() {
+            Duration::from_secs(1)
+        } else {
+            tick
+        };
+        if let Err(e) = interrupt::sleep(tick).await {
+            break Err(e);
+        }
+    };
+    if let Some(bar) = bar {
+        bar.finish_and_clear();
+    }
+    if result.is_err() {
+        tui::stop()?;
+        progress::stop();
+    }
+    result
+}
+
+/// 按下 Ctrl+C 后输出已下载的字节数
+fn print_interrupted() {
+    if interrupt::interrupted() {
+        let (downloaded, total) = metrics::downloaded();
+        eprintln!(
+            "{}",
+            Msg::InterruptSummary {
+                downloaded: HumanBytes(downloaded).to_string(),
+                total: HumanBytes(total).to_string(),
+            }
+        );
+    }
+}
+
+/// 输出下载完成的事件，包含文件的绝对路径及耗时
+fn emit_complete(file_path: &str, start: Instant) {
+    emit(
+        "complete",
+        json!({
+            "file": absolute(file_path),
+            "elapsed": start.elapsed().as_secs_f64(),
+        }),
+    );
+}
+
+/// 将下载完成的压缩包解压到 `--extract-dir`，无法识别格式的文件只提示不解压
+async fn extract_archive(file_path: &str) -> Result {
+    let session = session();
+    let path = Path::new(file_path);
+    let format = match Format::detect(path) {
+        Some(t) => t,
+        None => {
+            log(Msg::NotArchive(file_path.to_string()).to_string());
+            return Ok(());
+        }
+    };
+    let dir = match &session.config.extract_dir {
+        Some(t) => t.clone(),
+        None => match path.parent() {
+            Some(t) if !t.as_os_str().is_empty() => t.to_path_buf(),
+            _ => PathBuf::from("."),
+        },
+    };
+    let bar = add_bar(
+        metadata(path).await?.len(),
+        Msg::Extracting.to_string(),
+        session.config.progress_style.merge_template(),
+        None,
+    )?;
+    archive::extract(path, format, &dir, bar.clone()).await?;
+    bar.finish_with_message(Msg::ExtractDone.to_string());
+    log(Msg::Extracted(dir.display().to_string()).to_string());
+    Ok(())
+}
+
+/// 下载结束后执行 `--on-complete` 或 `--on-error`，通知 `--notify-webhook` 并按 `--notify` 发送桌面通知，
+/// `--dry-run` 及 `--smoke-test` 时不执行；失败时只输出警告，不影响下载的结果
+async fn notify(job: &Job, uri: &Uri, file_path: &str, elapsed: Duration, error: Option<&Error>) {
+    let session = session();
+    if session.config.dry_run || session.config.smoke_test {
+        return;
+    }
+    hook::after_download(job, uri, file_path, elapsed, error).await;
+    let file = absolute(file_path);
+    let size = job.resource_size.load(Ordering::Relaxed);
+    let text = match error {
+        None => Msg::NotifyComplete {
+            file: file.clone(),
+            size: HumanBytes(size as u64).to_string(),
+            elapsed: elapsed.as_secs_f64(),
+        },
+        Some(e) => Msg::NotifyFailed {
+            file: file.clone(),
+            error: format!("{:#}", e),
+        },
+    }
+    .to_string();
+    if session.config.notify {
+        // 标题为文件名，正文与 webhook 的 `text` 相同
+        let name = Path::new(&file)
+            .file_name()
+            .map(|t| t.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        if let Err(e) = desktop::send(name, text.clone()).await {
+            log(Msg::DesktopNotifyFailed(format!("{:#}", e)).to_string());
+        }
+    }
+    if let Some(webhook) = &session.config.notify_webhook {
+        // `text` 供 Slack、Matrix 等只显示文本的服务使用
+        let payload = json!({
+            "event": if error.is_none() { "complete" } else { "error" },
+            "file": file,
+            "url": uri.to_string(),
+            "size": size,
+            "duration": elapsed.as_secs_f64(),
+            "checksum": job.checksum.get(),
+            "error": error.map(|e| format!("{:#}", e)),
+            "text": text,
+        });
+        if let Err(e) = post_webhook(webhook, &payload).await {
+            log(Msg::WebhookFailed(format!("{:#}", e)).to_string());
+        }
+    }
+}
+
+/// 向 `uri` POST JSON，不附加下载用的请求头及认证信息
+async fn post_webhook(uri: &Uri, payload: &Value) -> Result {
+    let session = session();
+    let request = Request::builder()
+        .method(Method::POST)
+        .uri(uri)
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(payload.to_string()))?;
+    let response = timeout(WEBHOOK_TIMEOUT, send(session.clients[0].as_ref(), request))
+        .await
+        .map_err(|_| anyhow!(Msg::RequestTimeout(WEBHOOK_TIMEOUT)))??;
+    if !response.status().is_success() {
+        return Err(anyhow!(Msg::RequestFailed(response.status().to_string())));
+    }
+    Ok(())
+}
+
+/// 文件的绝对路径，文件不存在时原样返回
+pub(crate) fn absolute(file_path: &str) -> String {
+    std::fs::canonicalize(file_path)
+        .unwrap_or_else(|_| PathBuf::from(file_path))
+        .display()
+        .to_string()
+}
+
+fn emit_error(file_path: &str, error: &Error) {
+    emit(
+        "error",
+        json!({ "file": file_path, "error": format!("{:#}", error) }),
+    );
+}
+
+/// 输出耗时及统计，`--print-path` 时标准输出只保留文件路径，`--progress json` 时作为 `stats` 事件输出，`--quiet` 时不输出
+fn print_elapsed(start: Instant) {
+    let session = session();
+    let elapsed = start.elapsed();
+    if session.config.quiet {
+        return;
+    }
+    if session.config.progress == Progress::Json {
+        emit("stats", metrics::stats(elapsed));
+        return;
+    }
+    let text = match session.config.stats {
+        Stats::Json => metrics::stats(elapsed).to_string(),
+        Stats::Text => format!("{}\n{}", Msg::Elapsed(elapsed), metrics::summary(elapsed)),
+    };
+    if session.config.print_path {
+        eprintln!("{}", text);
+    } else {
+        println!("{}", text);
+    }
+}
+
+/// 指定 `--print-path` 时输出文件的绝对路径
+fn print_path(file_path: &str) -> Result {
+    let session = session();
+    // 路径已包含在 `complete` 事件中
+    if session.config.print_path && session.config.progress != Progress::Json {
+        println!("{}", std::fs::canonicalize(file_path)?.display());
+    }
+    Ok(())
+}
+
+/// 在全局截止时间内执行
+async fn with_deadline(future: impl Future<Output = Result>) -> Result {
+    let session = session();
+    match session.config.retry.deadline {
+        None => future.await,
+        Some(deadline) => timeout_at(deadline.into(), future)
+            .await
+            .map_err(|_| anyhow!(Msg::DeadlineExceeded))?,
+    }
+}
+
+/// 输出资源大小
+async fn print_size(uri: &Uri, human: bool) -> Result {
+    let content_length = probe(uri).await?.content_length;
+    if human {
+        println!("{}", HumanBytes(content_length as u64));
+    } else {
+        println!("{}", content_length);
+    }
+    Ok(())
+}
+
+/// 并发探测 `uri` 及所有候选 URI，按选择标准返回最佳的探测结果
+async fn probe_candidates(uri: &Uri) -> Result<Probe> {
+    let session = session();
+    let handles: Vec<_> = std::iter::once(uri)
+        .chain(&session.config.candidates)
+        .map(|uri| {
+            let uri = uri.clone();
+            spawn(SESSION.scope(session.clone(), async move {
+                let start = Instant::now();
+                let probe = probe(&uri).await;
+                (uri, probe, start.elapsed())
+            }))
+        })
+        .collect();
+
+    let mut probes = Vec::new();
+    let mut candidates = Vec::new();
+    for handle in handles {
+        let (uri, probe, latency) = handle.await?;
+        match probe {
+            Ok(probe) => {
+                if session.config.verbose {
+                    log(Msg::CandidateProbed {
+                        uri: uri.to_string(),
+                        latency,
+                        size: probe.content_length,
+                        ranges: probe.accept_ranges,
+                    }
+                    .to_string());
+                }
+                candidates.push(Candidate {
+                    latency,
+                    accept_ranges: probe.accept_ranges,
+                    content_length: probe.content_length,
+                });
+                probes.push(probe);
+            }
+            Err(e) => log(Msg::CandidateFailed {
+                uri: uri.to_string(),
+                error: e.to_string(),
+            }
+            .to_string()),
+        }
+    }
+    match candidate::select(
+        &candidates,
+        &session.config.criteria,
+        session.config.expected_size,
+    ) {
+        None => Err(anyhow!(Msg::NoCandidate)),
+        Some(i) => {
+            let probe = probes.swap_remove(i);
+            if let Some(size) = session.config.expected_size {
+                if probe.content_length != size {
+                    return Err(anyhow!(Msg::CandidateSizeMismatch {
+                        size,
+                        uri: probe.uri.to_string(),
+                        actual: probe.content_length,
+                    }));
+                }
+            }
+            log(Msg::CandidateSelected(probe.uri.to_string()).to_string());
+            Ok(probe)
+        }
+    }
+}
+
+/// 并发探测 Metalink 列出的镜像，返回首个可用镜像的探测结果及可分段下载的镜像
+async fn probe_mirrors() -> Result<(Probe, Vec<Uri>)> {
+    let session = session();
+    let handles: Vec<_> = session
+        .config
+        .mirrors
+        .iter()
+        .map(|uri| {
+            let uri = uri.clone();
+            spawn(SESSION.scope(session.clone(), async move {
+                let probe = probe(&uri).await;
+                (uri, probe)
+            }))
+        })
+        .collect();
+    let mut usable: Vec<Probe> = Vec::new();
+    // 没有镜像支持 range 请求时，由首个探测成功的镜像单连接下载
+    let mut fallback = None;
+    for handle in handles {
+        let (uri, probe) = handle.await?;
+        let probe = match probe {
+            Ok(t) => t,
+            Err(e) => {
+                log(Msg::MirrorFailed {
+                    uri: uri.to_string(),
+                    error: e.to_string(),
+                }
+                .to_string());
+                continue;
+            }
+        };
+        // 重定向到同一地址的镜像只保留一个
+        if usable.iter().any(|t| t.uri == probe.uri) {
+            continue;
+        }
+        let size = usable
+            .first()
+            .map(|t| t.content_length)
+            .or(session.config.expected_size);
+        if probe.accept_ranges && size.is_none_or(|t| t == probe.content_length) {
+            usable.push(probe);
+            continue;
+        }
+        log(Msg::MirrorSkipped(uri.to_string()).to_string());
+        if fallback.is_none()
+            && session
+                .config
+                .expected_size
+                .is_none_or(|t| t == probe.content_length)
+        {
+            fallback = Some(probe);
+        }
+    }
+    let mirrors: Vec<Uri> = usable.iter().map(|t| t.uri.clone()).collect();
+    let mut probe = usable
+        .into_iter()
+        .next()
+        .or(fallback)
+        .ok_or_else(|| anyhow!(Msg::NoMirror))?;
+    if mirrors.len() > 1 {
+        // 各镜像的校验值不同，不能用于 `If-Range`，完整性由 Metalink 中的摘要保证
+        probe.validators = (None, None);
+        if session.config.verbose {
+            log(Msg::MirrorsUsed(mirrors.len()).to_string());
+        }
+    }
+    Ok((probe, mirrors))
+}
+
+/// 并发测速各镜像，去掉测速失败的镜像，都失败时不按速度分配
+async fn benchmark_mirrors(uris: Vec<Uri>, bytes: usize) -> Vec<Mirror> {
+    let session = session();
+    let handles: Vec<_> = uris
+        .iter()
+        .map(|uri| {
+            let uri = uri.clone();
+            let request_timeout = session.config.retry.timeout(0);
+            spawn(SESSION.scope(session.clone(), async move {
+                let request = benchmark_mirror(&uri, bytes);
+                match request_timeout {
+                    None => request.await,
+                    Some(t) => timeout(t, request)
+                        .await
+                        .unwrap_or_else(|_| Err(anyhow!(Msg::RequestTimeout(t)))),
+                }
+            }))
+        })
+        .collect();
+    let mut mirrors = Vec::new();
+    for (uri, handle) in uris.iter().zip(handles) {
+        match handle.await.map_err(Error::from).and_then(|t| t) {
+            Ok((latency, speed)) => {
+                if session.config.verbose {
+                    log(Msg::MirrorBenchmarked {
+                        uri: uri.to_string(),
+                        latency,
+                        speed: HumanBytes(speed as u64).to_string(),
+                    }
+                    .to_string());
+                }
+                mirrors.push(Mirror {
+                    speed,
+                    ..Mirror::new(uri.clone())
+                });
+            }
+            Err(e) => log(Msg::MirrorFailed {
+                uri: uri.to_string(),
+                error: e.to_string(),
+            }
+            .to_string()),
+        }
+    }
+    if mirrors.is_empty() {
+        return uris.into_iter().map(Mirror::new).collect();
+    }
+    mirrors
+}
+
+/// 从镜像下载开头的 `bytes` 字节，返回首字节延迟及包含延迟在内的速度（字节/秒）
+async fn benchmark_mirror(uri: &Uri, bytes: usize) -> Result<(Duration, f64)> {
+    let start = Instant::now();
+    let mut body = if is_file_transfer(uri) {
+        interrupt::guard(retrieve(uri, 0)).await?
+    } else {
+        let range = format!("bytes=0-{}", bytes - 1);
+        let (_, response) = follow(Method::GET, uri.clone(), Some(&range)).await?;
+        if response.status() != StatusCode::PARTIAL_CONTENT {
+            return Err(anyhow!(Msg::RequestFailed(response.status().to_string())));
+        }
+        response.into_body()
+    };
+    let latency = start.elapsed();
+    let mut received = 0;
+    // 数据连接读取到资源末尾为止，只取需要的部分
+    while received < bytes {
+        match interrupt::guard(async { Ok(body.data().await) }).await? {
+            Some(t) => received += t?.len(),
+            None => break,
+        }
+    }
+    let elapsed = start.elapsed().as_secs_f64().max(f64::EPSILON);
+    Ok((latency, received as f64 / elapsed))
+}
+
+/// 下载文件，`--add-extension` 时 `file_path` 会被替换为追加扩展名后的路径
+async fn download(size: usize, uri: &Uri, output: &OutputTarget, file_path: &mut String) -> Result {
+    let session = session();
+    let init_uri;
+    let uri = match &session.config.init {
+        None => uri,
+        Some(init) => {
+            init_uri = init.request().await?;
+            &init_uri
+        }
+    };
+    let probe = if session.config.mirrors.len() > 1 {
+        let (probe, mirrors) = probe_mirrors().await?;
+        let bytes = session
+            .config
+            .benchmark_mirrors
+            .map(|t| t.min(probe.content_length));
+        let mirrors = match bytes {
+            Some(bytes) if bytes > 0 && mirrors.len() > 1 => {
+                benchmark_mirrors(mirrors, bytes).await
+            }
+            _ => mirrors.into_iter().map(Mirror::new).collect(),
+        };
+        let _ = job().mirrors.set(mirrors);
+        probe
+    } else if session.config.candidates.is_empty() {
+        probe(uri).await?
+    } else {
+        probe_candidates(uri).await?
+    };
+    // 多个镜像时划分为更多的块放入共享队列，由 `size` 个连接依次领取，较快的镜像下载更多的块
+    let size = if job().has_mirrors() {
+        let _ = job().slots.set(Arc::new(Semaphore::new(size)));
+        size * BLOCKS_PER_MIRROR_CONNECTION
+    } else {
+        size
+    };
+    let content_length = probe.content_length;
+    metrics::add_size(content_length);
+    job().resource_size.store(content_length, Ordering::Relaxed);
+    if let OutputTarget::Infer(dir) = output {
+        let name = probe
+            .file_name
+            .clone()
+            .or_else(|| filename::from_uri(&probe.uri))
+            .ok_or_else(|| anyhow!(Msg::FileNameUnknown(probe.uri.to_string())))?;
+        let path = dir.join(name).display().to_string();
+        if Path::new(&path).exists() {
+            return Err(anyhow!(Msg::FileExists(path)));
+        }
+        log(Msg::InferredFileName(path.clone()).to_string());
+        *file_path = path;
+    }
+    if session.config.add_extension && Path::new(file_path).extension().is_none() {
+        if let Some(extension) = probe.content_type.as_deref().and_then(mime::extension) {
+            let path = format!("{}.{}", file_path, extension);
+            if Path::new(&path).exists() {
+                return Err(anyhow!(Msg::FileExists(path)));
+            }
+            *file_path = path;
+        }
+    }
+    let file_path = file_path.as_str();
+    let _ = job().file.set(file_path.to_string());
+    info!(
+        uri = %probe.uri,
+        size = content_length,
+        accept_ranges = probe.accept_ranges,
+        blocks = size,
+        file = file_path,
+        "probed"
+    );
+    emit(
+        "start",
+        json!({
+            "file": file_path,
+            "uri": probe.uri.to_string(),
+            "size": content_length,
+            "blocks": size,
+        }),
+    );
+    if let Some(hashes) = &session.config.piece_hashes {
+        hashes.check(content_length)?;
+    }
+    if session.config.dry_run {
+        print_plan(&probe, size, file_path);
+        return Ok(());
+    }
+    if session.config.auto_checksum && matches!(uri.scheme_str(), Some("http" | "https")) {
+        if let Some(checksum) = discover_checksum(uri).await {
+            let _ = job().declared.set(checksum);
+        }
+    }
+    if let Some(checksum) = &probe.checksum {
+        if job().declared.set(checksum.clone()).is_ok() && session.config.verbose {
+            log(Msg::DeclaredChecksum(checksum.to_string()).to_string());
+        }
+    }
+    if let Some(handle) = &session.config.resume_handle {
+        if handle.size != content_length {
+            return Err(anyhow!(Msg::ResumeHandleSizeMismatch {
+                expected: handle.size,
+                actual: content_length,
+            }));
+        }
+    }
+    // 多个范围合并为一个请求只适用于 HTTP
+    if is_file_transfer(&probe.uri) && session.config.multi_range.is_some() {
+        return Err(anyhow!(Msg::FtpMultiRange));
+    }
+    if !probe.accept_ranges {
+        // 以下方式均依赖 range 请求
+        if session.config.smoke_test
+            || session.config.resume_from.is_some()
+            || session.config.local_prefix.is_some()
+            || session.config.pieces.is_some()
+            || session.config.resume_handle.is_some()
+        {
+            return Err(anyhow!(Msg::RangesUnsupported));
+        }
+        return download_single(&probe.uri, content_length, file_path).await;
+    }
+    if session.config.smoke_test {
+        return smoke::run(&probe.uri, content_length).await;
+    }
+    if let Some(partial) = &session.config.resume_from {
+        return resume_partial(size, &probe, partial, file_path).await;
+    }
+    if let Some(path) = &session.config.local_prefix {
+        return prefix::download(size, &probe.uri, content_length, path, file_path).await;
+    }
+    if let Some((pieces, piece_size)) = &session.config.pieces {
+        let ranges = pieces.byte_ranges(*piece_size, content_length)?;
+        return piece::download(&probe.uri, content_length, &ranges, file_path).await;
+    }
+    if let Some(part_path) = existing_part(file_path).await {
+        log(Msg::ResumingPart(part_path.display().to_string()).to_string());
+        job().resumed_part.store(true, Ordering::Relaxed);
+        return resume_partial(size, &probe, &part_path, file_path).await;
+    }
+    let output = if !session.config.temp_blocks {
+        Some(prepare_output(uri, &probe, size, file_path).await?)
+    } else {
+        // 块文件与合并出的输出文件同时存在，位于同一文件系统时需要两倍空间
+        let written = blocks_written(content_length, size).await;
+        check_free_space(&[
+            (
+                &job().temp_dir,
+                (content_length as u64).saturating_sub(written),
+            ),
+            (Path::new(&part_path(file_path)), content_length as u64),
+        ])?;
+        // 通过续传句柄或 `--continue` 继续时沿用已有的块文件
+        if !job().temp_dir.exists() {
+            create_temp_dir().await?;
+        } else if session.config.continue_download {
+            check_continued_blocks(content_length, size).await?;
+            log(Msg::ContinuingBlocks(job().temp_dir.display().to_string()).to_string());
+        }
+        if session.config.print_resume_handle {
+            let handle = Handle {
+                uri: probe.uri.clone(),
+                size: content_length,
+                blocks: size,
+                temp_dir: std::path::absolute(&job().temp_dir)?,
+                file_path: std::path::absolute(file_path)?.display().to_string(),
+            };
+            log(Msg::ResumeHandle(handle.to_string()).to_string());
+        }
+        None
+    };
+
+    let autosave = job()
+        .sidecar
+        .get()
+        .map(|_| Autosave(spawn_job(job(), autosave())));
+    let handles = spawn_blocks(&probe.uri, 0, content_length, size, output.as_deref())?;
+    let checksum = match wait_blocks(handles).await {
+        // 探测时声明支持 range 请求，分段请求却返回完整内容；没有可续传的块文件时从头通过单个连接下载
+        Err(e)
+            if ranges_ignored(&e)
+                && !session.config.continue_download
+                && session.config.resume_handle.is_none() =>
+        {
+            drop(autosave);
+            log(Msg::RangeIgnored.to_string());
+            discard_output(output.as_deref()).await?;
+            return download_single(&probe.uri, content_length, file_path).await;
+        }
+        t => t?,
+    };
+    match output {
+        Some(part_path) => {
+            // 文件不完整时不校验摘要
+            if !report_missing(file_path).await? {
+                verify_file(&part_path, content_length, checksum.as_ref()).await?;
+                chunk_output(&part_path).await?;
+            }
+            finish_file(&part_path, file_path).await?;
+            drop(autosave);
+            if let Some(sidecar) = job().sidecar.get() {
+                sidecar.remove().await?;
+            }
+            Ok(())
+        }
+        None => {
+            merge_file(content_length as u64, size, file_path, checksum.as_ref()).await?;
+            // 删除临时文件目录
+            remove_dir_all(&job().temp_dir).await?;
+            Ok(())
+        }
+    }
+}
+
+/// `--dry-run` 时输出下载计划，块的终点包含在内；批量下载时各文件的计划整体输出，不会交错
+fn print_plan(probe: &Probe, size: usize, file_path: &str) {
+    let content_length = probe.content_length;
+    let output = std::path::absolute(file_path).unwrap_or_else(|_| PathBuf::from(file_path));
+    let mut lines = vec![Msg::Plan {
+        uri: probe.uri.to_string(),
+        size: content_length,
+        human: HumanBytes(content_length as u64).to_string(),
+        ranges: probe.accept_ranges,
+        output: output.display().to_string(),
+    }
+    .to_string()];
+    if let Some(mirrors) = job().mirrors.get().filter(|t| t.len() > 1) {
+        for mirror in mirrors {
+            lines.push(Msg::PlanMirror(mirror.uri.to_string()).to_string());
+        }
+    }
+    let blocks = if probe.accept_ranges {
+        plan_blocks(0, content_length, size)
+    } else {
+        vec![(0, content_length)]
+    };
+    lines.push(Msg::PlanBlocks.to_string());
+    for (i, (start, block_size)) in blocks.into_iter().enumerate() {
+        let end = (start + block_size).saturating_sub(1);
+        lines.push(format!("{}\t{}\t{}\t{}", i + 1, start, end, block_size));
+    }
+    println!("{}", lines.join("\n"));
+}
+
+/// 创建直接写入的输出文件，并在旁边记录下载进度
+///
+/// 已有的 sidecar 与服务器上的资源一致，且输出文件大小正确时沿用已下载的部分；`--multi-range` 时不记录
+async fn prepare_output(uri: &Uri, probe: &Probe, size: usize, file_path: &str) -> Result<PathBuf> {
+    let session = session();
+    let content_length = probe.content_length;
+    if session.config.multi_range.is_some() {
+        return create_output(file_path, content_length as u64).await;
+    }
+    let fresh = Sidecar::new(
+        sidecar::path(file_path),
+        uri.to_string(),
+        content_length,
+        probe.validators.clone(),
+        plan_blocks(0, content_length, size),
+    );
+    let path = PathBuf::from(prealloc_path(file_path));
+    let len = metadata(&path).await.map(|t| t.len()).ok();
+    let sidecar = match Sidecar::load(fresh.path()).await {
+        Some(t) if len == Some(content_length as u64) && t.matches(&fresh) => {
+            log(Msg::ResumingSidecar(t.path().display().to_string()).to_string());
+            t
+        }
+        _ => {
+            create_output(file_path, content_length as u64).await?;
+            fresh
+        }
+    };
+    sidecar.save().await?;
+    let _ = job().sidecar.set(sidecar);
+    Ok(path)
+}
+
+/// 删除改为单连接下载前已创建的输出文件、sidecar 及临时文件目录
+async fn discard_output(output: Option<&Path>) -> Result {
+    if let Some(path) = output {
+        if path.exists() {
+            remove_file(path).await?;
+        }
+    }
+    if let Some(sidecar) = job().sidecar.get() {
+        sidecar.remove().await?;
+    }
+    if job().temp_dir.exists() {
+        remove_dir_all(&job().temp_dir).await?;
+    }
+    Ok(())
+}
+
+/// 定期保存 sidecar 中的下载进度
+async fn autosave() {
+    loop {
+        sleep(sidecar::SAVE_INTERVAL).await;
+        if let Some(sidecar) = job().sidecar.get() {
+            if let Err(e) = sidecar.save().await {
+                log(Msg::SidecarFailed(format!("{:#}", e)).to_string());
+            }
+        }
+    }
+}
+
+/// 服务器不支持 range 请求时通过单个连接顺序写入 `.part` 文件，失败后只能从头重试
+async fn download_single(uri: &Uri, content_length: usize, file_path: &str) -> Result {
+    let session = session();
+    log(Msg::SingleConnection.to_string());
+    let _connection = acquire_connection().await?;
+    let part_path = PathBuf::from(part_path(file_path));
+    let bar = add_download_bar(content_length as u64, 1)?;
+    let started = Instant::now();
+    let mut attempt = 0;
+    let mut waited = Duration::ZERO;
+    let chunk = job().chunk(1);
+    let checksum = loop {
+        let request = CHUNK.scope(
+            chunk.clone(),
+            request_single(uri, content_length, &part_path, &bar),
+        );
+        match chunk.guard(request).await {
+            Ok(checksum) => {
+                let file = job().file.get().cloned().unwrap_or_default();
+                metrics::add_task(file, 1, content_length, started.elapsed());
+                break checksum;
+            }
+            Err(e) if interrupt::interrupted() => {
+                bar.abandon_with_message(Msg::TaskInterrupted(1).to_string());
+                return Err(e);
+            }
+            Err(e) if tui::aborted(&e) => {
+                bar.abandon_with_message(Msg::TaskAborted(1).to_string());
+                return Err(anyhow!(Msg::TaskAborted(1)));
+            }
+            Err(e) => {
+                if let Some(delay) = session.config.retry.retry_after(&e, &mut waited) {
+                    bar.set_message(Msg::TaskWaiting { task: 1, delay }.to_string());
+                    interrupt::sleep(delay).await?;
+                    continue;
+                }
+                attempt += 1;
+                let delay = session.config.retry.backoff(attempt, e)?;
+                metrics::add_retry();
+                chunk.retried();
+                bar.set_message(
+                    Msg::TaskRetrying {
+                        task: 1,
+                        attempt,
+                        attempts: session.config.retry.attempts,
+                    }
+                    .to_string(),
+                );
+                interrupt::sleep(delay).await?;
+            }
+        }
+    };
+    bar.finish_with_message(Msg::TaskDone(1).to_string());
+    verify_file(&part_path, content_length, checksum.as_ref()).await?;
+    chunk_output(&part_path).await?;
+    finish_file(&part_path, file_path).await
+}
+
+/// 请求完整资源并写入 `part_path`，返回响应 trailer 中声明的摘要
+async fn request_single(
+    uri: &Uri,
+    content_length: usize,
+    part_path: &Path,
+    bar: &ProgressBar,
+) -> Result<Option<Checksum>> {
+    let response = if is_file_transfer(uri) {
+        let body = retrieve(uri, 0);
+        Response::new(interrupt::guard(body).await?)
+    } else {
+        let (_, response) = follow(Method::GET, uri.clone(), None).await?;
+        check_retry_after(&response)?;
+        if !response.status().is_success() {
+            return Err(anyhow!(Msg::RequestFailed(response.status().to_string())));
+        }
+        response
+    };
+    let mut file = BlockFile::Append(File::create(part_path).await?);
+    let mut written = 0;
+    bar.set_position(0);
+    let trailers = write_file(
+        response,
+        &mut file,
+        &mut written,
+        bar,
+        (0, content_length),
+        None,
+    )
+    .await?;
+    if written < content_length {
+        return Err(anyhow!(Msg::ResponseTooShort {
+            task: 1,
+            written,
+            expected: content_length,
+        }));
+    }
+    check_trailers(trailers, part_path, 0, written as u64).await
+}
+
+/// 检查已有的块文件不超过对应块的大小，超过说明资源已变化，无法继续
+async fn check_continued_blocks(content_length: usize, size: usize) -> Result {
+    for (i, (_, block_size)) in plan_blocks(0, content_length, size).into_iter().enumerate() {
+        let path_buf = job().temp_dir.join(i.to_string());
+        if let Ok(t) = metadata(&path_buf).await {
+            if t.len() > block_size as u64 {
+                return Err(anyhow!(Msg::ContinueBlockTooLarge {
+                    path: path_buf.display().to_string(),
+                    len: t.len(),
+                    expected: block_size,
+                }));
+            }
+        }
+    }
+    Ok(())
+}
+
+/// 已有块文件的总字节数
+async fn blocks_written(content_length: usize, size: usize) -> u64 {
+    let mut written = 0;
+    for i in 0..plan_blocks(0, content_length, size).len() {
+        if let Ok(t) = metadata(job().temp_dir.join(i.to_string())).await {
+            written += t.len();
+        }
+    }
+    written
+}
+
+/// 读取 `--verify-signature` 的分离签名，以 `http://` 或 `https://` 开头时下载
+async fn fetch_signature(location: &str) -> Result<Vec<u8>> {
+    if !location.starts_with("http://") && !location.starts_with("https://") {
+        return Ok(read(location).await?);
+    }
+    Ok(fetch(location.parse()?).await?.to_vec())
+}
+
+/// 依次尝试 `<uri>.sha256`、`<uri>.sha256sum` 及同目录下的 `SHA256SUMS`，返回与文件名对应的摘要
+async fn discover_checksum(uri: &Uri) -> Option<Checksum> {
+    let session = session();
+    let name = filename::from_uri(uri)?;
+    let path = uri.path();
+    let dir = &path[..path.rfind('/').map_or(0, |i| i + 1)];
+    let candidates = [
+        format!("{}.sha256", path),
+        format!("{}.sha256sum", path),
+        format!("{}SHA256SUMS", dir),
+    ];
+    for candidate in candidates {
+        let location = match resolve_location(uri, &candidate) {
+            Ok(t) => t,
+            Err(_) => continue,
+        };
+        let checksum = match fetch(location.clone()).await {
+            Ok(body) => checksum::find_sha256(&String::from_utf8_lossy(&body), &name),
+            Err(e) => {
+                if session.config.verbose {
+                    log(format!("{}: {:#}", location, e));
+                }
+                None
+            }
+        };
+        if let Some(checksum) = checksum {
+            log(Msg::ChecksumFound(location.to_string()).to_string());
+            return Some(checksum);
+        }
+    }
+    log(Msg::ChecksumNotFound.to_string());
+    None
+}
+
+/// 跟随重定向 GET `uri`，返回完整的响应内容
+async fn fetch(uri: Uri) -> Result<Bytes> {
+    let (_, response) = follow(Method::GET, uri, None).await?;
+    if !response.status().is_success() {
+        return Err(anyhow!(Msg::RequestFailed(response.status().to_string())));
+    }
+    Ok(to_bytes(response.into_body()).await?)
+}
+
+/// 将放弃下载的范围按 `<start>-<end>`（含 `end`）逐行写入 `<file-path>.missing`，返回是否存在缺失
+async fn report_missing(file_path: &str) -> Result<bool> {
+    let mut missing = job().missing.lock().unwrap().clone();
+    if missing.is_empty() {
+        return Ok(false);
+    }
+    missing.sort_unstable();
+    let report: String = missing
+        .iter()
+        .map(|(start, end)| format!("{}-{}\n", start, end - 1))
+        .collect();
+    let path = format!("{}.missing", file_path);
+    tokio::fs::write(&path, report).await?;
+    log(Msg::MissingRanges {
+        count: missing.len(),
+        bytes: missing.iter().map(|(start, end)| end - start).sum(),
+        path,
+    }
+    .to_string());
+    Ok(true)
+}
+
+/// 续传已有的部分下载文件，将剩余部分并发写入其末尾，完成后重命名为输出文件
+///
+/// 扩展到完整长度前先在 `<partial>.download.json` 中记录各块的进度，中途失败后文件长度不再代表已下载的
+/// 字节数，重新运行时按记录继续；没有记录时，完整长度的文件只有校验摘要通过才视为已完成
+async fn resume_partial(size: usize, probe: &Probe, partial: &Path, file_path: &str) -> Result {
+    let session = session();
+    let (uri, content_length) = (&probe.uri, probe.content_length);
+    let display = partial.display().to_string();
+    let mut len = metadata(partial).await?.len() as usize;
+    if len > content_length {
+        return Err(anyhow!(Msg::PartialTooLarge {
+            path: display,
+            len,
+            total: content_length,
+        }));
+    }
+    let sidecar_path = sidecar::path(&display);
+    let fresh = |start| {
+        Sidecar::new(
+            sidecar_path.clone(),
+            uri.to_string(),
+            content_length,
+            probe.validators.clone(),
+            plan_blocks(start, content_length, size),
+        )
+    };
+    let recorded = Sidecar::load(&sidecar_path).await;
+    let sidecar = match recorded {
+        Some(t) if len == content_length && t.matches(&fresh(t.start())) => {
+            log(Msg::ResumingSidecar(t.path().display().to_string()).to_string());
+            t
+        }
+        recorded => {
+            // 记录无法沿用时，只有记录的起点之前的部分可信
+            if let Some(start) = recorded.map(|t| t.start()).filter(|t| *t < len) {
+                OpenOptions::new()
+                    .write(true)
+                    .open(partial)
+                    .await?
+                    .set_len(start as u64)
+                    .await?;
+                len = start;
+            }
+            if len == content_length {
+                let declared = job().declared.get().cloned();
+                if session.config.checksum.is_some() || declared.is_some() {
+                    log(Msg::AlreadyComplete(display).to_string());
+                    verify_file(partial, content_length, None).await?;
+                    chunk_output(partial).await?;
+                    return finish_file(partial, file_path).await;
+                }
+                // 用户指定的文件不能丢弃；自动续传的 `.part` 文件从头下载
+                if !job().resumed_part.load(Ordering::Relaxed) {
+                    return Err(anyhow!(Msg::ResumeFileUnverified(display)));
+                }
+                log(Msg::PartUnverified(display).to_string());
+                File::create(partial).await?;
+                len = 0;
+            }
+            if job().resumed_part.load(Ordering::Relaxed) {
+                prefix::check(uri, partial, len).await?;
+            }
+            let sidecar = fresh(len);
+            sidecar.save().await?;
+            let file = OpenOptions::new().write(true).open(partial).await?;
+            file.set_len(content_length as u64).await?;
+            sidecar
+        }
+    };
+    let start = sidecar.start();
+    let _ = job().sidecar.set(sidecar);
+    let autosave = Autosave(spawn_job(job(), autosave()));
+    let handles = spawn_blocks(uri, start, content_length, size, Some(partial))?;
+    let checksum = wait_blocks(handles).await?;
+    verify_file(partial, content_length, checksum.as_ref()).await?;
+    chunk_output(partial).await?;
+    finish_file(partial, file_path).await?;
+    drop(autosave);
+    if let Some(sidecar) = job().sidecar.get() {
+        sidecar.remove().await?;
+    }
+    Ok(())
+}
+
+/// 查找上次单流下载留下的 `.part` 文件，存在临时文件目录时不视为可续传
+///
+/// 单流下载的 `.part` 文件的长度即为已下载的字节数；续传扩展过的文件以 sidecar 的记录为准
+async fn existing_part(file_path: &str) -> Option<PathBuf> {
+    let part_path = PathBuf::from(part_path(file_path));
+    if job().temp_dir.exists() {
+        return None;
+    }
+    match metadata(&part_path).await {
+        Ok(t) if t.is_file() => Some(part_path),
+        _ => None,
+    }
+}
+
+/// 将 `[start, end)` 划分为 `size` 个块，返回各块的起始位置与大小
+fn split_blocks(start: usize, end: usize, size: usize) -> Vec<(usize, usize)> {
+    // 单个任务下载的数据大小
+    let block_size = (end - start) / size;
+
+    // 第一个块获取 `block_size + 余数` 个字节
+    let first_attach = (end - start) % size;
+    let mut blocks = vec![(start, block_size + first_attach)];
+
+    // 剩余块获取 `block_size` 个字节
+    for i in 1..size {
+        blocks.push((start + i * block_size + first_attach, block_size));
+    }
+    blocks
+}
+
+/// 按块划分下载范围，指定片摘要时对齐到片的边界
+fn plan_blocks(start: usize, end: usize, size: usize) -> Vec<(usize, usize)> {
+    let session = session();
+    let blocks = split_blocks(start, end, size);
+    match &session.config.piece_hashes {
+        None => blocks,
+        Some(t) => align_blocks(&blocks, end, t.length),
+    }
+}
+
+/// 将块之间的分界移到最近的 `length` 的整数倍处，使每片完整地落在一个块中；块数不变，部分块可能为空
+fn align_blocks(blocks: &[(usize, usize)], end: usize, length: usize) -> Vec<(usize, usize)> {
+    let mut from = blocks.first().map_or(end, |t| t.0);
+    let mut aligned = Vec::with_capacity(blocks.len());
+    for (i, &(start, block_size)) in blocks.iter().enumerate() {
+        let to = if i + 1 == blocks.len() {
+            end
+        } else {
+            ((start + block_size + length / 2) / length * length).clamp(from, end)
+        };
+        aligned.push((from, to - from));
+        from = to;
+    }
+    aligned
+}
+
+/// 确认各块按顺序恰好覆盖 `[start, end)`，没有空隙或重叠
+fn check_partition(blocks: &[(usize, usize)], start: usize, end: usize) -> Result {
+    let mut expected = start;
+    for &(block_start, block_size) in blocks {
+        if block_start != expected {
+            return Err(anyhow!(Msg::InvalidPartition {
+                expected,
+                actual: block_start,
+            }));
+        }
+        expected += block_size;
+    }
+    if expected != end {
+        return Err(anyhow!(Msg::InvalidPartition {
+            expected: end,
+            actual: expected,
+        }));
+    }
+    Ok(())
+}
+
+/// 为 `[start, end)` 的每个块创建进度条并启动下载任务
+pub(crate) fn spawn_blocks(
+    uri: &Uri,
+    start: usize,
+    end: usize,
+    size: usize,
+    output: Option<&Path>,
+) -> Result<Vec<JoinHandle<Result<Option<Checksum>>>>> {
+    let session = session();
+    let mut blocks = Vec::with_capacity(size);
+    let mut bars = Vec::with_capacity(size);
+    let split = plan_blocks(start, end, size);
+    check_partition(&split, start, end)?;
+    for (i, block) in split.into_iter().enumerate() {
+        let task_index = i + 1;
+        bars.push(add_download_bar(block.1 as u64, task_index)?);
+        blocks.push(((i, task_index), block));
+    }
+    if let (Some(output), Some(n)) = (output, session.config.multi_range) {
+        let mut handles = Vec::new();
+        while !blocks.is_empty() {
+            let n = n.min(blocks.len());
+            handles.push(multipart::download_group(
+                uri.clone(),
+                blocks.drain(..n).collect(),
+                output.to_path_buf(),
+                bars.drain(..n).collect(),
+            ));
+        }
+        return Ok(handles);
+    }
+    let handles = blocks
+        .into_iter()
+        .zip(bars)
+        .map(|((index, (start, block_size)), bar)| {
+            download_block(
+                uri.clone(),
+                index,
+                start,
+                block_size,
+                output.map(Path::to_path_buf),
+                bar,
+            )
+        })
+        .collect();
+    Ok(handles)
+}
+
+/// 等待所有任务结束，返回汇总后的完整资源摘要
+///
+/// 某个任务失败时中止并等待其余任务，避免它们在清理临时文件后继续下载、写入；中断时则等待其余任务写完已收到的数据
+pub(crate) async fn wait_blocks(
+    handles: Vec<JoinHandle<Result<Option<Checksum>>>>,
+) -> Result<Option<Checksum>> {
+    let mut tasks = Tasks(handles);
+    let mut checksum = None;
+    let mut interrupted = None;
+    while !tasks.0.is_empty() {
+        // 按结束的先后处理，任一任务失败时不必等待排在前面的任务
+        let (index, result) = poll_fn(|cx| {
+            for (i, handle) in tasks.0.iter_mut().enumerate() {
+                if let Poll::Ready(t) = Pin::new(handle).poll(cx) {
+                    return Poll::Ready((i, t));
+                }
+            }
+            Poll::Pending
+        })
+        .await;
+        tasks.0.swap_remove(index);
+        let error = match result {
+            Ok(Ok(Some(t))) => match aggregate_checksum(checksum.take(), t) {
+                Ok(t) => {
+                    checksum = t;
+                    continue;
+                }
+                Err(e) => e,
+            },
+            Ok(Ok(None)) => continue,
+            Ok(Err(e)) => e,
+            Err(e) => anyhow!(e),
+        };
+        if interrupt::interrupted() {
+            interrupted.get_or_insert(error);
+            continue;
+        }
+        tasks.abort().await;
+        return Err(error);
+    }
+    match interrupted {
+        Some(e) => Err(e),
+        None => Ok(checksum),
+    }
+}
+
+/// 尚未结束的块下载任务，丢弃时一并中止，等待这些任务的任务被中止时不会留下仍在写入的块
+struct Tasks<T>(Vec<JoinHandle<T>>);
+
+impl<T> Tasks<T> {
+    /// 中止所有任务并等待其结束
+    async fn abort(&mut self) {
+        for handle in &self.0 {
+            handle.abort();
+        }
+        for handle in self.0.drain(..) {
+            let _ = handle.await;
+        }
+    }
+}
+
+impl<T> Drop for Tasks<T> {
+    fn drop(&mut self) {
+        for handle in &self.0 {
+            handle.abort();
+        }
+    }
+}
+
+/// 校验直接写入的输出文件的摘要，`--checksum` 优先于摘要文件及服务器声明的摘要
+pub(crate) async fn verify_file(
+    path: &Path,
+    content_length: usize,
+    checksum: Option<&Checksum>,
+) -> Result {
+    let session = session();
+    let declared = job().declared.get().cloned();
+    if let Some(checksum) = session
+        .config
+        .checksum
+        .as_ref()
+        .or(declared.as_ref())
+        .or(checksum)
+    {
+        let actual = hash_file(path, 0, content_length as u64, checksum.hasher()).await?;
+        checksum.verify(&actual)?;
+        let _ = job().checksum.set(checksum.to_string());
+    }
+    Ok(())
+}
+
+/// 读取直接写入的输出文件，写入内容定义的块列表
+pub(crate) async fn chunk_output(path: &Path) -> Result {
+    let session = session();
+    if let Some((chunks_path, size)) = &session.config.chunks {
+        let chunks = chunker::chunk_file(path, *size).await?;
+        chunker::write_chunks(chunks_path, &chunks).await?;
+    }
+    Ok(())
+}
+
+/// 创建临时文件目录，`--no-temp` 时报错
+pub(crate) async fn create_temp_dir() -> Result {
+    let session = session();
+    if session.config.no_temp {
+        return Err(anyhow!(Msg::TempDirDisabled));
+    }
+    Ok(create_dir(&job().temp_dir).await?)
+}
+
+/// 创建并预分配 `.part` 输出文件，供各任务直接写入对应偏移
+///
+/// 预先占用磁盘空间可减少碎片，空间不足时在下载前失败；文件系统不支持时只设置长度
+pub(crate) async fn create_output(file_path: &str, size: u64) -> Result<PathBuf> {
+    let session = session();
+    let path = PathBuf::from(prealloc_path(file_path));
+    // 重新创建时已有文件占用的空间会被释放
+    let existing = metadata(&path).await.map(|t| t.len()).unwrap_or(0);
+    check_free_space(&[(&path, size.saturating_sub(existing))])?;
+    let file = File::create(&path).await?;
+    if !file.metadata().await?.is_file() {
+        return Err(anyhow!(Msg::NotRegularFile(path.display().to_string())));
+    }
+    if session.config.preallocate && size > 0 {
+        match file.allocate(size).await {
+            Ok(()) => {}
+            Err(e) if matches!(e.kind(), ErrorKind::StorageFull | ErrorKind::QuotaExceeded) => {
+                drop(file);
+                remove_file(&path).await?;
+                return Err(anyhow!(e).context(Msg::PreallocateFailed {
+                    path: path.display().to_string(),
+                    size: HumanBytes(size).to_string(),
+                }));
+            }
+            Err(e) => debug!(error = %e, "allocate unsupported"),
+        }
+    }
+    file.set_len(size).await?;
+    Ok(path)
+}
+
+/// 开始下载前确认磁盘空间足够，`needs` 为将写入各路径的字节数；位于同一文件系统的路径合计计算
+fn check_free_space(needs: &[(&Path, u64)]) -> Result {
+    let mut filesystems: Vec<(Option<u64>, &Path, u64)> = vec![];
+    for &(path, size) in needs {
+        // 路径尚未创建时检查最近的已有上级目录
+        let Some(dir) = path
+            .ancestors()
+            .find(|t| t.is_dir() || t.as_os_str().is_empty())
+        else {
+            continue;
+        };
+        let dir = if dir.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            dir
+        };
+        let device = device_id(dir);
+        match filesystems
+            .iter_mut()
+            .find(|t| t.0.is_some() && t.0 == device)
+        {
+            Some(t) => t.2 += size,
+            None => filesystems.push((device, dir, size)),
+        }
+    }
+    for (_, dir, needed) in filesystems {
+        // 无法获取可用空间时不检查
+        let Ok(available) = fs4::available_space(dir) else {
+            continue;
+        };
+        if available < needed {
+            return Err(anyhow!(Msg::InsufficientSpace {
+                path: dir.display().to_string(),
+                available: HumanBytes(available).to_string(),
+                needed: HumanBytes(needed).to_string(),
+            }));
+        }
+    }
+    Ok(())
+}
+
+/// 目录所在的设备，用于判断两个路径是否位于同一文件系统
+#[cfg(unix)]
+fn device_id(dir: &Path) -> Option<u64> {
+    use std::os::unix::fs::MetadataExt;
+    std::fs::metadata(dir).ok().map(|t| t.dev())
+}
+
+#[cfg(not(unix))]
+fn device_id(_dir: &Path) -> Option<u64> {
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_blocks_partitions_range() {
+        for (start, end) in [(0, 0), (0, 1), (0, 7), (3, 1000), (0, 1000003), (10, 11)] {
+            for size in 1..=16 {
+                let blocks = split_blocks(start, end, size);
+                assert_eq!(blocks.len(), size);
+                check_partition(&blocks, start, end).unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn split_blocks_puts_remainder_first() {
+        assert_eq!(split_blocks(0, 10, 3), vec![(0, 4), (4, 3), (7, 3)]);
+        assert_eq!(
+            split_blocks(5, 10, 5),
+            vec![(5, 1), (6, 1), (7, 1), (8, 1), (9, 1)]
+        );
+    }
+
+    #[test]
+    fn align_blocks_keeps_pieces_whole() {
+        for (start, end) in [(0, 0), (0, 7), (3, 1000), (0, 1000003)] {
+            for size in 1..=16 {
+                for length in [1, 4, 100, 4096, 2000000] {
+                    let blocks = align_blocks(&split_blocks(start, end, size), end, length);
+                    assert_eq!(blocks.len(), size);
+                    check_partition(&blocks, start, end).unwrap();
+                    for &(block_start, _) in &blocks[1..] {
+                        assert!(
+                            block_start % length == 0 || block_start == start || block_start == end
+                        );
+                    }
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn range_start_strict() {
+        let mode = RangeMismatch::Strict;
+        assert_eq!(range_start((10, 19), 100, (10, 19, 100), mode).unwrap(), 10);
+        assert!(range_start((10, 19), 100, (8, 19, 100), mode).is_err());
+        assert!(range_start((10, 19), 100, (12, 19, 100), mode).is_err());
+        assert!(range_start((10, 19), 100, (10, 25, 100), mode).is_err());
+        assert!(range_start((10, 19), 100, (10, 19, 101), mode).is_err());
+    }
+
+    #[test]
+    fn range_start_lenient() {
+        let mode = RangeMismatch::Lenient;
+        assert_eq!(range_start((10, 19), 100, (10, 19, 100), mode).unwrap(), 10);
+        // 起点提前，跳过多出的部分
+        assert_eq!(range_start((10, 19), 100, (4, 19, 100), mode).unwrap(), 4);
+        // 终点延后或提前，由写入时截断或重试补齐
+        assert_eq!(range_start((10, 19), 100, (10, 30, 100), mode).unwrap(), 10);
+        assert_eq!(range_start((10, 19), 100, (0, 14, 100), mode).unwrap(), 0);
+        // 起点延后，按该起点写入后补齐空缺
+        assert_eq!(range_start((10, 19), 100, (12, 19, 100), mode).unwrap(), 12);
+        assert_eq!(range_start((10, 19), 100, (19, 40, 100), mode).unwrap(), 19);
+        // 与请求没有重叠或资源总长度变化时无法对应
+        assert!(range_start((10, 19), 100, (0, 9, 100), mode).is_err());
+        assert!(range_start((10, 19), 100, (20, 29, 100), mode).is_err());
+        assert!(range_start((10, 19), 100, (10, 19, 128), mode).is_err());
+    }
+
+    /// 只记录写入字节数及单次写入的最大长度
+    #[derive(Default)]
+    struct Sink {
+        len: u64,
+        max_write: usize,
+    }
+
+    impl AsyncWrite for Sink {
+        fn poll_write(
+            mut self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+            buf: &[u8],
+        ) -> std::task::Poll<std::io::Result<usize>> {
+            self.len += buf.len() as u64;
+            self.max_write = self.max_write.max(buf.len());
+            std::task::Poll::Ready(Ok(buf.len()))
+        }
+
+        fn poll_flush(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+
+        fn poll_shutdown(
+            self: std::pin::Pin<&mut Self>,
+            _: &mut std::task::Context<'_>,
+        ) -> std::task::Poll<std::io::Result<()>> {
+            std::task::Poll::Ready(Ok(()))
+        }
+    }
+
+    #[tokio::test]
+    async fn copy_block_uses_bounded_buffer() {
+        const BLOCKS: u64 = 16;
+        const BLOCK_SIZE: u64 = 8 * 1024 * 1024 + 1;
+        let mut sink = Sink::default();
+        let mut buffer = vec![0; 4096];
+        let mut max_data = 0;
+        for i in 0..BLOCKS {
+            let mut block = tokio::io::repeat(i as u8).take(BLOCK_SIZE);
+            let copied = copy_block(&mut block, &mut sink, &mut buffer, |data| {
+                max_data = max_data.max(data.len());
+            })
+            .await
+            .unwrap();
+            assert_eq!(copied, BLOCK_SIZE);
+        }
+        assert_eq!(sink.len, BLOCKS * BLOCK_SIZE);
+        assert!(sink.max_write <= buffer.len());
+        assert!(max_data <= buffer.len());
+    }
+
+    #[test]
+    fn check_partition_rejects_gaps_and_overlaps() {
+        assert!(check_partition(&[(0, 4), (5, 5)], 0, 10).is_err());
+        assert!(check_partition(&[(0, 6), (5, 5)], 0, 10).is_err());
+        assert!(check_partition(&[(0, 4), (4, 5)], 0, 10).is_err());
+        assert!(check_partition(&[(1, 9)], 0, 10).is_err());
+        assert!(check_partition(&[(0, 4), (4, 6)], 0, 10).is_ok());
+    }
+
+    #[tokio::test]
+    async fn wait_blocks_aborts_remaining_tasks_on_failure() {
+        let finished = Arc::new(AtomicBool::new(false));
+        let slow = {
+            let finished = finished.clone();
+            spawn(async move {
+                sleep(Duration::from_millis(200)).await;
+                finished.store(true, Ordering::Relaxed);
+                Ok(None)
+            })
+        };
+        let failed = spawn(async { Err(anyhow!(Msg::Aborted)) });
+        // 失败的任务在后，仍应在其失败后立即返回
+        let started = Instant::now();
+        assert!(wait_blocks(vec![slow, failed]).await.is_err());
+        sleep(Duration::from_millis(300)).await;
+        assert!(!finished.load(Ordering::Relaxed));
+        assert!(started.elapsed() < Duration::from_millis(1000));
     }
-    merge_file(content_length as u64).await?;
-    println!("耗时：{:?}", start.elapsed());
-    Ok(())
 }
@@ -0,0 +1,150 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+use hyper::body::to_bytes;
+use hyper::header::CONTENT_TYPE;
+use hyper::{Body, Method, Uri};
+use regex::Regex;
+use serde_json::Value;
+
+use crate::http::{check_redirect_host, log, request_builder, resolve_location, send};
+use crate::message::Msg;
+use crate::session::session;
+use crate::Result;
+
+/// 下载前的初始化请求，POST 到表单地址后从响应中提取实际的下载地址
+pub struct Init {
+    pub uri: Uri,
+    /// 请求体
+    pub data: String,
+    pub extract: Extract,
+}
+
+impl Init {
+    /// 请求体以 `{` 或 `[` 开头时视为 JSON，否则视为表单
+    pub fn content_type(&self) -> &'static str {
+        match self.data.trim_start().chars().next() {
+            Some('{' | '[') => "application/json",
+            _ => "application/x-www-form-urlencoded",
+        }
+    }
+
+    /// 发送初始化请求，返回从响应中提取的下载地址
+    pub async fn request(&self) -> Result<Uri> {
+        let session = session();
+        let request = request_builder(Method::POST, &self.uri)
+            .header(CONTENT_TYPE, self.content_type())
+            .body(Body::from(self.data.clone()))?;
+        let response = send(session.clients[0].as_ref(), request).await?;
+        if !response.status().is_success() {
+            return Err(anyhow!(Msg::RequestFailed(response.status().to_string())));
+        }
+        let body = to_bytes(response.into_body()).await?;
+        let location = self.extract.apply(&String::from_utf8_lossy(&body))?;
+        let uri = resolve_location(&self.uri, location.trim())?;
+        check_redirect_host(&uri)?;
+        log(Msg::InitUri(uri.to_string()).to_string());
+        Ok(uri)
+    }
+}
+
+/// 从初始化请求的响应中提取下载地址的表达式
+pub enum Extract {
+    /// `json:<path>`，以 `.` 分隔的字段名或数组下标，如 `data.files.0.url`
+    Json(Vec<String>),
+    /// `regex:<pattern>`，有捕获组时取第一个捕获组，否则取整个匹配
+    Regex(Regex),
+}
+
+impl FromStr for Extract {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!(Msg::InvalidExtract(s.to_string()));
+        match s.split_once(':') {
+            Some(("json", path)) if !path.is_empty() => {
+                Ok(Self::Json(path.split('.').map(String::from).collect()))
+            }
+            Some(("regex", pattern)) => Ok(Self::Regex(
+                Regex::new(pattern).map_err(|e| invalid().context(e.to_string()))?,
+            )),
+            _ => Err(invalid()),
+        }
+    }
+}
+
+impl Extract {
+    /// 从响应体中提取字符串
+    pub fn apply(&self, body: &str) -> Result<String> {
+        let extracted = match self {
+            Self::Json(path) => {
+                let mut value: &Value = &serde_json::from_str(body)
+                    .map_err(|e| anyhow!(e).context(Msg::ExtractFailed))?;
+                for key in path {
+                    value = match (value, key.parse::<usize>()) {
+                        (Value::Array(t), Ok(i)) => t.get(i),
+                        (Value::Object(t), _) => t.get(key),
+                        _ => None,
+                    }
+                    .unwrap_or(&Value::Null);
+                }
+                value.as_str().map(String::from)
+            }
+            Self::Regex(regex) => regex.captures(body).and_then(|t| {
+                t.get(1)
+                    .or_else(|| t.get(0))
+                    .map(|t| t.as_str().to_string())
+            }),
+        };
+        extracted.ok_or_else(|| anyhow!(Msg::ExtractFailed))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn extract_reads_json_path_or_regex() {
+        let body = r#"{"data": {"files": [{"url": "https://example.com/a.bin"}]}}"#;
+        for (extract, expected) in [
+            ("json:data.files.0.url", Some("https://example.com/a.bin")),
+            ("json:data.files.1.url", None),
+            ("json:data", None),
+            (
+                r#"regex:"url": "([^"]+)""#,
+                Some("https://example.com/a.bin"),
+            ),
+            ("regex:https://[a-z.]+", Some("https://example.com")),
+            ("regex:ftp://", None),
+        ] {
+            let extracted = extract.parse::<Extract>().unwrap().apply(body).ok();
+            assert_eq!(extracted.as_deref(), expected, "{:?}", extract);
+        }
+        assert!("json:a"
+            .parse::<Extract>()
+            .unwrap()
+            .apply("<html>")
+            .is_err());
+        for extract in ["json:", "xpath://a", "regex:("] {
+            assert!(extract.parse::<Extract>().is_err(), "{:?}", extract);
+        }
+    }
+
+    #[test]
+    fn content_type_follows_data() {
+        for (data, expected) in [
+            (r#" {"a": 1}"#, "application/json"),
+            ("[1]", "application/json"),
+            ("a=1&b=2", "application/x-www-form-urlencoded"),
+            ("", "application/x-www-form-urlencoded"),
+        ] {
+            let init = Init {
+                uri: "http://127.0.0.1/".parse().unwrap(),
+                data: data.to_string(),
+                extract: Extract::Json(Vec::new()),
+            };
+            assert_eq!(init.content_type(), expected, "{:?}", data);
+        }
+    }
+}
@@ -0,0 +1,72 @@
+//! 处理 Ctrl+C
+//!
+//! 首次按下时各任务停止请求并写完已收到的数据，保留可续传的文件后退出；再次按下时立即退出
+
+use std::future::Future;
+use std::process::exit;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tokio::signal::ctrl_c;
+use tokio::sync::Notify;
+use tokio::{pin, select, spawn};
+
+use crate::message::Msg;
+use crate::Result;
+
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+static NOTIFY: Notify = Notify::const_new();
+
+/// 开始监听 Ctrl+C
+pub fn watch() {
+    spawn(async {
+        if ctrl_c().await.is_err() {
+            return;
+        }
+        interrupt();
+        if ctrl_c().await.is_ok() {
+            exit(130);
+        }
+    });
+}
+
+/// 与按下 Ctrl+C 一样通知各任务停止，用于仪表盘在原始模式下读到的 Ctrl+C
+pub fn interrupt() {
+    INTERRUPTED.store(true, Ordering::Relaxed);
+    NOTIFY.notify_waiters();
+}
+
+pub fn interrupted() -> bool {
+    INTERRUPTED.load(Ordering::Relaxed)
+}
+
+/// 等待到按下 Ctrl+C 为止
+async fn wait() {
+    let notified = NOTIFY.notified();
+    pin!(notified);
+    // 先注册再检查，避免错过检查之后的通知
+    notified.as_mut().enable();
+    if interrupted() {
+        return;
+    }
+    notified.await;
+}
+
+/// 运行 `future`，按下 Ctrl+C 时放弃并返回错误
+pub async fn guard<T>(future: impl Future<Output = Result<T>>) -> Result<T> {
+    select! {
+        biased;
+        _ = wait() => Err(anyhow!(Msg::Interrupted)),
+        t = future => t,
+    }
+}
+
+/// 重试前等待 `delay`，按下 Ctrl+C 时提前返回错误
+pub async fn sleep(delay: Duration) -> Result {
+    guard(async {
+        tokio::time::sleep(delay).await;
+        Ok(())
+    })
+    .await
+}
@@ -0,0 +1,58 @@
+//! 分段并发下载
+//!
+//! 命令行程序之外，也可以通过 [`Downloader`] 在其他程序中嵌入下载逻辑
+
+mod archive;
+mod auth;
+mod azure;
+mod candidate;
+mod checksum;
+mod chunker;
+mod config;
+mod connector;
+mod daemon;
+mod defaults;
+mod desktop;
+mod downloader;
+mod filename;
+mod ftp;
+mod gcs;
+mod handle;
+mod history;
+mod hook;
+mod http;
+mod init;
+mod interrupt;
+mod limit;
+mod logging;
+mod message;
+mod metalink;
+mod metrics;
+mod mime;
+mod multipart;
+mod netrc;
+mod pause;
+mod piece;
+mod prefix;
+mod progress;
+mod proxy;
+#[cfg(feature = "http3")]
+mod quic;
+mod retry;
+mod s3;
+mod scheduler;
+mod session;
+mod sftp;
+mod sidecar;
+mod signature;
+mod smoke;
+mod style;
+mod transport;
+mod tui;
+mod util;
+mod webdav;
+
+pub use downloader::Downloader;
+pub use http::run;
+
+pub type Result<T = ()> = anyhow::Result<T>;
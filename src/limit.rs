@@ -0,0 +1,317 @@
+//! 限制下载速度
+//!
+//! 所有任务共用一个令牌桶，每写入一段数据前取走相应数量的令牌，令牌不足时等待补充。
+//! 指定单个连接的上限时，每个响应另有一个令牌桶。`--limit-schedule` 按当地时间的时段调整共用令牌桶的速度。
+//! 另外也可以检测过低的速度
+
+use std::cmp::Ordering;
+use std::str::FromStr;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+use anyhow::anyhow;
+use time::OffsetDateTime;
+use tokio::spawn;
+use tokio::time::{interval, sleep};
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 所有任务共用的令牌桶
+static GLOBAL: OnceLock<Bucket> = OnceLock::new();
+
+/// 按时段调整速度时检查当前时间的间隔
+const SCHEDULE_TICK: Duration = Duration::from_secs(10);
+
+/// 令牌桶，每秒补充 `rate` 个令牌，最多积攒一秒的量
+pub struct Bucket {
+    state: Mutex<State>,
+}
+
+struct State {
+    /// 每秒补充的令牌数，为空时不限速
+    rate: Option<f64>,
+    /// 可用的令牌数，为负时表示已预支
+    tokens: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    pub fn new(rate: u64) -> Self {
+        Self {
+            state: Mutex::new(State {
+                rate: Some(rate as f64),
+                tokens: rate as f64,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    fn unlimited() -> Self {
+        Self {
+            state: Mutex::new(State {
+                rate: None,
+                tokens: 0.0,
+                updated: Instant::now(),
+            }),
+        }
+    }
+
+    /// 调整速度上限，为空时不限速；从不限速改为限速时重新积攒一秒的量
+    fn set_rate(&self, rate: Option<u64>) {
+        let mut state = self.state.lock().unwrap();
+        let rate = rate.map(|t| t as f64);
+        if state.rate.is_none() {
+            state.tokens = rate.unwrap_or_default();
+            state.updated = Instant::now();
+        } else if let Some(rate) = rate {
+            state.tokens = state.tokens.min(rate);
+        }
+        state.rate = rate;
+    }
+
+    /// 取走 `n` 个令牌，不足时等待到预支的部分补足为止
+    pub async fn take(&self, n: usize) {
+        let wait = {
+            let mut state = self.state.lock().unwrap();
+            let rate = match state.rate {
+                Some(t) => t,
+                None => return,
+            };
+            let now = Instant::now();
+            let elapsed = now.duration_since(state.updated).as_secs_f64();
+            state.tokens = (state.tokens + elapsed * rate).min(rate);
+            state.updated = now;
+            state.tokens -= n as f64;
+            if state.tokens < 0.0 {
+                Duration::from_secs_f64(-state.tokens / rate)
+            } else {
+                Duration::ZERO
+            }
+        };
+        if !wait.is_zero() {
+            sleep(wait).await;
+        }
+    }
+}
+
+/// 设置所有任务共用的速度上限（字节/秒）
+pub fn set_global(rate: u64) {
+    let _ = GLOBAL.set(Bucket::new(rate));
+}
+
+/// 按 `schedule` 定期调整所有任务共用的速度上限，不在任何时段内时使用 `rate`，为空时不限速
+pub fn start_schedule(schedule: Schedule, rate: Option<u64>) {
+    let bucket = GLOBAL.get_or_init(Bucket::unlimited);
+    bucket.set_rate(schedule.rate(now()).unwrap_or(rate));
+    spawn(async move {
+        let mut ticker = interval(SCHEDULE_TICK);
+        loop {
+            ticker.tick().await;
+            bucket.set_rate(schedule.rate(now()).unwrap_or(rate));
+        }
+    });
+}
+
+/// 当地时间距零点的分钟数，无法获取时区时使用 UTC
+fn now() -> u16 {
+    let now = OffsetDateTime::now_local().unwrap_or_else(|_| OffsetDateTime::now_utc());
+    now.hour() as u16 * 60 + now.minute() as u16
+}
+
+/// 按时段设置的速度上限，如 `08:00-18:00=2M,18:00-08:00=0`，速度为 0 表示不限速
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    /// 起止时间（距零点的分钟数，不含结束时间）及速度上限，结束早于开始时跨过零点；靠前的时段优先
+    periods: Vec<(u16, u16, Option<u64>)>,
+}
+
+impl Schedule {
+    /// 时刻 `minute` 所在时段的速度上限，不在任何时段内时返回 `None`
+    fn rate(&self, minute: u16) -> Option<Option<u64>> {
+        self.periods
+            .iter()
+            .find(|(start, end, _)| match start.cmp(end) {
+                Ordering::Less => (*start..*end).contains(&minute),
+                Ordering::Greater => minute >= *start || minute < *end,
+                Ordering::Equal => true,
+            })
+            .map(|(_, _, rate)| *rate)
+    }
+}
+
+impl FromStr for Schedule {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || anyhow!(Msg::InvalidSchedule(s.to_string()));
+        let minute = |t: &str| {
+            let (hour, minute) = t.trim().split_once(':')?;
+            let (hour, minute) = (hour.parse::<u16>().ok()?, minute.parse::<u16>().ok()?);
+            (hour < 24 && minute < 60).then_some(hour * 60 + minute)
+        };
+        let periods = s
+            .split(',')
+            .map(|period| {
+                let (range, rate) = period.split_once('=').ok_or_else(invalid)?;
+                let (start, end) = range.split_once('-').ok_or_else(invalid)?;
+                let rate = match rate.trim() {
+                    "0" => None,
+                    t => Some(parse_rate(t)?),
+                };
+                Ok((
+                    minute(start).ok_or_else(invalid)?,
+                    minute(end).ok_or_else(invalid)?,
+                    rate,
+                ))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Self { periods })
+    }
+}
+
+/// 写入 `n` 字节前调用，依次等待本连接及所有连接共用的令牌桶，未设置速度上限时立即返回
+pub async fn take(n: usize, connection: Option<&Bucket>) {
+    if let Some(bucket) = connection {
+        bucket.take(n).await;
+    }
+    if let Some(bucket) = GLOBAL.get() {
+        bucket.take(n).await;
+    }
+}
+
+/// 解析速度，如 `500K`、`5M`、`1.5G`，单位为 1024 的幂，不带单位时为字节
+pub fn parse_rate(s: &str) -> Result<u64> {
+    let invalid = || anyhow!(Msg::InvalidRate(s.to_string()));
+    let t = s.trim();
+    let (number, unit) = match t.char_indices().last() {
+        Some((i, c)) if c.is_ascii_alphabetic() => (&t[..i], c.to_ascii_lowercase()),
+        _ => (t, 'b'),
+    };
+    let scale = match unit {
+        'b' => 1.0,
+        'k' => 1024.0,
+        'm' => 1024.0 * 1024.0,
+        'g' => 1024.0 * 1024.0 * 1024.0,
+        _ => return Err(invalid()),
+    };
+    let number: f64 = number.parse().map_err(|_| invalid())?;
+    let rate = (number * scale).round();
+    if !(rate >= 1.0 && rate.is_finite()) {
+        return Err(invalid());
+    }
+    Ok(rate as u64)
+}
+
+/// 低速检测，每个检测周期内的平均速度低于下限时视为失败
+pub struct LowSpeed {
+    /// 速度下限（字节/秒）
+    limit: u64,
+    /// 检测周期
+    time: Duration,
+    start: Instant,
+    bytes: u64,
+}
+
+impl LowSpeed {
+    pub fn new(limit: u64, time: Duration) -> Self {
+        Self {
+            limit,
+            time,
+            start: Instant::now(),
+            bytes: 0,
+        }
+    }
+
+    /// 距本周期结束的时间
+    pub fn remaining(&self) -> Duration {
+        self.time.saturating_sub(self.start.elapsed())
+    }
+
+    /// 记录收到的 `n` 个字节，周期结束时检查平均速度并开始下一个周期
+    pub fn update(&mut self, n: usize) -> Result {
+        self.bytes += n as u64;
+        let elapsed = self.start.elapsed();
+        if elapsed < self.time {
+            return Ok(());
+        }
+        let speed = (self.bytes as f64 / elapsed.as_secs_f64()) as u64;
+        if speed < self.limit {
+            return Err(anyhow!(Msg::TooSlow {
+                speed,
+                limit: self.limit,
+                time: self.time,
+            }));
+        }
+        self.start = Instant::now();
+        self.bytes = 0;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_rate_accepts_binary_units() {
+        for (s, expected) in [
+            ("500", Some(500)),
+            ("500K", Some(500 * 1024)),
+            ("5m", Some(5 * 1024 * 1024)),
+            ("1.5G", Some(3 * 512 * 1024 * 1024)),
+            (" 2k ", Some(2048)),
+            ("0", None),
+            ("0.4", None),
+            ("-1K", None),
+            ("K", None),
+            ("5T", None),
+            ("fast", None),
+        ] {
+            assert_eq!(parse_rate(s).ok(), expected, "{:?}", s);
+        }
+    }
+
+    #[test]
+    fn schedule_picks_first_matching_period() {
+        let schedule: Schedule = "08:00-18:00=2M,17:00-01:30=0,23:00-23:00=1K"
+            .parse()
+            .unwrap();
+        for (minute, expected) in [
+            (7 * 60 + 59, Some(Some(1024))),
+            (8 * 60, Some(Some(2 * 1024 * 1024))),
+            // 与后一个时段重叠时以靠前的为准
+            (17 * 60 + 30, Some(Some(2 * 1024 * 1024))),
+            (18 * 60, Some(None)),
+            // 跨过零点
+            (60, Some(None)),
+            (90, Some(Some(1024))),
+        ] {
+            assert_eq!(schedule.rate(minute), expected, "{:?}", minute);
+        }
+        let schedule: Schedule = "09:00-10:00=1M".parse().unwrap();
+        assert_eq!(schedule.rate(10 * 60), None);
+        for s in [
+            "",
+            "08:00=1M",
+            "08:00-24:00=1M",
+            "8-9=1M",
+            "08:00-09:00=fast",
+        ] {
+            assert!(s.parse::<Schedule>().is_err(), "{:?}", s);
+        }
+    }
+
+    #[tokio::test]
+    async fn bucket_waits_for_borrowed_tokens() {
+        let bucket = Bucket::new(10_000);
+        let start = Instant::now();
+        // 初始积攒一秒的量，之后预支的部分按速度补足
+        bucket.take(10_000).await;
+        assert!(start.elapsed() < Duration::from_millis(100));
+        bucket.take(5_000).await;
+        let elapsed = start.elapsed();
+        assert!(elapsed >= Duration::from_millis(450), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(1000), "{:?}", elapsed);
+    }
+}
@@ -0,0 +1,36 @@
+//! `--log-level`、`--log-file` 启用的结构化日志
+//!
+//! 每块的请求在 `chunk` span 中记录，事件带有地址、偏移、字节数及重试次数等字段，便于排查多连接下载的失败
+
+use std::fs::OpenOptions;
+use std::path::Path;
+use std::sync::Mutex;
+
+use anyhow::Context;
+use tracing::Level;
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 只指定 `--log-file` 时为 `info`，都未指定时不记录；没有 `--log-file` 时写入标准错误
+pub fn init(level: Option<Level>, file: Option<&Path>) -> Result {
+    let level = match (level, file) {
+        (Some(t), _) => t,
+        (None, Some(_)) => Level::INFO,
+        (None, None) => return Ok(()),
+    };
+    let builder = tracing_subscriber::fmt().with_max_level(level);
+    // 作为库多次下载时只有第一次生效
+    let _ = match file {
+        Some(path) => {
+            let file = OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(path)
+                .with_context(|| Msg::LogFileFailed(path.display().to_string()))?;
+            builder.with_writer(Mutex::new(file)).try_init()
+        }
+        None => builder.with_writer(std::io::stderr).try_init(),
+    };
+    Ok(())
+}
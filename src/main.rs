@@ -1,9 +1,4 @@
-mod config;
-mod http;
-
-use http::run;
-
-pub(crate) type Result<T = ()> = anyhow::Result<T>;
+use download::{run, Result};
 
 #[tokio::main]
 async fn main() -> Result {
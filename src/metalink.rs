@@ -0,0 +1,244 @@
+//! 解析 Metalink 文件
+//!
+//! 支持 Metalink 4（RFC 5854，`.meta4`）及 Metalink 3（`.metalink`），读取第一个 `<file>` 的文件名、大小、
+//! 摘要、分片摘要及镜像列表
+
+use std::path::Path;
+
+use anyhow::anyhow;
+use hyper::Uri;
+use regex::Regex;
+
+use crate::checksum::Checksum;
+use crate::message::Msg;
+use crate::piece::PieceHashes;
+use crate::webdav::unescape;
+use crate::Result;
+
+pub struct Metalink {
+    pub name: String,
+    pub size: Option<usize>,
+    /// 最强的摘要
+    pub checksum: Option<Checksum>,
+    /// 算法最强的一组分片摘要
+    pub pieces: Option<PieceHashes>,
+    /// 按优先级排列的镜像
+    pub urls: Vec<Uri>,
+}
+
+impl Metalink {
+    pub fn load(path: &Path) -> Result<Self> {
+        let invalid = || anyhow!(Msg::InvalidMetalink(path.display().to_string()));
+        let text = std::fs::read_to_string(path).map_err(|e| invalid().context(e))?;
+        Self::parse(&text).ok_or_else(invalid)
+    }
+
+    fn parse(text: &str) -> Option<Self> {
+        let element = |name: &str| {
+            Regex::new(&format!(
+                r"(?s)<(?:[\w-]+:)?{0}(\s[^>]*)?>(.*?)</(?:[\w-]+:)?{0}>",
+                name
+            ))
+            .ok()
+        };
+        let file = element("file")?.captures(text)?;
+        let name = attribute(file.get(1)?.as_str(), "name")?;
+        let body = file.get(2)?.as_str();
+        let size = match element("size")?.captures(body) {
+            None => None,
+            Some(t) => Some(t[2].trim().parse().ok()?),
+        };
+        // `<pieces>` 中的分片摘要不参与整个文件的校验
+        let pieces = element("pieces")?;
+        let whole = pieces.replace_all(body, "");
+        let hash = element("hash")?;
+        let piece_hashes = pieces
+            .captures_iter(body)
+            .filter_map(|t| {
+                let attributes = t.get(1)?.as_str();
+                let length = attribute(attributes, "length")?.parse().ok()?;
+                let algorithm = attribute(attributes, "type")?;
+                let hashes = hash
+                    .captures_iter(&t[2])
+                    .map(|t| format!("{}:{}", algorithm, t[2].trim().to_ascii_lowercase()).parse())
+                    .collect::<Result<Vec<Checksum>>>()
+                    .ok()?;
+                match length {
+                    0 => None,
+                    _ => Some(PieceHashes { length, hashes }),
+                }
+            })
+            .filter(|t| !t.hashes.is_empty())
+            .max_by_key(|t| t.hashes[0].algorithm);
+        let checksum = hash
+            .captures_iter(&whole)
+            .filter_map(|t| {
+                let algorithm = attribute(t.get(1)?.as_str(), "type")?;
+                format!("{}:{}", algorithm, t[2].trim().to_ascii_lowercase())
+                    .parse::<Checksum>()
+                    .ok()
+            })
+            .max_by_key(|t| t.algorithm);
+        // Metalink 4 的 `priority` 越小越优先，Metalink 3 的 `preference` 越大越优先
+        let mut urls: Vec<(i64, Uri)> = element("url")?
+            .captures_iter(body)
+            .filter_map(|t| {
+                let attributes = t.get(1).map(|t| t.as_str()).unwrap_or_default();
+                if attribute(attributes, "type").as_deref() == Some("bittorrent") {
+                    return None;
+                }
+                let rank = match attribute(attributes, "priority") {
+                    Some(t) => t.parse().ok()?,
+                    None => attribute(attributes, "preference")
+                        .and_then(|t| t.parse::<i64>().ok())
+                        .map_or(999_999, |t| -t),
+                };
+                Some((rank, unescape(t[2].trim()).parse().ok()?))
+            })
+            .collect();
+        urls.sort_by_key(|(rank, _)| *rank);
+        let urls: Vec<Uri> = urls.into_iter().map(|(_, uri)| uri).collect();
+        if urls.is_empty() {
+            return None;
+        }
+        Some(Self {
+            name: unescape(&name),
+            size,
+            checksum,
+            pieces: piece_hashes,
+            urls,
+        })
+    }
+}
+
+/// 标签中 `name="value"` 形式的属性
+fn attribute(attributes: &str, name: &str) -> Option<String> {
+    let pattern = format!(r#"(?:^|\s){}\s*=\s*(?:"([^"]*)"|'([^']*)')"#, name);
+    let captures = Regex::new(&pattern).ok()?.captures(attributes)?;
+    Some(
+        captures
+            .get(1)
+            .or_else(|| captures.get(2))?
+            .as_str()
+            .to_string(),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const MD5: &str = "0123456789abcdef0123456789abcdef";
+    const SHA1: &str = "0123456789abcdef0123456789abcdef01234567";
+    const SHA256: &str = "0123456789abcdef0123456789abcdef0123456789abcdef0123456789abcdef";
+
+    /// 以 `file` 为 `<file>` 的内容构造 Metalink 4 文档
+    fn meta4(file: &str) -> String {
+        format!(
+            r#"<?xml version="1.0"?><metalink xmlns="urn:ietf:params:xml:ns:metalink"><file name="a.bin">{}<url priority="1">http://example.com/a.bin</url></file></metalink>"#,
+            file
+        )
+    }
+
+    /// 解析出的分片长度及各分片摘要
+    fn pieces(file: &str) -> Option<(usize, Vec<String>)> {
+        let pieces = Metalink::parse(&meta4(file))?.pieces?;
+        let hashes = pieces.hashes.iter().map(|t| t.to_string()).collect();
+        Some((pieces.length, hashes))
+    }
+
+    #[test]
+    fn pieces_are_extracted() {
+        let sha1 = format!(
+            r#"<pieces length="1024" type="sha-1"><hash>{0}</hash><hash>{0}</hash></pieces>"#,
+            SHA1
+        );
+        let sha256 = format!(
+            r#"<pieces length="2048" type="sha-256"><hash>{}</hash></pieces>"#,
+            SHA256.to_ascii_uppercase()
+        );
+        let empty = r#"<pieces length="1024" type="sha-1"></pieces>"#.to_string();
+        let zero = format!(
+            r#"<pieces length="0" type="sha-256"><hash>{}</hash></pieces>"#,
+            SHA256
+        );
+        let bad = format!(
+            r#"<pieces length="1024" type="sha-256"><hash>{}</hash></pieces>"#,
+            SHA1
+        );
+        let unknown = format!(
+            r#"<pieces length="1024" type="crc32"><hash>{}</hash></pieces>"#,
+            SHA1
+        );
+        for (file, expected) in [
+            (String::new(), None),
+            (
+                sha1.clone(),
+                Some((1024, vec![format!("sha1:{}", SHA1); 2])),
+            ),
+            (
+                sha256.clone(),
+                Some((2048, vec![format!("sha256:{}", SHA256)])),
+            ),
+            // 存在多组时选择算法最强的一组，与出现的顺序无关
+            (
+                format!("{}{}", sha256, sha1),
+                Some((2048, vec![format!("sha256:{}", SHA256)])),
+            ),
+            (
+                format!("{}{}", sha1, sha256),
+                Some((2048, vec![format!("sha256:{}", SHA256)])),
+            ),
+            // 无效的一组被忽略
+            (
+                format!("{}{}", empty, sha1),
+                Some((1024, vec![format!("sha1:{}", SHA1); 2])),
+            ),
+            (
+                format!("{}{}", zero, sha1),
+                Some((1024, vec![format!("sha1:{}", SHA1); 2])),
+            ),
+            (
+                format!("{}{}", bad, sha1),
+                Some((1024, vec![format!("sha1:{}", SHA1); 2])),
+            ),
+            (
+                format!("{}{}", unknown, sha1),
+                Some((1024, vec![format!("sha1:{}", SHA1); 2])),
+            ),
+            (zero, None),
+        ] {
+            assert!(pieces(&file) == expected, "{:?}", file);
+        }
+    }
+
+    #[test]
+    fn piece_hashes_are_not_whole_file_checksums() {
+        let pieces = format!(
+            r#"<pieces length="1024" type="sha-256"><hash>{}</hash></pieces>"#,
+            SHA256
+        );
+        for (file, expected) in [
+            (pieces.clone(), None),
+            (
+                format!(r#"<hash type="md5">{}</hash>{}"#, MD5, pieces),
+                Some(format!("md5:{}", MD5)),
+            ),
+            (
+                format!(
+                    r#"{}<hash type="sha-1">{}</hash><hash type="md5">{}</hash>"#,
+                    pieces, SHA1, MD5
+                ),
+                Some(format!("sha1:{}", SHA1)),
+            ),
+        ] {
+            let metalink = Metalink::parse(&meta4(&file)).unwrap();
+            assert!(metalink.pieces.is_some(), "{:?}", file);
+            assert!(
+                metalink.checksum.map(|t| t.to_string()) == expected,
+                "{:?}",
+                file
+            );
+        }
+    }
+}
@@ -0,0 +1,240 @@
+//! Prometheus 文本格式的下载指标及下载结束后的统计
+
+use std::convert::Infallible;
+use std::fmt::Write;
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::service::{make_service_fn, service_fn};
+use hyper::{Body, Request, Response, Server};
+use indicatif::HumanBytes;
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+use tokio::spawn;
+use tokio::time::interval;
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 已下载的字节数
+static BYTES: AtomicU64 = AtomicU64::new(0);
+/// 资源大小，批量下载时为各资源大小之和
+static SIZE: AtomicU64 = AtomicU64::new(0);
+/// 正在下载的块数
+static ACTIVE_BLOCKS: AtomicU64 = AtomicU64::new(0);
+/// 累计重试次数
+static RETRIES: AtomicU64 = AtomicU64::new(0);
+/// 每秒采样得到的最高速度（字节/秒）
+static PEAK: AtomicU64 = AtomicU64::new(0);
+/// 合并块文件的累计耗时（微秒）
+static MERGE_MICROS: AtomicU64 = AtomicU64::new(0);
+
+lazy_static! {
+    static ref START: Instant = Instant::now();
+    /// 下载完成的任务
+    static ref TASKS: Mutex<Vec<Task>> = Mutex::new(Vec::new());
+}
+
+/// 任务通过其连接下载的字节数及耗时
+struct Task {
+    file: String,
+    task: usize,
+    bytes: u64,
+    elapsed: Duration,
+}
+
+pub fn add_bytes(len: usize) {
+    BYTES.fetch_add(len as u64, Ordering::Relaxed);
+}
+
+pub fn add_size(size: usize) {
+    SIZE.fetch_add(size as u64, Ordering::Relaxed);
+}
+
+/// 已下载的字节数及资源大小
+pub fn downloaded() -> (u64, u64) {
+    (BYTES.load(Ordering::Relaxed), SIZE.load(Ordering::Relaxed))
+}
+
+pub fn add_retry() {
+    RETRIES.fetch_add(1, Ordering::Relaxed);
+}
+
+/// 记录任务完成时通过其连接下载的字节数及耗时
+pub fn add_task(file: String, task: usize, bytes: usize, elapsed: Duration) {
+    TASKS.lock().unwrap().push(Task {
+        file,
+        task,
+        bytes: bytes as u64,
+        elapsed,
+    });
+}
+
+pub fn add_merge_time(elapsed: Duration) {
+    MERGE_MICROS.fetch_add(elapsed.as_micros() as u64, Ordering::Relaxed);
+}
+
+/// 每秒采样一次已下载的字节数，记录最高速度
+pub fn sample_peak() {
+    spawn(async {
+        let mut ticks = interval(Duration::from_secs(1));
+        let mut last = BYTES.load(Ordering::Relaxed);
+        loop {
+            ticks.tick().await;
+            let bytes = BYTES.load(Ordering::Relaxed);
+            PEAK.fetch_max(bytes.saturating_sub(last), Ordering::Relaxed);
+            last = bytes;
+        }
+    });
+}
+
+/// 耗时 `elapsed` 内的合计字节数、平均速度及最高速度，不足一秒时最高速度取平均速度
+fn throughput(elapsed: Duration) -> (u64, f64, f64) {
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let average = bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+    let peak = (PEAK.load(Ordering::Relaxed) as f64).max(average);
+    (bytes, average, peak)
+}
+
+/// 按文件及任务号排列的任务
+fn sorted_tasks() -> MutexGuard<'static, Vec<Task>> {
+    let mut tasks = TASKS.lock().unwrap();
+    tasks.sort_by(|a, b| (&a.file, a.task).cmp(&(&b.file, b.task)));
+    tasks
+}
+
+fn speed(bytes: u64, elapsed: Duration) -> f64 {
+    bytes as f64 / elapsed.as_secs_f64().max(f64::EPSILON)
+}
+
+/// 下载结束后的统计，每个任务一行
+pub fn summary(elapsed: Duration) -> String {
+    let (bytes, average, peak) = throughput(elapsed);
+    let mut lines = vec![Msg::Stats {
+        bytes: HumanBytes(bytes).to_string(),
+        average: HumanBytes(average as u64).to_string(),
+        peak: HumanBytes(peak as u64).to_string(),
+        retries: RETRIES.load(Ordering::Relaxed),
+        merge: Duration::from_micros(MERGE_MICROS.load(Ordering::Relaxed)),
+    }
+    .to_string()];
+    for t in sorted_tasks().iter() {
+        lines.push(
+            Msg::TaskStats {
+                task: t.task,
+                bytes: HumanBytes(t.bytes).to_string(),
+                speed: HumanBytes(speed(t.bytes, t.elapsed) as u64).to_string(),
+            }
+            .to_string(),
+        );
+    }
+    lines.join("\n")
+}
+
+/// `--stats json` 输出的统计，字节数及速度均以字节为单位，时间以秒为单位
+pub fn stats(elapsed: Duration) -> Value {
+    let (bytes, average, peak) = throughput(elapsed);
+    let tasks: Vec<Value> = sorted_tasks()
+        .iter()
+        .map(|t| {
+            json!({
+                "file": t.file,
+                "task": t.task,
+                "bytes": t.bytes,
+                "elapsed": t.elapsed.as_secs_f64(),
+                "speed": speed(t.bytes, t.elapsed),
+            })
+        })
+        .collect();
+    json!({
+        "elapsed": elapsed.as_secs_f64(),
+        "bytes": bytes,
+        "average": average,
+        "peak": peak,
+        "retries": RETRIES.load(Ordering::Relaxed),
+        "merge": Duration::from_micros(MERGE_MICROS.load(Ordering::Relaxed)).as_secs_f64(),
+        "tasks": tasks,
+    })
+}
+
+/// 块下载任务存活期间计入正在下载的块数
+pub struct ActiveBlock;
+
+impl ActiveBlock {
+    pub fn new() -> Self {
+        ACTIVE_BLOCKS.fetch_add(1, Ordering::Relaxed);
+        Self
+    }
+}
+
+impl Drop for ActiveBlock {
+    fn drop(&mut self) {
+        ACTIVE_BLOCKS.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+/// 在 `addr` 上启动指标服务
+pub fn serve(addr: SocketAddr) -> Result {
+    lazy_static::initialize(&START);
+    let server = Server::try_bind(&addr)?.serve(make_service_fn(|_| async {
+        Ok::<_, Infallible>(service_fn(handle))
+    }));
+    spawn(async move {
+        if let Err(e) = server.await {
+            eprintln!("{}", Msg::MetricsFailed(e.to_string()));
+        }
+    });
+    Ok(())
+}
+
+async fn handle(_request: Request<Body>) -> std::result::Result<Response<Body>, Infallible> {
+    let bytes = BYTES.load(Ordering::Relaxed);
+    let speed = bytes as f64 / START.elapsed().as_secs_f64();
+    let mut text = String::new();
+    for (name, kind, help, value) in [
+        (
+            "download_bytes_total",
+            "counter",
+            "Bytes downloaded.",
+            bytes as f64,
+        ),
+        (
+            "download_size_bytes",
+            "gauge",
+            "Size of the resource.",
+            SIZE.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "download_active_blocks",
+            "gauge",
+            "Blocks being downloaded.",
+            ACTIVE_BLOCKS.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "download_retries_total",
+            "counter",
+            "Block retries.",
+            RETRIES.load(Ordering::Relaxed) as f64,
+        ),
+        (
+            "download_speed_bytes_per_second",
+            "gauge",
+            "Average speed since start.",
+            speed,
+        ),
+    ] {
+        let _ = writeln!(
+            text,
+            "# HELP {name} {help}\n# TYPE {name} {kind}\n{name} {value}"
+        );
+    }
+    let mut response = Response::new(Body::from(text));
+    response.headers_mut().insert(
+        CONTENT_TYPE,
+        HeaderValue::from_static("text/plain; version=0.0.4"),
+    );
+    Ok(response)
+}
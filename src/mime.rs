@@ -0,0 +1,59 @@
+/// 常见 MIME 类型对应的文件扩展名
+const EXTENSIONS: &[(&str, &str)] = &[
+    ("application/gzip", "gz"),
+    ("application/java-archive", "jar"),
+    ("application/json", "json"),
+    ("application/msword", "doc"),
+    ("application/pdf", "pdf"),
+    ("application/vnd.android.package-archive", "apk"),
+    ("application/vnd.ms-excel", "xls"),
+    ("application/vnd.ms-powerpoint", "ppt"),
+    (
+        "application/vnd.openxmlformats-officedocument.presentationml.presentation",
+        "pptx",
+    ),
+    (
+        "application/vnd.openxmlformats-officedocument.spreadsheetml.sheet",
+        "xlsx",
+    ),
+    (
+        "application/vnd.openxmlformats-officedocument.wordprocessingml.document",
+        "docx",
+    ),
+    ("application/wasm", "wasm"),
+    ("application/x-7z-compressed", "7z"),
+    ("application/x-bzip2", "bz2"),
+    ("application/x-gzip", "gz"),
+    ("application/x-iso9660-image", "iso"),
+    ("application/x-msdownload", "exe"),
+    ("application/x-rar-compressed", "rar"),
+    ("application/x-tar", "tar"),
+    ("application/x-xz", "xz"),
+    ("application/xml", "xml"),
+    ("application/zip", "zip"),
+    ("application/zstd", "zst"),
+    ("audio/flac", "flac"),
+    ("audio/mpeg", "mp3"),
+    ("audio/ogg", "ogg"),
+    ("audio/wav", "wav"),
+    ("image/gif", "gif"),
+    ("image/jpeg", "jpg"),
+    ("image/png", "png"),
+    ("image/svg+xml", "svg"),
+    ("image/webp", "webp"),
+    ("text/csv", "csv"),
+    ("text/html", "html"),
+    ("text/plain", "txt"),
+    ("video/mp4", "mp4"),
+    ("video/webm", "webm"),
+    ("video/x-matroska", "mkv"),
+];
+
+/// 根据 `Content-Type` 推断文件扩展名，忽略参数部分，未知类型返回 `None`
+pub fn extension(content_type: &str) -> Option<&'static str> {
+    let mime = content_type.split(';').next()?.trim().to_ascii_lowercase();
+    EXTENSIONS
+        .iter()
+        .find(|(t, _)| *t == mime)
+        .map(|(_, extension)| *extension)
+}
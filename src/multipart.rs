@@ -0,0 +1,443 @@
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper::header::{CONTENT_RANGE, CONTENT_TYPE, RANGE};
+use hyper::{Method, StatusCode, Uri};
+use indicatif::ProgressBar;
+use tokio::fs::OpenOptions;
+use tokio::io::{AsyncSeekExt, AsyncWriteExt, SeekFrom};
+use tokio::task::JoinHandle;
+use tokio::time::timeout;
+
+use crate::checksum::Checksum;
+use crate::http::{
+    add_bytes, build, check_retry_after, download_block, job, log, low_speed, next_data,
+    parse_content_range, request_builder, send, spawn_job, wait_blocks,
+};
+use crate::interrupt;
+use crate::limit::{self, Bucket};
+use crate::message::Msg;
+use crate::pause;
+use crate::scheduler::acquire_connection;
+use crate::session::session;
+use crate::Result;
+
+/// `multipart/byteranges` 响应体的解析事件
+pub enum Event {
+    /// 新的分段开始，携带其范围 `[start, end]`
+    Part(usize, usize),
+    /// 当前分段的内容
+    Data(Vec<u8>),
+}
+
+/// 流式解析 `multipart/byteranges` 响应体
+pub struct Parser {
+    /// 分段分隔符 `--<boundary>`
+    delimiter: Vec<u8>,
+    buffer: Vec<u8>,
+    /// 当前分段尚未读取的内容长度
+    remaining: usize,
+    done: bool,
+}
+
+impl Parser {
+    /// 从 `Content-Type` 中读取 boundary，不是 `multipart/byteranges` 时返回 `None`
+    pub fn new(content_type: &str) -> Option<Self> {
+        let (mime, params) = content_type.split_once(';')?;
+        if !mime.trim().eq_ignore_ascii_case("multipart/byteranges") {
+            return None;
+        }
+        let boundary = params.split(';').find_map(|t| {
+            let (name, value) = t.split_once('=')?;
+            name.trim()
+                .eq_ignore_ascii_case("boundary")
+                .then(|| value.trim().trim_matches('"'))
+        })?;
+        Some(Self {
+            delimiter: format!("--{}", boundary).into_bytes(),
+            buffer: Vec::new(),
+            remaining: 0,
+            done: false,
+        })
+    }
+
+    /// 读入一段响应体，返回解析出的事件
+    pub fn feed(&mut self, mut chunk: &[u8]) -> Result<Vec<Event>> {
+        let mut events = Vec::new();
+        while !self.done {
+            if self.remaining > 0 {
+                // 先消耗缓冲区中剩余的内容，再直接转发新读入的数据
+                let source = if self.buffer.is_empty() {
+                    if chunk.is_empty() {
+                        break;
+                    }
+                    let len = self.remaining.min(chunk.len());
+                    let (data, rest) = chunk.split_at(len);
+                    chunk = rest;
+                    data.to_vec()
+                } else {
+                    let len = self.remaining.min(self.buffer.len());
+                    self.buffer.drain(..len).collect()
+                };
+                self.remaining -= source.len();
+                events.push(Event::Data(source));
+                continue;
+            }
+            self.buffer.extend_from_slice(chunk);
+            chunk = &[];
+            match self.parse_headers()? {
+                None => break,
+                Some((start, end)) => events.push(Event::Part(start, end)),
+            }
+        }
+        Ok(events)
+    }
+
+    /// 解析缓冲区中分隔符之后的分段头，返回分段的范围，数据不足时返回 `None`
+    fn parse_headers(&mut self) -> Result<Option<(usize, usize)>> {
+        let start = self.buffer.iter().position(|&t| t != b'\r' && t != b'\n');
+        let start = match start {
+            None => return Ok(None),
+            Some(t) => t,
+        };
+        let rest = &self.buffer[start..];
+        if rest.len() < self.delimiter.len() + 2 {
+            return Ok(None);
+        }
+        if !rest.starts_with(&self.delimiter) {
+            return Err(anyhow!(Msg::InvalidMultipart));
+        }
+        if rest[self.delimiter.len()..].starts_with(b"--") {
+            self.done = true;
+            return Ok(None);
+        }
+        let end = match rest.windows(4).position(|t| t == b"\r\n\r\n") {
+            None => return Ok(None),
+            Some(t) => t,
+        };
+        let headers = String::from_utf8_lossy(&rest[self.delimiter.len()..end]).into_owned();
+        self.buffer.drain(..start + end + 4);
+        for line in headers.split("\r\n") {
+            if let Some((name, value)) = line.split_once(':') {
+                if name.trim().eq_ignore_ascii_case("content-range") {
+                    let value = value.trim();
+                    return match parse_content_range(value) {
+                        Some((start, end, _)) if start <= end => {
+                            self.remaining = end - start + 1;
+                            Ok(Some((start, end)))
+                        }
+                        _ => Err(anyhow!(Msg::InvalidContentRange(value.to_string()))),
+                    };
+                }
+            }
+        }
+        Err(anyhow!(Msg::InvalidMultipart))
+    }
+}
+
+/// 块的任务下标及其起始位置与大小
+pub type Block = ((usize, usize), (usize, usize));
+
+/// 通过一次 multi-range 请求下载多个块，服务器未返回的部分再逐块请求
+pub fn download_group(
+    uri: Uri,
+    blocks: Vec<Block>,
+    output: PathBuf,
+    bars: Vec<ProgressBar>,
+) -> JoinHandle<Result<Option<Checksum>>> {
+    let session = session();
+    spawn_job(job(), async move {
+        let mut written = vec![0; blocks.len()];
+        // 按 `Retry-After` 已等待的合计时间
+        let mut waited = Duration::ZERO;
+        let result = loop {
+            // 逐块补齐时各块另行占用连接，此处只在请求期间占用
+            let connection = acquire_connection().await?;
+            let request = request_group(&uri, &blocks, &output, &mut written, &bars);
+            let result = match session.config.retry.timeout(0) {
+                None => request.await,
+                Some(t) => timeout(t, request)
+                    .await
+                    .unwrap_or_else(|_| Err(anyhow!(Msg::RequestTimeout(t)))),
+            };
+            drop(connection);
+            // 与单个范围的请求一样按 `Retry-After` 等待后重新请求，而不是立即逐块请求
+            let delay = match &result {
+                Err(e) => session.config.retry.retry_after(e, &mut waited),
+                Ok(()) => None,
+            };
+            match delay {
+                None => break result,
+                Some(delay) => {
+                    for (&(index, _), bar) in blocks.iter().zip(&bars) {
+                        let message = Msg::TaskWaiting {
+                            task: index.1,
+                            delay,
+                        };
+                        bar.set_message(message.to_string());
+                    }
+                    interrupt::sleep(delay).await?;
+                }
+            }
+        };
+        if let Err(e) = result {
+            if session.config.verbose {
+                log(Msg::MultiRangeFailed(e.to_string()).to_string());
+            }
+        }
+        let mut handles = Vec::new();
+        for ((&(index, (start, block_size)), bar), written) in blocks.iter().zip(bars).zip(written)
+        {
+            if written < block_size {
+                // 进度条改为显示剩余部分
+                bar.set_length((block_size - written) as u64);
+                handles.push(download_block(
+                    uri.clone(),
+                    index,
+                    start + written,
+                    block_size - written,
+                    Some(output.clone()),
+                    bar,
+                ));
+            } else {
+                bar.finish_with_message(Msg::TaskDone(index.1).to_string());
+            }
+        }
+        wait_blocks(handles).await
+    })
+}
+
+/// 发送 multi-range 请求，将返回的各分段写入输出文件的对应偏移
+///
+/// 服务器可能合并相邻的范围、只返回部分范围或忽略 range 请求，
+/// `written` 只记录各块从起始位置连续写入的字节数，其余部分由调用方逐块补齐
+async fn request_group(
+    uri: &Uri,
+    blocks: &[Block],
+    output: &Path,
+    written: &mut [usize],
+    bars: &[ProgressBar],
+) -> Result {
+    let session = session();
+    let ranges: Vec<String> = blocks
+        .iter()
+        .filter(|(_, (_, block_size))| *block_size > 0)
+        .map(|(_, (start, block_size))| format!("{}-{}", start, start + block_size - 1))
+        .collect();
+    if ranges.is_empty() {
+        return Ok(());
+    }
+    let request = build(
+        request_builder(Method::GET, uri).header(RANGE, format!("bytes={}", ranges.join(","))),
+    )?;
+    let mut response = send(session.clients[0].as_ref(), request).await?;
+    check_retry_after(&response)?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Ok(());
+    }
+    let header = |name| {
+        response
+            .headers()
+            .get(name)
+            .and_then(|t| t.to_str().ok())
+            .map(String::from)
+    };
+    let mut parser = header(CONTENT_TYPE).as_deref().and_then(Parser::new);
+    let mut file = OpenOptions::new().write(true).open(output).await?;
+    let bucket = session.config.limit_rate_per_conn.map(Bucket::new);
+    let mut offset = 0;
+    // 已收到的分段，用于检查重叠
+    let mut parts = Vec::new();
+    if parser.is_none() {
+        // 单个范围的响应，可能是合并后的范围
+        let content_range = header(CONTENT_RANGE).unwrap_or_default();
+        offset = match parse_content_range(&content_range) {
+            Some((start, end, _)) if start <= end => {
+                check_part(blocks, &mut parts, (start, end))?;
+                start
+            }
+            _ => return Err(anyhow!(Msg::InvalidContentRange(content_range))),
+        };
+        file.seek(SeekFrom::Start(offset as u64)).await?;
+    }
+
+    let mut low_speed = low_speed();
+    while let Some(next) = next_data(&mut response, low_speed.as_mut()).await {
+        let bytes = next?;
+        let events = match &mut parser {
+            None => vec![Event::Data(bytes.to_vec())],
+            Some(parser) => parser.feed(&bytes)?,
+        };
+        for event in events {
+            match event {
+                Event::Part(start, end) => {
+                    check_part(blocks, &mut parts, (start, end))?;
+                    offset = start;
+                    file.seek(SeekFrom::Start(offset as u64)).await?;
+                }
+                Event::Data(data) => {
+                    limit::take(data.len(), bucket.as_ref()).await;
+                    file.write_all(&data).await?;
+                    add_bytes(data.len());
+                    let end = offset + data.len();
+                    for (((_, (start, block_size)), written), bar) in
+                        blocks.iter().zip(written.iter_mut()).zip(bars)
+                    {
+                        let from = offset.max(*start);
+                        let to = end.min(start + block_size);
+                        if from < to && from == start + *written {
+                            *written += to - from;
+                            bar.inc((to - from) as u64);
+                        }
+                    }
+                    offset = end;
+                }
+            }
+        }
+        pause::wait(bars, None).await;
+    }
+    file.flush().await?;
+    Ok(())
+}
+
+/// 检查 multi-range 响应中的分段 `[start, end]` 是否与请求的范围一致且不与已收到的分段重叠，通过后记入 `parts`
+///
+/// 服务器可以合并相邻的范围，分段须从某个块的起点开始，到首尾相接的块中某个块的终点结束
+fn check_part(
+    blocks: &[Block],
+    parts: &mut Vec<(usize, usize)>,
+    (start, end): (usize, usize),
+) -> Result {
+    let range = || format!("{}-{}", start, end);
+    let mut requested: Vec<(usize, usize)> = blocks
+        .iter()
+        .filter(|(_, (_, block_size))| *block_size > 0)
+        .map(|(_, (block_start, block_size))| (*block_start, block_start + block_size))
+        .collect();
+    requested.sort_unstable();
+    // 从分段起点所在的块开始，依次合并首尾相接的块，直到到达分段终点
+    let mut covered = None;
+    for (from, to) in requested {
+        match covered {
+            None if from == start => covered = Some(to),
+            Some(t) if t == from && t <= end => covered = Some(to),
+            _ => {}
+        }
+    }
+    if covered != Some(end + 1) {
+        return Err(anyhow!(Msg::UnrequestedPart(range())));
+    }
+    if parts.iter().any(|&(from, to)| start <= to && from <= end) {
+        return Err(anyhow!(Msg::OverlappingPart(range())));
+    }
+    parts.push((start, end));
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const BODY: &[u8] = b"\r\n--XYZ\r\nContent-Type: text/plain\r\nContent-Range: bytes 0-4/20\r\n\r\nhello\r\n--XYZ\r\nContent-Range: bytes 10-14/20\r\n\r\nworld\r\n--XYZ--\r\n";
+
+    /// 按 `chunk` 字节分块读入，返回各分段的范围及内容
+    fn parts(
+        content_type: &str,
+        body: &[u8],
+        chunk: usize,
+    ) -> Result<Vec<(usize, usize, Vec<u8>)>> {
+        let mut parser = Parser::new(content_type).unwrap();
+        let mut parts: Vec<(usize, usize, Vec<u8>)> = Vec::new();
+        for piece in body.chunks(chunk) {
+            for event in parser.feed(piece)? {
+                match event {
+                    Event::Part(start, end) => parts.push((start, end, Vec::new())),
+                    Event::Data(data) => parts.last_mut().unwrap().2.extend(data),
+                }
+            }
+        }
+        Ok(parts)
+    }
+
+    #[test]
+    fn boundary_from_content_type() {
+        for (content_type, expected) in [
+            ("multipart/byteranges; boundary=XYZ", Some("--XYZ")),
+            (
+                "Multipart/ByteRanges; charset=utf-8; Boundary=\"XYZ\"",
+                Some("--XYZ"),
+            ),
+            ("multipart/byteranges", None),
+            ("multipart/byteranges; charset=utf-8", None),
+            ("multipart/mixed; boundary=XYZ", None),
+            ("application/octet-stream", None),
+        ] {
+            let delimiter = Parser::new(content_type).map(|t| t.delimiter);
+            assert_eq!(
+                delimiter,
+                expected.map(|t| t.as_bytes().to_vec()),
+                "{}",
+                content_type
+            );
+        }
+    }
+
+    #[test]
+    fn parts_split_across_chunks() {
+        let expected = vec![(0, 4, b"hello".to_vec()), (10, 14, b"world".to_vec())];
+        for chunk in [1, 2, 3, 7, 16, BODY.len()] {
+            let parts = parts("multipart/byteranges; boundary=XYZ", BODY, chunk).unwrap();
+            assert_eq!(parts, expected, "chunk size {}", chunk);
+        }
+    }
+
+    #[test]
+    fn invalid_parts_are_rejected() {
+        for body in [
+            // 分隔符不是声明的 boundary
+            &b"--ABC\r\nContent-Range: bytes 0-4/20\r\n\r\nhello\r\n--ABC--\r\n"[..],
+            // 缺少 Content-Range
+            b"--XYZ\r\nContent-Type: text/plain\r\n\r\nhello\r\n--XYZ--\r\n",
+            // 起点大于终点
+            b"--XYZ\r\nContent-Range: bytes 4-0/20\r\n\r\nhello\r\n--XYZ--\r\n",
+            b"--XYZ\r\nContent-Range: items 0-4/20\r\n\r\nhello\r\n--XYZ--\r\n",
+        ] {
+            let result = parts("multipart/byteranges; boundary=XYZ", body, 8);
+            assert!(result.is_err(), "{}", String::from_utf8_lossy(body));
+        }
+    }
+
+    #[test]
+    fn data_after_closing_delimiter_is_ignored() {
+        let mut body = BODY.to_vec();
+        body.extend_from_slice(b"epilogue");
+        let parts = parts("multipart/byteranges; boundary=XYZ", &body, 5).unwrap();
+        assert_eq!(parts.len(), 2);
+    }
+
+    #[test]
+    fn check_part_accepts_requested_and_coalesced_ranges() {
+        let blocks = [((0, 1), (0, 10)), ((1, 2), (10, 10)), ((2, 3), (30, 10))];
+        for (parts, expected) in [
+            (&[(0, 9), (10, 19), (30, 39)][..], true),
+            // 合并相邻的范围
+            (&[(0, 19), (30, 39)], true),
+            // 跨越未请求的 20-29
+            (&[(10, 39)], false),
+            // 不从块的起点开始或不在块的终点结束
+            (&[(5, 9)], false),
+            (&[(0, 14)], false),
+            (&[(40, 49)], false),
+            // 重复或重叠
+            (&[(0, 9), (0, 9)], false),
+            (&[(0, 19), (10, 19)], false),
+        ] {
+            let mut received = Vec::new();
+            let ok = parts
+                .iter()
+                .all(|&part| check_part(&blocks, &mut received, part).is_ok());
+            assert_eq!(ok, expected, "{:?}", parts);
+        }
+    }
+}
@@ -0,0 +1,150 @@
+//! 读取 `.netrc` 中各主机的凭据
+
+use std::env;
+use std::fs::read_to_string;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::auth::Auth;
+use crate::message::Msg;
+use crate::util::home;
+use crate::Result;
+
+/// 一项凭据，`machine` 为空时是 `default` 项
+struct Entry {
+    machine: Option<String>,
+    login: String,
+    password: String,
+}
+
+pub struct Netrc {
+    entries: Vec<Entry>,
+}
+
+impl Netrc {
+    /// 默认路径：`NETRC` 环境变量，否则为主目录下的 `.netrc`
+    pub fn default_path() -> PathBuf {
+        if let Some(path) = env::var_os("NETRC") {
+            return PathBuf::from(path);
+        }
+        home().join(".netrc")
+    }
+
+    pub fn load(path: &Path) -> Result<Self> {
+        let text =
+            read_to_string(path).with_context(|| Msg::InvalidNetrc(path.display().to_string()))?;
+        Ok(Self::parse(&text))
+    }
+
+    fn parse(text: &str) -> Self {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut lines = text.lines();
+        while let Some(line) = lines.next() {
+            let mut tokens = line.split_whitespace();
+            while let Some(token) = tokens.next() {
+                match token {
+                    "machine" => entries.push(Entry {
+                        machine: tokens.next().map(str::to_ascii_lowercase),
+                        login: String::new(),
+                        password: String::new(),
+                    }),
+                    "default" => entries.push(Entry {
+                        machine: None,
+                        login: String::new(),
+                        password: String::new(),
+                    }),
+                    "login" | "password" | "account" => {
+                        let value = tokens.next().unwrap_or_default().to_string();
+                        match (entries.last_mut(), token) {
+                            (Some(entry), "login") => entry.login = value,
+                            (Some(entry), "password") => entry.password = value,
+                            _ => {}
+                        }
+                    }
+                    // 宏定义持续到下一个空行
+                    "macdef" => {
+                        for line in lines.by_ref() {
+                            if line.trim().is_empty() {
+                                break;
+                            }
+                        }
+                        break;
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Self { entries }
+    }
+
+    /// `host` 的凭据，没有匹配的 `machine` 时使用 `default` 项
+    pub fn auth(&self, host: &str) -> Option<Auth> {
+        let host = host.to_ascii_lowercase();
+        let entry = self
+            .entries
+            .iter()
+            .find(|t| t.machine.as_deref() == Some(host.as_str()))
+            .or_else(|| self.entries.iter().find(|t| t.machine.is_none()))?;
+        Some(Auth::Basic {
+            user: entry.login.clone(),
+            password: entry.password.clone(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn credentials(text: &str, host: &str) -> Option<(String, String)> {
+        match Netrc::parse(text).auth(host)? {
+            Auth::Basic { user, password } => Some((user, password)),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn parse_tokens() {
+        let multi_line = "machine example.com\n  login alice\n  password secret\n";
+        let macro_def = "macdef init\nmachine evil.com login x password y\n\nmachine example.com login bob password pw";
+        for (text, host, expected) in [
+            (
+                "machine example.com login alice password secret",
+                "example.com",
+                Some(("alice", "secret")),
+            ),
+            (multi_line, "example.com", Some(("alice", "secret"))),
+            // 主机名不区分大小写
+            (
+                "machine Example.COM login alice password secret",
+                "EXAMPLE.com",
+                Some(("alice", "secret")),
+            ),
+            (
+                "machine a.com login a password 1 machine b.com login b password 2",
+                "b.com",
+                Some(("b", "2")),
+            ),
+            // 没有匹配的 `machine` 时使用 `default`，`account` 被忽略
+            (
+                "machine a.com login a password 1\ndefault login anon account x password guest",
+                "c.com",
+                Some(("anon", "guest")),
+            ),
+            ("machine a.com login a password 1", "c.com", None),
+            // 宏定义中的内容不是凭据
+            (macro_def, "evil.com", None),
+            (macro_def, "example.com", Some(("bob", "pw"))),
+            (
+                "machine example.com login alice",
+                "example.com",
+                Some(("alice", "")),
+            ),
+        ] {
+            let expected =
+                expected.map(|(user, password)| (user.to_string(), password.to_string()));
+            assert_eq!(credentials(text, host), expected, "{:?}", text);
+        }
+    }
+}
@@ -0,0 +1,70 @@
+//! 暂停与继续下载
+//!
+//! Unix 下每收到一次 `SIGUSR1` 切换一次暂停状态；指定控制文件时，文件存在期间保持暂停；仪表盘中可单独暂停一个块。
+//! 暂停时各任务停止读取响应体，连接保持打开，由 TCP 流控让服务器停止发送
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
+
+use indicatif::ProgressBar;
+use tokio::spawn;
+use tokio::time::sleep;
+
+use crate::message::Msg;
+use crate::tui::Chunk;
+use crate::Result;
+
+/// 检查暂停状态的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// 由信号切换的暂停状态
+static SIGNALED: AtomicBool = AtomicBool::new(false);
+/// 由控制文件决定的暂停状态
+static FILE_EXISTS: AtomicBool = AtomicBool::new(false);
+
+fn paused() -> bool {
+    SIGNALED.load(Ordering::Relaxed) || FILE_EXISTS.load(Ordering::Relaxed)
+}
+
+/// 开始监听暂停信号及控制文件
+pub fn watch(control_file: Option<PathBuf>) -> Result {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+
+        let mut signals = signal(SignalKind::user_defined1())?;
+        spawn(async move {
+            while signals.recv().await.is_some() {
+                SIGNALED.fetch_xor(true, Ordering::Relaxed);
+            }
+        });
+    }
+    if let Some(path) = control_file {
+        spawn(async move {
+            loop {
+                FILE_EXISTS.store(path.exists(), Ordering::Relaxed);
+                sleep(POLL_INTERVAL).await;
+            }
+        });
+    }
+    Ok(())
+}
+
+/// 暂停期间等待，`chunk` 为仪表盘中可单独暂停的块；进度条显示已暂停，继续后恢复原有消息
+pub async fn wait(bars: &[ProgressBar], chunk: Option<&Chunk>) {
+    let waiting = || paused() || chunk.is_some_and(Chunk::paused);
+    if !waiting() {
+        return;
+    }
+    let messages: Vec<_> = bars.iter().map(ProgressBar::message).collect();
+    for bar in bars {
+        bar.set_message(Msg::Paused.to_string());
+    }
+    while waiting() {
+        sleep(POLL_INTERVAL).await;
+    }
+    for (bar, message) in bars.iter().zip(messages) {
+        bar.set_message(message);
+    }
+}
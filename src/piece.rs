@@ -0,0 +1,228 @@
+use std::path::Path;
+use std::str::FromStr;
+use std::sync::atomic::Ordering;
+
+use anyhow::{anyhow, Error};
+use hyper::Uri;
+use tokio::fs::OpenOptions;
+
+use crate::checksum::{hash_file, Checksum};
+use crate::http::{add_download_bar, create_output, download_block, finish_file, job, wait_blocks};
+use crate::message::Msg;
+use crate::session::session;
+use crate::Result;
+
+/// 按片下标选择的下载范围，如 `0,3,5-7`
+pub struct Pieces {
+    /// 升序排列且互不重叠的片下标区间 `[start, end]`
+    indices: Vec<(usize, usize)>,
+}
+
+impl FromStr for Pieces {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut indices = Vec::new();
+        for t in s.split(',') {
+            let t = t.trim();
+            let (start, end) = t.split_once('-').unwrap_or((t, t));
+            match (start.trim().parse(), end.trim().parse()) {
+                (Ok(start), Ok(end)) if start <= end => indices.push((start, end)),
+                _ => return Err(anyhow!(Msg::InvalidPieces(t.to_string()))),
+            }
+        }
+        // 合并重叠或相邻的区间，连续的片只需一次请求
+        indices.sort_unstable();
+        let mut merged: Vec<(usize, usize)> = Vec::with_capacity(indices.len());
+        for (start, end) in indices {
+            match merged.last_mut() {
+                Some(last) if start <= last.1 + 1 => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        Ok(Self { indices: merged })
+    }
+}
+
+impl Pieces {
+    /// 换算为字节范围，返回各范围的起始位置与大小
+    ///
+    /// 最后一片可以不满 `piece_size`，超出总片数的下标返回错误
+    pub fn byte_ranges(
+        &self,
+        piece_size: usize,
+        content_length: usize,
+    ) -> Result<Vec<(usize, usize)>> {
+        let count = content_length.div_ceil(piece_size);
+        self.indices
+            .iter()
+            .map(|&(start, end)| {
+                if end >= count {
+                    return Err(anyhow!(Msg::PieceOutOfRange { index: end, count }));
+                }
+                let offset = start * piece_size;
+                Ok((
+                    offset,
+                    ((end + 1) * piece_size).min(content_length) - offset,
+                ))
+            })
+            .collect()
+    }
+}
+
+/// 按顺序排列的各片摘要，最后一片可以不满 `length`
+#[derive(Clone)]
+pub struct PieceHashes {
+    pub length: usize,
+    pub hashes: Vec<Checksum>,
+}
+
+impl PieceHashes {
+    /// 读取每行一个 `<algorithm>:<hex>` 的摘要文件，忽略空行
+    pub fn load(path: &Path, length: usize) -> Result<Self> {
+        let invalid = || anyhow!(Msg::InvalidPieceHashes(path.display().to_string()));
+        let text = std::fs::read_to_string(path).map_err(|e| invalid().context(e))?;
+        let hashes = text
+            .lines()
+            .map(str::trim)
+            .filter(|t| !t.is_empty())
+            .map(str::parse)
+            .collect::<Result<Vec<_>>>()
+            .map_err(|e| invalid().context(e))?;
+        if hashes.is_empty() {
+            return Err(invalid());
+        }
+        Ok(Self { length, hashes })
+    }
+
+    /// 确认片数与资源大小一致
+    pub fn check(&self, content_length: usize) -> Result {
+        let expected = content_length.div_ceil(self.length);
+        if self.hashes.len() != expected {
+            return Err(anyhow!(Msg::PieceHashCount {
+                expected,
+                actual: self.hashes.len(),
+            }));
+        }
+        Ok(())
+    }
+}
+
+/// 校验块中完整包含的各片，遇到不一致的片时将已写入的字节数退回到该片的起点并返回错误
+pub async fn verify(
+    index: (usize, usize),
+    (start, block_size): (usize, usize),
+    output: Option<&Path>,
+    written: &mut usize,
+) -> Result {
+    let session = session();
+    let hashes = match &session.config.piece_hashes {
+        None => return Ok(()),
+        Some(t) => t,
+    };
+    // 直接写入时按输出文件中的偏移读取，块文件从块的起点开始
+    let (path, base) = match output {
+        Some(t) => (t.to_path_buf(), 0),
+        None => (job().temp_dir.join(index.0.to_string()), start),
+    };
+    let end = start + block_size;
+    let content_length = job().resource_size.load(Ordering::Relaxed);
+    let first = start.div_ceil(hashes.length);
+    for (i, expected) in hashes.hashes.iter().enumerate().skip(first) {
+        let piece_start = i * hashes.length;
+        let piece_end = (piece_start + hashes.length).min(content_length);
+        if piece_end > end {
+            break;
+        }
+        let len = (piece_end - piece_start) as u64;
+        let actual = hash_file(&path, (piece_start - base) as u64, len, expected.hasher()).await?;
+        if expected.verify(&actual).is_err() {
+            *written = piece_start - start;
+            match (output, job().sidecar.get()) {
+                (Some(_), Some(sidecar)) => {
+                    sidecar.progress(index.0).store(*written, Ordering::Relaxed);
+                }
+                (Some(_), None) => {}
+                // 块文件的长度即已写入的字节数
+                (None, _) => {
+                    let file = OpenOptions::new().write(true).open(&path).await?;
+                    file.set_len(*written as u64).await?;
+                }
+            }
+            return Err(anyhow!(Msg::PieceMismatch {
+                task: index.1,
+                piece: i,
+            }));
+        }
+    }
+    Ok(())
+}
+
+/// 仅下载选中的片，写入预分配输出文件的对应偏移，其余部分保留为空洞
+///
+/// 文件不完整，因此不校验完整资源的摘要
+pub async fn download(
+    uri: &Uri,
+    content_length: usize,
+    ranges: &[(usize, usize)],
+    file_path: &str,
+) -> Result {
+    let part_path = create_output(file_path, content_length as u64).await?;
+    let mut handles = Vec::with_capacity(ranges.len());
+    for (i, &(start, block_size)) in ranges.iter().enumerate() {
+        let task_index = i + 1;
+        let bar = add_download_bar(block_size as u64, task_index)?;
+        handles.push(download_block(
+            uri.clone(),
+            (i, task_index),
+            start,
+            block_size,
+            Some(part_path.clone()),
+            bar,
+        ));
+    }
+    wait_blocks(handles).await?;
+    finish_file(&part_path, file_path).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn piece_hashes_load_and_check_count() {
+        let path = std::env::temp_dir().join(format!("download-pieces-{}", std::process::id()));
+        let digest = format!("sha256:{}", "0".repeat(64));
+        std::fs::write(&path, format!("{}\n\n{}\n", digest, digest)).unwrap();
+        let hashes = PieceHashes::load(&path, 10).unwrap();
+        assert_eq!(hashes.hashes.len(), 2);
+        assert!(hashes.check(11).is_ok());
+        assert!(hashes.check(20).is_ok());
+        assert!(hashes.check(21).is_err());
+        assert!(hashes.check(10).is_err());
+        std::fs::write(&path, "sha256:zz\n").unwrap();
+        assert!(PieceHashes::load(&path, 10).is_err());
+        std::fs::write(&path, "\n").unwrap();
+        assert!(PieceHashes::load(&path, 10).is_err());
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn pieces_merge_into_byte_ranges() {
+        for (pieces, expected) in [
+            ("0", &[(0, 10)][..]),
+            ("3,1-2", &[(10, 30)]),
+            ("0,2-3,3", &[(0, 10), (20, 20)]),
+            // 最后一片不满一片
+            ("4", &[(40, 5)]),
+        ] {
+            let pieces: Pieces = pieces.parse().unwrap();
+            assert_eq!(pieces.byte_ranges(10, 45).unwrap(), expected);
+        }
+        for pieces in ["", "a", "3-1", "1,-2"] {
+            assert!(pieces.parse::<Pieces>().is_err(), "{:?}", pieces);
+        }
+        let pieces: Pieces = "5".parse().unwrap();
+        assert!(pieces.byte_ranges(10, 45).is_err());
+    }
+}
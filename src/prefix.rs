@@ -0,0 +1,190 @@
+//! 以本地文件作为资源的前缀，只下载其余部分
+//!
+//! 使用前先下载前缀中均匀分布的若干段与本地文件比较，避免拼接出与远端资源不一致的文件
+
+use std::future::Future;
+use std::path::Path;
+use std::sync::atomic::Ordering;
+
+use anyhow::anyhow;
+use hyper::body::{to_bytes, Bytes, HttpBody};
+use hyper::header::CONTENT_RANGE;
+use hyper::{Method, StatusCode, Uri};
+use tokio::fs::{metadata, File, OpenOptions};
+use tokio::io::{copy, AsyncReadExt, AsyncSeekExt, AsyncWriteExt, SeekFrom};
+
+use crate::config::RangeMismatch;
+use crate::http::{
+    chunk_output, create_output, finish_file, follow, is_file_transfer, job, parse_content_range,
+    range_start, retrieve, spawn_blocks, verify_file, wait_blocks,
+};
+use crate::message::Msg;
+use crate::Result;
+
+/// 校验本地前缀时每段比较的字节数
+const PREFIX_SAMPLE: usize = 64 * 1024;
+
+/// 校验本地前缀时在整个前缀中均匀选取的段数，前缀不长于各段之和时整体比较
+const PREFIX_WINDOWS: usize = 8;
+
+/// 复制本地文件已有的前缀，仅下载其余部分
+pub async fn download(
+    size: usize,
+    uri: &Uri,
+    content_length: usize,
+    prefix: &Path,
+    file_path: &str,
+) -> Result {
+    let len = metadata(prefix).await?.len() as usize;
+    if len > content_length {
+        return Err(anyhow!(Msg::PartialTooLarge {
+            path: prefix.display().to_string(),
+            len,
+            total: content_length,
+        }));
+    }
+    check(uri, prefix, len).await?;
+    let part_path = create_output(file_path, content_length as u64).await?;
+    let mut from = File::open(prefix).await?.take(len as u64);
+    let mut to = OpenOptions::new().write(true).open(&part_path).await?;
+    copy(&mut from, &mut to).await?;
+    to.flush().await?;
+    if len < content_length {
+        let handles = spawn_blocks(uri, len, content_length, size, Some(&part_path))?;
+        let checksum = wait_blocks(handles).await?;
+        verify_file(&part_path, content_length, checksum.as_ref()).await?;
+    }
+    chunk_output(&part_path).await?;
+    finish_file(&part_path, file_path).await
+}
+
+/// 下载整个前缀中均匀分布的若干段与本地文件比较，确认本地文件与远端资源一致
+pub async fn check(uri: &Uri, prefix: &Path, len: usize) -> Result {
+    let windows = prefix_windows(len);
+    compare_windows(prefix, &windows, |start, size| {
+        fetch_window(uri, start, size)
+    })
+    .await
+}
+
+/// 在长度为 `len` 的前缀中选取比较的各段，首尾两段分别从前缀的开头及末尾开始
+fn prefix_windows(len: usize) -> Vec<(usize, usize)> {
+    if len == 0 {
+        return Vec::new();
+    }
+    if len <= PREFIX_WINDOWS * PREFIX_SAMPLE {
+        return vec![(0, len)];
+    }
+    let last = len - PREFIX_SAMPLE;
+    (0..PREFIX_WINDOWS)
+        .map(|i| (last * i / (PREFIX_WINDOWS - 1), PREFIX_SAMPLE))
+        .collect()
+}
+
+/// 用 `fetch` 获取远端的各段，与本地文件的对应部分比较，任意一段不一致时失败
+async fn compare_windows<F, Fut>(prefix: &Path, windows: &[(usize, usize)], fetch: F) -> Result
+where
+    F: Fn(usize, usize) -> Fut,
+    Fut: Future<Output = Result<Bytes>>,
+{
+    let mut file = File::open(prefix).await?;
+    for &(start, size) in windows {
+        let remote = fetch(start, size).await?;
+        file.seek(SeekFrom::Start(start as u64)).await?;
+        let mut local = vec![0; size];
+        file.read_exact(&mut local).await?;
+        if remote[..] != local[..] {
+            return Err(anyhow!(Msg::PrefixMismatch(prefix.display().to_string())));
+        }
+    }
+    Ok(())
+}
+
+/// 下载资源中从 `start` 开始的 `size` 个字节，响应的范围及资源总长度须与请求一致
+async fn fetch_window(uri: &Uri, start: usize, size: usize) -> Result<Bytes> {
+    if is_file_transfer(uri) {
+        // 数据连接读取到资源末尾为止，只取需要的部分
+        let mut body = retrieve(uri, start).await?;
+        let mut remote = Vec::new();
+        while remote.len() < size {
+            match body.data().await {
+                Some(bytes) => remote.extend_from_slice(&bytes?),
+                None => break,
+            }
+        }
+        remote.truncate(size);
+        return Ok(Bytes::from(remote));
+    }
+    let requested = (start, start + size - 1);
+    let range = format!("bytes={}-{}", requested.0, requested.1);
+    let (_, response) = follow(Method::GET, uri.clone(), Some(&range)).await?;
+    if response.status() != StatusCode::PARTIAL_CONTENT {
+        return Err(anyhow!(Msg::RequestFailed(response.status().to_string())));
+    }
+    let content_range = match response.headers().get(CONTENT_RANGE) {
+        None => return Err(anyhow!(Msg::HeaderMissing(CONTENT_RANGE.to_string()))),
+        Some(t) => t.to_str()?.to_string(),
+    };
+    let actual = parse_content_range(&content_range)
+        .ok_or_else(|| anyhow!(Msg::InvalidContentRange(content_range.clone())))?;
+    let total = job().resource_size.load(Ordering::Relaxed);
+    range_start(requested, total, actual, RangeMismatch::Strict)?;
+    Ok(to_bytes(response.into_body()).await?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn prefix_windows_span_whole_prefix() {
+        assert!(prefix_windows(0).is_empty());
+        assert_eq!(prefix_windows(100), [(0, 100)]);
+        let whole = PREFIX_WINDOWS * PREFIX_SAMPLE;
+        assert_eq!(prefix_windows(whole), [(0, whole)]);
+        let len = 100 * PREFIX_SAMPLE + 7;
+        let windows = prefix_windows(len);
+        assert_eq!(windows.len(), PREFIX_WINDOWS);
+        assert_eq!(windows[0], (0, PREFIX_SAMPLE));
+        assert_eq!(
+            windows[PREFIX_WINDOWS - 1],
+            (len - PREFIX_SAMPLE, PREFIX_SAMPLE)
+        );
+        // 相邻两段之间的间隔不超过前缀长度的一段平均值
+        for pair in windows.windows(2) {
+            assert!(pair[1].0 > pair[0].0 + PREFIX_SAMPLE, "{:?}", pair);
+            assert!(
+                pair[1].0 - pair[0].0 <= len / (PREFIX_WINDOWS - 1),
+                "{:?}",
+                pair
+            );
+        }
+    }
+
+    #[tokio::test]
+    async fn compare_windows_rejects_corrupted_middle() {
+        let len = 100 * PREFIX_SAMPLE;
+        let remote: Vec<u8> = (0..len).map(|i| (i % 251) as u8).collect();
+        let windows = prefix_windows(len);
+        let path = std::env::temp_dir().join(format!("download-prefix-{}", std::process::id()));
+        let fetch = |start: usize, size: usize| {
+            let bytes = Bytes::copy_from_slice(&remote[start..start + size]);
+            async move { Ok(bytes) }
+        };
+
+        tokio::fs::write(&path, &remote).await.unwrap();
+        assert!(compare_windows(&path, &windows, fetch).await.is_ok());
+
+        // 损坏中间某段，首尾保持不变
+        let mut local = remote.clone();
+        let (middle, _) = windows[PREFIX_WINDOWS / 2];
+        local[middle + 10] ^= 0xff;
+        tokio::fs::write(&path, &local).await.unwrap();
+        let result = compare_windows(&path, &windows, fetch).await;
+        tokio::fs::remove_file(&path).await.unwrap();
+        assert!(matches!(
+            result.unwrap_err().downcast_ref(),
+            Some(Msg::PrefixMismatch(_))
+        ));
+    }
+}
@@ -0,0 +1,196 @@
+//! 不绘制进度条时的进度输出
+//!
+//! 进度仍记录在隐藏的 `ProgressBar` 中，后台线程定期读取。`--progress json` 时每行一个 JSON 对象写入标准输出，
+//! 块的位置变化时输出 `chunk_progress`，下载完成时输出 `chunk_done`；`--progress plain` 时定期向标准错误输出一行
+//! 总进度，适合 CI 日志
+
+use std::io::{stdout, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use indicatif::{HumanBytes, ProgressBar};
+use lazy_static::lazy_static;
+use serde_json::{json, Value};
+
+use crate::config::Progress;
+use crate::message::Msg;
+
+/// JSON 事件读取进度的间隔
+const JSON_TICK: Duration = Duration::from_millis(500);
+/// 纯文本输出总进度的间隔
+const PLAIN_TICK: Duration = Duration::from_secs(5);
+
+lazy_static! {
+    static ref BARS: Mutex<Vec<Tracked>> = Mutex::new(Vec::new());
+    static ref THREAD: Mutex<Option<JoinHandle<()>>> = Mutex::new(None);
+}
+
+static RUNNING: AtomicBool = AtomicBool::new(false);
+static PLAIN: AtomicBool = AtomicBool::new(false);
+
+/// 跟踪的进度条及上次输出的位置
+struct Tracked {
+    bar: ProgressBar,
+    file: String,
+    /// 块号，合并进度条为空
+    chunk: Option<usize>,
+    reported: Option<u64>,
+}
+
+/// 按 `mode` 启动读取进度的线程，绘制进度条时不启动
+pub fn start(mode: Progress) {
+    let tick = match mode {
+        Progress::Bars => return,
+        Progress::Json => JSON_TICK,
+        Progress::Plain => PLAIN_TICK,
+    };
+    PLAIN.store(mode == Progress::Plain, Ordering::SeqCst);
+    RUNNING.store(true, Ordering::SeqCst);
+    *THREAD.lock().unwrap() = Some(thread::spawn(move || {
+        let mut last = (Instant::now(), 0);
+        while active() {
+            // 分段休眠，停止时不必等满一个间隔
+            let deadline = Instant::now() + tick;
+            while active() && Instant::now() < deadline {
+                thread::sleep(Duration::from_millis(100));
+            }
+            if PLAIN.load(Ordering::SeqCst) {
+                print_plain(&mut last);
+            } else {
+                report();
+            }
+        }
+    }));
+}
+
+/// 停止读取进度的线程，并输出尚未输出的进度
+pub fn stop() {
+    if !RUNNING.swap(false, Ordering::SeqCst) {
+        return;
+    }
+    if let Some(handle) = THREAD.lock().unwrap().take() {
+        let _ = handle.join();
+    }
+    if !PLAIN.load(Ordering::SeqCst) {
+        report();
+    }
+}
+
+pub fn active() -> bool {
+    RUNNING.load(Ordering::SeqCst)
+}
+
+/// 跟踪文件 `file` 第 `chunk` 块的进度
+pub fn add(bar: &ProgressBar, file: String, chunk: usize) {
+    track(bar, file, Some(chunk));
+}
+
+/// 跟踪合并文件 `file` 的进度，只用于纯文本输出
+pub fn add_merge(bar: &ProgressBar, file: String) {
+    if PLAIN.load(Ordering::SeqCst) {
+        track(bar, file, None);
+    }
+}
+
+fn track(bar: &ProgressBar, file: String, chunk: Option<usize>) {
+    BARS.lock().unwrap().push(Tracked {
+        bar: bar.clone(),
+        file,
+        chunk,
+        reported: None,
+    });
+}
+
+/// 输出一个 JSON 事件，`event` 为事件名，`fields` 为其余字段；先输出各块尚未输出的进度，保证事件的先后顺序
+pub fn emit(event: &str, fields: Value) {
+    report();
+    write(event, fields);
+}
+
+fn write(event: &str, fields: Value) {
+    let mut value = json!({ "event": event });
+    if let (Some(object), Value::Object(fields)) = (value.as_object_mut(), fields) {
+        object.extend(fields);
+    }
+    let mut out = stdout().lock();
+    let _ = writeln!(out, "{}", value);
+    let _ = out.flush();
+}
+
+/// 输出位置变化的块的进度，完成的块输出 `chunk_done` 后不再跟踪
+fn report() {
+    BARS.lock().unwrap().retain_mut(|tracked| {
+        let chunk = match tracked.chunk {
+            Some(t) => t,
+            None => return false,
+        };
+        let position = tracked.bar.position();
+        let total = tracked.bar.length().unwrap_or_default();
+        if tracked.reported != Some(position) {
+            tracked.reported = Some(position);
+            write(
+                "chunk_progress",
+                json!({
+                    "file": tracked.file,
+                    "chunk": chunk,
+                    "downloaded": position,
+                    "total": total,
+                }),
+            );
+        }
+        if position < total && !tracked.bar.is_finished() {
+            return true;
+        }
+        // 放弃的块结束时未下载完，不算完成
+        if position >= total {
+            write(
+                "chunk_done",
+                json!({ "file": tracked.file, "chunk": chunk, "size": total }),
+            );
+        }
+        false
+    });
+}
+
+/// 输出一行总进度，正在合并时输出合并进度；`last` 为上次输出的时间及已下载字节数，用于计算速度
+fn print_plain(last: &mut (Instant, u64)) {
+    let mut bars = BARS.lock().unwrap();
+    bars.retain(|t| t.chunk.is_some() || !t.bar.is_finished());
+    if let Some(merge) = bars.iter().find(|t| t.chunk.is_none()) {
+        let percent = percent(merge.bar.position(), merge.bar.length().unwrap_or_default());
+        eprintln!("{}", Msg::PlainMerging(percent));
+        return;
+    }
+    let (position, length) = bars.iter().fold((0, 0), |(position, length), t| {
+        (
+            position + t.bar.position(),
+            length + t.bar.length().unwrap_or_default(),
+        )
+    });
+    // 下载完成后只输出一次
+    if length == 0 || (position == length && last.1 == position) {
+        return;
+    }
+    let now = Instant::now();
+    let speed = position.saturating_sub(last.1) as f64 / (now - last.0).as_secs_f64();
+    *last = (now, position);
+    eprintln!(
+        "{}",
+        Msg::PlainProgress {
+            percent: percent(position, length),
+            downloaded: HumanBytes(position).to_string(),
+            total: HumanBytes(length).to_string(),
+            speed: HumanBytes(speed as u64).to_string(),
+        }
+    );
+}
+
+fn percent(position: u64, length: u64) -> f64 {
+    if length == 0 {
+        0.0
+    } else {
+        (position as f64 * 100.0 / length as f64).min(100.0)
+    }
+}
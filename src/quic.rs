@@ -0,0 +1,228 @@
+//! 实验性的 HTTP/3 客户端
+//!
+//! 通过 QUIC 连接服务器，同一主机的请求复用一个连接，响应体转为 hyper 的 `Body`，与 HTTP/1.1、HTTP/2
+//! 共用写入、限速、trailer 及超时的处理。HTTP/3 只用于 https，http 及经代理的请求仍通过 TCP 发送；
+//! 需启用 `http3` 功能
+
+use std::collections::HashMap;
+use std::future::poll_fn;
+use std::net::SocketAddr;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use h3::client::SendRequest;
+use hyper::body::{to_bytes, Buf, Bytes};
+use hyper::header::{HeaderName, HeaderValue};
+use hyper::{Body, HeaderMap, Request, Response, Uri, Version};
+use quinn::crypto::rustls::QuicClientConfig;
+use quinn::Endpoint;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::CertificateDer;
+use tokio::net::lookup_host;
+use tokio::sync::Mutex;
+use tokio::time::timeout;
+
+use crate::connector::Connector;
+use crate::message::Msg;
+use crate::proxy::Proxies;
+use crate::transport::{HttpClient, ResponseFuture};
+use crate::Result;
+
+/// 到一个主机的 HTTP/3 连接
+struct Connection {
+    /// 连接关闭前保持 endpoint
+    _endpoint: Endpoint,
+    sender: SendRequest<h3_quinn::OpenStreams, Bytes>,
+}
+
+/// 按主机复用 QUIC 连接的 HTTP/3 客户端
+pub struct Client {
+    config: quinn::ClientConfig,
+    /// TLS 握手时使用的服务器名称，为空时使用 URI 中的主机名
+    server_name: Option<String>,
+    proxies: Proxies,
+    connect_timeout: Option<Duration>,
+    /// http 及经代理的请求使用的 TCP 客户端
+    fallback: hyper::Client<Connector>,
+    /// 各主机（`host:port`）的连接，连接出错后移除，下次请求时重新建立
+    connections: Mutex<HashMap<String, Connection>>,
+}
+
+impl Client {
+    pub fn new(
+        server_name: Option<String>,
+        proxies: Proxies,
+        connect_timeout: Option<Duration>,
+    ) -> Result<Self> {
+        let connector = Connector::new(
+            server_name.clone(),
+            &["h2", "http/1.1"],
+            proxies.clone(),
+            connect_timeout,
+        )?;
+        let mut tls = rustls::ClientConfig::builder_with_provider(Arc::new(
+            rustls::crypto::ring::default_provider(),
+        ))
+        .with_protocol_versions(&[&rustls::version::TLS13])?
+        .with_root_certificates(roots()?)
+        .with_no_client_auth();
+        tls.alpn_protocols = vec![b"h3".to_vec()];
+        Ok(Self {
+            config: quinn::ClientConfig::new(Arc::new(QuicClientConfig::try_from(tls)?)),
+            server_name,
+            proxies,
+            connect_timeout,
+            fallback: hyper::Client::builder().build(connector),
+            connections: Mutex::new(HashMap::new()),
+        })
+    }
+
+    /// 取得到 `uri` 所在主机的连接，没有时建立
+    async fn sender(
+        &self,
+        uri: &Uri,
+    ) -> Result<(String, SendRequest<h3_quinn::OpenStreams, Bytes>)> {
+        let host = uri
+            .host()
+            .unwrap_or("")
+            .trim_matches(|c| c == '[' || c == ']');
+        let port = uri.port_u16().unwrap_or(443);
+        let key = format!("{}:{}", host, port);
+        // 建立连接期间持有锁，同一主机的并发请求共用一个连接
+        let mut connections = self.connections.lock().await;
+        if let Some(connection) = connections.get(&key) {
+            return Ok((key, connection.sender.clone()));
+        }
+        let server_name = self.server_name.as_deref().unwrap_or(host);
+        let connect = self.connect(host, port, server_name);
+        let connection = match self.connect_timeout {
+            None => connect.await?,
+            Some(t) => timeout(t, connect)
+                .await
+                .map_err(|_| anyhow!(Msg::ConnectTimeout(t)))??,
+        };
+        let sender = connection.sender.clone();
+        connections.insert(key.clone(), connection);
+        Ok((key, sender))
+    }
+
+    async fn connect(&self, host: &str, port: u16, server_name: &str) -> Result<Connection> {
+        let address = lookup_host((host, port))
+            .await?
+            .next()
+            .ok_or_else(|| anyhow!(Msg::ResolveFailed(host.to_string())))?;
+        let local: SocketAddr = match address {
+            SocketAddr::V4(_) => "0.0.0.0:0",
+            SocketAddr::V6(_) => "[::]:0",
+        }
+        .parse()?;
+        let mut endpoint = Endpoint::client(local)?;
+        endpoint.set_default_client_config(self.config.clone());
+        let connection = endpoint.connect(address, server_name)?.await?;
+        let (mut driver, sender) = h3::client::new(h3_quinn::Connection::new(connection)).await?;
+        tokio::spawn(async move {
+            let _ = poll_fn(|cx| driver.poll_close(cx)).await;
+        });
+        Ok(Connection {
+            _endpoint: endpoint,
+            sender,
+        })
+    }
+
+    async fn send(&self, request: Request<Body>) -> Result<Response<Body>> {
+        let (parts, body) = request.into_parts();
+        let body = to_bytes(body).await?;
+        let mut builder = http::Request::builder()
+            .method(parts.method.as_str())
+            .uri(parts.uri.to_string());
+        for (name, value) in &parts.headers {
+            builder = builder.header(name.as_str(), value.as_bytes());
+        }
+        let request = builder.body(())?;
+
+        let (key, mut sender) = self.sender(&parts.uri).await?;
+        let exchange = async {
+            let mut stream = sender.send_request(request).await?;
+            if !body.is_empty() {
+                stream.send_data(body).await?;
+            }
+            stream.finish().await?;
+            let response = stream.recv_response().await?;
+            Ok::<_, anyhow::Error>((stream, response))
+        };
+        let (mut stream, response) = match exchange.await {
+            Ok(t) => t,
+            Err(e) => {
+                self.connections.lock().await.remove(&key);
+                return Err(e);
+            }
+        };
+
+        let (mut body_sender, body) = Body::channel();
+        tokio::spawn(async move {
+            loop {
+                match stream.recv_data().await {
+                    Ok(Some(mut data)) => {
+                        let bytes = data.copy_to_bytes(data.remaining());
+                        if body_sender.send_data(bytes).await.is_err() {
+                            stream.stop_sending(h3::error::Code::H3_REQUEST_CANCELLED);
+                            return;
+                        }
+                    }
+                    Ok(None) => break,
+                    Err(_) => return body_sender.abort(),
+                }
+            }
+            match stream.recv_trailers().await {
+                Ok(Some(trailers)) => {
+                    let _ = body_sender.send_trailers(headers(&trailers)).await;
+                }
+                Ok(None) => {}
+                Err(_) => body_sender.abort(),
+            }
+        });
+        let mut builder = Response::builder()
+            .status(response.status().as_u16())
+            .version(Version::HTTP_3);
+        if let Some(t) = builder.headers_mut() {
+            *t = headers(response.headers());
+        }
+        Ok(builder.body(body)?)
+    }
+}
+
+impl HttpClient for Client {
+    fn request(&self, request: Request<Body>) -> ResponseFuture<'_> {
+        let uri = request.uri();
+        if uri.scheme_str() != Some("https") || self.proxies.select(uri).is_some() {
+            let response = self.fallback.request(request);
+            return Box::pin(async move { Ok(response.await?) });
+        }
+        Box::pin(self.send(request))
+    }
+}
+
+/// 把 h3 使用的 `http` 1.x 头部转为 hyper 的头部，无法转换的忽略
+fn headers(source: &http::HeaderMap) -> HeaderMap {
+    source
+        .iter()
+        .filter_map(|(name, value)| {
+            let name = HeaderName::from_bytes(name.as_str().as_bytes()).ok()?;
+            let value = HeaderValue::from_bytes(value.as_bytes()).ok()?;
+            Some((name, value))
+        })
+        .collect()
+}
+
+/// 信任的根证书：内置的 Mozilla 根证书，以及 `SSL_CERT_FILE` 中的证书
+fn roots() -> Result<rustls::RootCertStore> {
+    let mut roots = rustls::RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(path) = std::env::var_os("SSL_CERT_FILE") {
+        for cert in CertificateDer::pem_file_iter(&path)? {
+            roots.add(cert?)?;
+        }
+    }
+    Ok(roots)
+}
@@ -0,0 +1,206 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::{Duration, Instant};
+
+use anyhow::Error;
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 重试调度器，退避等待不会超过全局截止时间
+pub struct Retry {
+    /// 最大重试次数
+    pub attempts: usize,
+    /// 首次重试的等待时间，之后每次翻倍
+    pub base_delay: Duration,
+    /// 单次重试的最大等待时间
+    pub max_delay: Duration,
+    /// 所有任务合计的最大重试次数，每个任务仍各自计算 `attempts`
+    pub max_overall: Option<usize>,
+    /// 全局截止时间
+    pub deadline: Option<Instant>,
+    /// 首次请求的超时时间
+    pub timeout: Option<Duration>,
+    /// 每次重试时超时时间的增长倍数
+    pub timeout_backoff: f64,
+    /// 超时时间的上限
+    pub timeout_cap: Option<Duration>,
+    /// 同一传输方式或镜像连续失败多少次后切换到下一个
+    pub switch_after: usize,
+    /// 每个任务按 `Retry-After` 等待的合计时间上限
+    pub max_retry_after: Duration,
+    /// 本次运行所有任务已进行的重试次数
+    pub overall: AtomicUsize,
+}
+
+impl Retry {
+    /// 计算第 `attempt` 次重试前的等待时间，在指数退避的后一半范围内随机取值，避免各任务同时重试
+    ///
+    /// 重试次数用尽，或剩余时间不足以完成等待时，直接返回 `error`
+    pub fn backoff(&self, attempt: usize, error: Error) -> Result<Duration> {
+        // 资源已经变化，重试也无法得到一致的内容
+        if attempt > self.attempts || matches!(error.downcast_ref(), Some(Msg::ResourceChanged)) {
+            return Err(error);
+        }
+        if let Some(max) = self.max_overall {
+            if self.overall.fetch_add(1, Ordering::Relaxed) >= max {
+                return Err(error.context(Msg::OverallRetriesExhausted(max)));
+            }
+        }
+        let delay = self
+            .base_delay
+            .saturating_mul(1 << (attempt - 1).min(16))
+            .min(self.max_delay);
+        let delay = delay / 2 + delay.mul_f64(jitter() / 2.0);
+        if let Some(deadline) = self.deadline {
+            let remaining = deadline.saturating_duration_since(Instant::now());
+            if remaining <= delay {
+                return Err(error.context(Msg::RetryTimeExhausted(remaining)));
+            }
+        }
+        Ok(delay)
+    }
+
+    /// 服务器通过 `Retry-After` 要求稍后重试时返回等待时间并计入 `waited`，该等待不计入重试次数
+    ///
+    /// 合计等待超过上限或剩余时间不足时返回 `None`，按普通失败处理
+    pub fn retry_after(&self, error: &Error, waited: &mut Duration) -> Option<Duration> {
+        let delay = match error.downcast_ref::<Msg>() {
+            Some(Msg::RetryAfter { delay, .. }) => *delay,
+            _ => return None,
+        };
+        if *waited + delay > self.max_retry_after {
+            return None;
+        }
+        if let Some(deadline) = self.deadline {
+            if deadline.saturating_duration_since(Instant::now()) <= delay {
+                return None;
+            }
+        }
+        *waited += delay;
+        Some(delay)
+    }
+
+    /// 第 `attempt` 次重试（`0` 为首次请求）使用的超时时间
+    pub fn timeout(&self, attempt: usize) -> Option<Duration> {
+        let secs = self.timeout?.as_secs_f64() * self.timeout_backoff.powi(attempt.min(64) as i32);
+        let timeout = Duration::try_from_secs_f64(secs).unwrap_or(Duration::MAX);
+        Some(match self.timeout_cap {
+            Some(cap) => timeout.min(cap),
+            None => timeout,
+        })
+    }
+
+    /// 第 `attempt` 次重试使用的传输方式下标，每失败 `switch_after` 次轮换一次
+    pub fn transport(&self, attempt: usize, transports: usize) -> usize {
+        attempt / self.switch_after % transports
+    }
+}
+
+/// `[0, 1)` 内的随机数，每个 `RandomState` 使用不同的随机种子
+fn jitter() -> f64 {
+    let mut hasher = RandomState::new().build_hasher();
+    hasher.write_u8(0);
+    (hasher.finish() >> 11) as f64 / (1u64 << 53) as f64
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::anyhow;
+
+    use super::*;
+
+    fn settings() -> Retry {
+        Retry {
+            attempts: 5,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(4),
+            max_overall: None,
+            deadline: None,
+            timeout: Some(Duration::from_secs(10)),
+            timeout_backoff: 2.0,
+            timeout_cap: Some(Duration::from_secs(30)),
+            switch_after: 2,
+            max_retry_after: Duration::from_secs(5),
+            overall: AtomicUsize::new(0),
+        }
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_max_delay() {
+        let retry = settings();
+        for (attempt, delay) in [(1, 1), (2, 2), (3, 4), (4, 4), (5, 4)] {
+            let delay = Duration::from_secs(delay);
+            let actual = retry.backoff(attempt, anyhow!("failed")).unwrap();
+            assert!(delay / 2 <= actual && actual <= delay, "{:?}", attempt);
+        }
+        assert!(retry.backoff(6, anyhow!("failed")).is_err());
+        assert!(retry.backoff(1, anyhow!(Msg::ResourceChanged)).is_err());
+    }
+
+    #[test]
+    fn backoff_respects_overall_limit_and_deadline() {
+        let retry = Retry {
+            max_overall: Some(2),
+            ..settings()
+        };
+        assert!(retry.backoff(1, anyhow!("failed")).is_ok());
+        assert!(retry.backoff(1, anyhow!("failed")).is_ok());
+        let error = retry.backoff(1, anyhow!("failed")).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(Msg::OverallRetriesExhausted(2))
+        ));
+
+        let retry = Retry {
+            deadline: Some(Instant::now() + Duration::from_millis(100)),
+            ..settings()
+        };
+        let error = retry.backoff(1, anyhow!("failed")).unwrap_err();
+        assert!(matches!(
+            error.downcast_ref(),
+            Some(Msg::RetryTimeExhausted(_))
+        ));
+    }
+
+    #[test]
+    fn retry_after_accumulates_until_limit() {
+        let retry = settings();
+        let error = |secs| {
+            anyhow!(Msg::RetryAfter {
+                status: "503".to_string(),
+                delay: Duration::from_secs(secs),
+            })
+        };
+        let mut waited = Duration::ZERO;
+        assert_eq!(
+            retry.retry_after(&error(2), &mut waited),
+            Some(Duration::from_secs(2))
+        );
+        assert_eq!(
+            retry.retry_after(&error(3), &mut waited),
+            Some(Duration::from_secs(3))
+        );
+        assert_eq!(retry.retry_after(&error(1), &mut waited), None);
+        assert_eq!(waited, Duration::from_secs(5));
+        assert_eq!(retry.retry_after(&anyhow!("failed"), &mut waited), None);
+    }
+
+    #[test]
+    fn timeout_grows_and_transport_rotates() {
+        let retry = settings();
+        let secs = |attempt| retry.timeout(attempt).map(|t| t.as_secs());
+        assert_eq!(
+            [secs(0), secs(1), secs(2), secs(64)],
+            [Some(10), Some(20), Some(30), Some(30)]
+        );
+        let transports: Vec<usize> = (0..6).map(|t| retry.transport(t, 2)).collect();
+        assert_eq!(transports, [0, 0, 1, 1, 0, 0]);
+        let retry = Retry {
+            timeout: None,
+            ..retry
+        };
+        assert_eq!(retry.timeout(3), None);
+    }
+}
@@ -0,0 +1,42 @@
+//! 多个下载共用的并发限制
+//!
+//! 批量下载及守护进程中，`--max-concurrent-downloads` 限制同时进行的下载数，其余下载排队等待；
+//! `--max-connections-total` 限制所有下载合计的连接数，各块领取到连接后才发出请求
+
+use std::sync::OnceLock;
+
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+use crate::Result;
+
+static DOWNLOADS: OnceLock<Semaphore> = OnceLock::new();
+static CONNECTIONS: OnceLock<Semaphore> = OnceLock::new();
+
+/// 设置同时进行的下载数及合计连接数的上限，为空时不限制；只在首次设置时生效
+pub fn init(downloads: Option<usize>, connections: Option<usize>) {
+    if let Some(t) = downloads {
+        let _ = DOWNLOADS.set(Semaphore::new(t));
+    }
+    if let Some(t) = connections {
+        let _ = CONNECTIONS.set(Semaphore::new(t));
+    }
+}
+
+/// 占用一个下载名额，名额用完时排队等待；未限制时返回 `None`
+pub async fn acquire_download() -> Result<Option<SemaphorePermit<'static>>> {
+    acquire(&DOWNLOADS).await
+}
+
+/// 占用一个连接，未限制连接数时返回 `None`
+pub async fn acquire_connection() -> Result<Option<SemaphorePermit<'static>>> {
+    acquire(&CONNECTIONS).await
+}
+
+async fn acquire(
+    semaphore: &'static OnceLock<Semaphore>,
+) -> Result<Option<SemaphorePermit<'static>>> {
+    match semaphore.get() {
+        None => Ok(None),
+        Some(t) => Ok(Some(t.acquire().await?)),
+    }
+}
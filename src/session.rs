@@ -0,0 +1,88 @@
+//! 一次运行共用的配置、HTTP 客户端及各存储服务的认证状态
+//!
+//! 命令行程序每次运行使用一个会话；[`Downloader`](crate::Downloader) 每次下载各建一个，重复及并发的下载互不影响
+
+use std::sync::Arc;
+
+use hyper::header::{HeaderName, HeaderValue, AUTHORIZATION};
+use hyper::{Method, Uri};
+use tokio::task_local;
+
+use crate::azure;
+use crate::config::{Backend, Config};
+use crate::gcs;
+use crate::interrupt;
+use crate::s3;
+use crate::transport::HttpClient;
+use crate::webdav;
+use crate::Result;
+
+pub struct Session {
+    pub config: Config,
+    /// 各传输方式的 HTTPS 客户端，与 `config.transports` 一一对应
+    pub clients: Vec<Arc<dyn HttpClient>>,
+    pub s3: s3::State,
+    pub gcs: gcs::State,
+    pub azure: azure::State,
+    pub webdav: webdav::State,
+}
+
+impl Session {
+    pub fn new(config: Config) -> Result<Arc<Self>> {
+        let clients = config
+            .transports
+            .iter()
+            .map(|t| {
+                let client = t.client(
+                    config.server_name.clone(),
+                    config.proxy.clone(),
+                    config.connect_timeout,
+                )?;
+                Ok(Arc::from(client))
+            })
+            .collect::<Result<_>>()?;
+        Ok(Arc::new(Self {
+            config,
+            clients,
+            s3: s3::State::default(),
+            gcs: gcs::State::default(),
+            azure: azure::State::default(),
+            webdav: webdav::State::default(),
+        }))
+    }
+
+    /// 把存储服务的地址转为实际请求的 HTTP 地址，之后发往该地址的请求带上对应的认证
+    pub async fn resolve(&self, uri: &Uri) -> Result<Uri> {
+        match uri.scheme_str() {
+            Some("s3") => self.s3.resolve(uri),
+            Some("gs") => interrupt::guard(self.gcs.resolve(uri, self.clients[0].clone())).await,
+            Some("az") => self.azure.resolve(uri),
+            _ if self.config.backend == Some(Backend::Azure) => self.azure.resolve(uri),
+            _ if self.is_webdav(uri) => self.webdav.resolve(uri),
+            _ => Ok(uri.clone()),
+        }
+    }
+
+    /// 是否为 WebDAV 地址，需通过 `PROPFIND` 获取大小
+    pub fn is_webdav(&self, uri: &Uri) -> bool {
+        matches!(uri.scheme_str(), Some("dav" | "davs"))
+            || self.config.backend == Some(Backend::WebDav)
+    }
+
+    /// 存储服务的签名或令牌，替换其他认证方式
+    pub fn authorization(&self, method: &Method, uri: &Uri) -> Vec<(HeaderName, HeaderValue)> {
+        let mut headers = self.s3.sign(method, uri);
+        headers.extend(self.gcs.authorization(uri).map(|t| (AUTHORIZATION, t)));
+        headers.extend(self.webdav.authorization(uri).map(|t| (AUTHORIZATION, t)));
+        headers
+    }
+}
+
+task_local! {
+    /// 当前任务所属的会话，启动新任务时需一并传入
+    pub static SESSION: Arc<Session>;
+}
+
+pub fn session() -> Arc<Session> {
+    SESSION.with(Arc::clone)
+}
@@ -0,0 +1,234 @@
+//! SFTP 下载
+//!
+//! 与 FTP 一样，每个块各自建立 SSH 连接并登录，打开文件后从块的起点读取，各块并行读取不同的偏移。
+//! 读取的内容转为 hyper 的 `Body`，与 HTTP 下载共用写入、限速及超时的处理；需启用 `sftp` 功能
+
+use std::path::Path;
+use std::time::Duration;
+
+use anyhow::anyhow;
+use hyper::{Body, Uri};
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 获取资源大小，`key` 为 `--ssh-key` 指定的私钥
+#[cfg(feature = "sftp")]
+pub async fn probe(
+    uri: &Uri,
+    connect_timeout: Option<Duration>,
+    key: Option<&Path>,
+) -> Result<usize> {
+    let session = connect(uri, connect_timeout, key).await?;
+    let metadata = session.sftp.metadata(path(uri)).await?;
+    Ok(metadata.size.unwrap_or_default() as usize)
+}
+
+/// 登录并从 `offset` 开始读取资源，读取到末尾为止
+#[cfg(feature = "sftp")]
+pub async fn retrieve(
+    uri: &Uri,
+    offset: usize,
+    connect_timeout: Option<Duration>,
+    key: Option<&Path>,
+) -> Result<Body> {
+    use hyper::body::Bytes;
+    use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+
+    let session = connect(uri, connect_timeout, key).await?;
+    let mut file = session.sftp.open(path(uri)).await?;
+    if offset > 0 {
+        file.seek(SeekFrom::Start(offset as u64)).await?;
+    }
+    let (mut sender, body) = Body::channel();
+    tokio::spawn(async move {
+        // 读取完成前保持连接
+        let _session = session;
+        let mut buffer = vec![0; BUFFER_SIZE];
+        loop {
+            match file.read(&mut buffer).await {
+                Ok(0) => break,
+                Ok(n) => {
+                    let bytes = Bytes::copy_from_slice(&buffer[..n]);
+                    if sender.send_data(bytes).await.is_err() {
+                        break;
+                    }
+                }
+                Err(_) => {
+                    sender.abort();
+                    break;
+                }
+            }
+        }
+    });
+    Ok(body)
+}
+
+/// 未启用 `sftp` 功能时无法下载
+#[cfg(not(feature = "sftp"))]
+pub async fn probe(
+    _uri: &Uri,
+    _connect_timeout: Option<Duration>,
+    _key: Option<&Path>,
+) -> Result<usize> {
+    Err(anyhow!(Msg::SftpUnsupported))
+}
+
+#[cfg(not(feature = "sftp"))]
+pub async fn retrieve(
+    _uri: &Uri,
+    _offset: usize,
+    _connect_timeout: Option<Duration>,
+    _key: Option<&Path>,
+) -> Result<Body> {
+    Err(anyhow!(Msg::SftpUnsupported))
+}
+
+/// SSH 的默认端口
+#[cfg(feature = "sftp")]
+const PORT: u16 = 22;
+/// 每次读取的大小
+#[cfg(feature = "sftp")]
+const BUFFER_SIZE: usize = 64 * 1024;
+/// 未指定 `--ssh-key` 时依次尝试的私钥
+#[cfg(feature = "sftp")]
+const DEFAULT_KEYS: [&str; 3] = ["id_ed25519", "id_ecdsa", "id_rsa"];
+
+/// 已登录并打开 SFTP 子系统的连接
+#[cfg(feature = "sftp")]
+struct Session {
+    sftp: russh_sftp::client::SftpSession,
+    _handle: russh::client::Handle<Client>,
+}
+
+/// 按 `~/.ssh/known_hosts` 确认服务器的公钥
+#[cfg(feature = "sftp")]
+struct Client {
+    host: String,
+    port: u16,
+}
+
+#[cfg(feature = "sftp")]
+impl russh::client::Handler for Client {
+    type Error = anyhow::Error;
+
+    async fn check_server_key(
+        &mut self,
+        server_public_key: &russh::keys::PublicKeyOrCertificate,
+    ) -> Result<bool> {
+        let key = match server_public_key {
+            russh::keys::PublicKeyOrCertificate::PublicKey { key, .. } => key.clone(),
+            russh::keys::PublicKeyOrCertificate::Certificate(t) => t.public_key().clone().into(),
+        };
+        match russh::keys::check_known_hosts(&self.host, self.port, &key) {
+            Ok(true) => Ok(true),
+            Err(russh::keys::Error::KeyChanged { line }) => Err(anyhow!(Msg::SshHostKeyChanged {
+                host: self.host.clone(),
+                line,
+            })),
+            // 没有 known_hosts 文件时同样视为未知的服务器
+            _ => Err(anyhow!(Msg::SshUnknownHost {
+                host: self.host.clone(),
+                port: self.port,
+            })),
+        }
+    }
+}
+
+/// 连接服务器并登录，URI 中有密码时使用密码，否则依次尝试 `--ssh-key` 或 `~/.ssh` 下的默认私钥
+#[cfg(feature = "sftp")]
+async fn connect(
+    uri: &Uri,
+    connect_timeout: Option<Duration>,
+    key: Option<&Path>,
+) -> Result<Session> {
+    use std::sync::Arc;
+
+    use crate::proxy::percent_decode;
+
+    let authority = uri.authority().map(|t| t.as_str()).unwrap_or_default();
+    let decode = |t| String::from_utf8_lossy(&percent_decode(t)).into_owned();
+    let (user, password) = match authority.rsplit_once('@') {
+        None => (default_user(), None),
+        Some((userinfo, _)) => match userinfo.split_once(':') {
+            Some((user, password)) => (decode(user), Some(decode(password))),
+            None => (decode(userinfo), None),
+        },
+    };
+    let host = uri.host().unwrap_or_default();
+    let host = host.trim_matches(|c| c == '[' || c == ']').to_string();
+    let port = uri.port_u16().unwrap_or(PORT);
+    let client = Client {
+        host: host.clone(),
+        port,
+    };
+    let config = Arc::new(russh::client::Config::default());
+    let connect = russh::client::connect(config, (host, port), client);
+    let mut handle = match connect_timeout {
+        None => connect.await?,
+        Some(t) => tokio::time::timeout(t, connect)
+            .await
+            .map_err(|_| anyhow!(Msg::ConnectTimeout(t)))??,
+    };
+    let authenticated = match password {
+        Some(password) => handle
+            .authenticate_password(&user, password)
+            .await?
+            .success(),
+        None => {
+            let mut authenticated = false;
+            for key in keys(key)? {
+                let hash = handle.best_supported_rsa_hash().await?.flatten();
+                let key = russh::keys::PrivateKeyWithHashAlg::new(Arc::new(key), hash);
+                if handle.authenticate_publickey(&user, key).await?.success() {
+                    authenticated = true;
+                    break;
+                }
+            }
+            authenticated
+        }
+    };
+    if !authenticated {
+        return Err(anyhow!(Msg::SshAuthFailed(user)));
+    }
+    let channel = handle.channel_open_session().await?;
+    channel.request_subsystem(true, "sftp").await?;
+    let sftp = russh_sftp::client::SftpSession::new(channel.into_stream()).await?;
+    Ok(Session {
+        sftp,
+        _handle: handle,
+    })
+}
+
+/// 用于登录的私钥：指定的私钥无法读取时报错，默认私钥不存在或已加密时跳过
+#[cfg(feature = "sftp")]
+fn keys(key: Option<&Path>) -> Result<Vec<russh::keys::PrivateKey>> {
+    use anyhow::Context;
+
+    if let Some(path) = key {
+        let key = russh::keys::load_secret_key(path, None)
+            .with_context(|| Msg::SshKeyInvalid(path.display().to_string()))?;
+        return Ok(vec![key]);
+    }
+    let dir = crate::util::home().join(".ssh");
+    Ok(DEFAULT_KEYS
+        .iter()
+        .filter_map(|name| russh::keys::load_secret_key(dir.join(name), None).ok())
+        .collect())
+}
+
+/// URI 中没有用户名时使用当前用户
+#[cfg(feature = "sftp")]
+fn default_user() -> String {
+    std::env::var("USER")
+        .or_else(|_| std::env::var("USERNAME"))
+        .unwrap_or_default()
+}
+
+/// URI 对应的服务器上的路径，以 `/~/` 开头时相对于登录后的目录
+#[cfg(feature = "sftp")]
+fn path(uri: &Uri) -> String {
+    let path = uri.path();
+    let path = path.strip_prefix("/~/").unwrap_or(path);
+    String::from_utf8_lossy(&crate::proxy::percent_decode(path)).into_owned()
+}
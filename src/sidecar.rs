@@ -0,0 +1,218 @@
+//! 直接写入输出文件时记录下载进度的 `<file-path>.download.json`
+//!
+//! 记录 URI、资源大小、`ETag`/`Last-Modified` 及各块的范围与已写入的字节数。重新运行时与服务器上的资源
+//! 一致才沿用已下载的部分，之后的分段请求带有 `If-Range`，资源在下载过程中变化时中止
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::Duration;
+
+use serde_json::{json, Value};
+use tokio::fs::{metadata, read_to_string, remove_file, rename, write};
+use tokio::sync::Mutex;
+use tokio::task::JoinHandle;
+
+use crate::Result;
+
+/// 定期保存进度的间隔
+pub const SAVE_INTERVAL: Duration = Duration::from_secs(1);
+
+/// `file_path` 对应的 sidecar 路径
+pub fn path(file_path: &str) -> PathBuf {
+    PathBuf::from(format!("{}.download.json", file_path))
+}
+
+pub struct Sidecar {
+    path: PathBuf,
+    uri: String,
+    size: usize,
+    etag: Option<String>,
+    last_modified: Option<String>,
+    /// 各块的起点及大小
+    blocks: Vec<(usize, usize)>,
+    /// 各块已写入的字节数
+    written: Vec<AtomicUsize>,
+    /// 避免定期保存与最后一次保存同时写入
+    saving: Mutex<()>,
+}
+
+impl Sidecar {
+    pub fn new(
+        path: PathBuf,
+        uri: String,
+        size: usize,
+        (etag, last_modified): (Option<String>, Option<String>),
+        blocks: Vec<(usize, usize)>,
+    ) -> Self {
+        let written = blocks.iter().map(|_| AtomicUsize::new(0)).collect();
+        Self {
+            path,
+            uri,
+            size,
+            etag,
+            last_modified,
+            blocks,
+            written,
+            saving: Mutex::new(()),
+        }
+    }
+
+    /// 读取已有的 sidecar，不存在或无法解析时返回 `None`
+    pub async fn load(path: &Path) -> Option<Self> {
+        let value: Value = serde_json::from_str(&read_to_string(path).await.ok()?).ok()?;
+        let text = |key: &str| value.get(key).and_then(Value::as_str).map(String::from);
+        let number = |value: &Value, key: &str| Some(value.get(key)?.as_u64()? as usize);
+        let mut blocks = Vec::new();
+        let mut written = Vec::new();
+        for block in value.get("blocks")?.as_array()? {
+            let (start, size) = (number(block, "start")?, number(block, "size")?);
+            blocks.push((start, size));
+            written.push(AtomicUsize::new(number(block, "written")?.min(size)));
+        }
+        Some(Self {
+            path: path.to_path_buf(),
+            uri: text("uri")?,
+            size: number(&value, "size")?,
+            etag: text("etag"),
+            last_modified: text("last_modified"),
+            blocks,
+            written,
+            saving: Mutex::new(()),
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// `If-Range` 使用的校验值，弱 ETag 不能用于 `If-Range`，此时使用 `Last-Modified`
+    pub fn validator(&self) -> Option<&str> {
+        match &self.etag {
+            Some(t) if !t.starts_with("W/") => Some(t),
+            _ => self.last_modified.as_deref(),
+        }
+    }
+
+    /// 响应的 `ETag` 及 `Last-Modified` 是否与记录的一致
+    pub fn same_validators(
+        &self,
+        (etag, last_modified): &(Option<String>, Option<String>),
+    ) -> bool {
+        &self.etag == etag && &self.last_modified == last_modified
+    }
+
+    /// 是否记录了同一资源的同样分块，没有校验值时无法确认资源未变化
+    pub fn matches(&self, other: &Self) -> bool {
+        self.validator().is_some()
+            && self.uri == other.uri
+            && self.size == other.size
+            && self.same_validators(&(other.etag.clone(), other.last_modified.clone()))
+            && self.blocks == other.blocks
+    }
+
+    /// 第一块的起点，续传 `.part` 文件时之前的部分在记录前已经下载完成
+    pub fn start(&self) -> usize {
+        self.blocks.first().map_or(self.size, |t| t.0)
+    }
+
+    /// 第 `index` 块已写入的字节数
+    pub fn progress(&self, index: usize) -> &AtomicUsize {
+        &self.written[index]
+    }
+
+    pub async fn save(&self) -> Result {
+        let _saving = self.saving.lock().await;
+        let blocks: Vec<_> = self
+            .blocks
+            .iter()
+            .zip(&self.written)
+            .map(|((start, size), written)| {
+                json!({
+                    "start": start,
+                    "size": size,
+                    "written": written.load(Ordering::Relaxed),
+                })
+            })
+            .collect();
+        let value = json!({
+            "uri": self.uri,
+            "size": self.size,
+            "etag": self.etag,
+            "last_modified": self.last_modified,
+            "blocks": blocks,
+        });
+        // 先写入临时文件再重命名，避免中途退出时留下不完整的内容
+        let temp = self.temp_path();
+        write(&temp, serde_json::to_string_pretty(&value)?).await?;
+        rename(&temp, &self.path).await?;
+        Ok(())
+    }
+
+    pub async fn remove(&self) -> Result {
+        let _saving = self.saving.lock().await;
+        for path in [self.path.clone(), self.temp_path()] {
+            if metadata(&path).await.is_ok() {
+                remove_file(&path).await?;
+            }
+        }
+        Ok(())
+    }
+
+    fn temp_path(&self) -> PathBuf {
+        let mut path = self.path.clone().into_os_string();
+        path.push(".tmp");
+        PathBuf::from(path)
+    }
+}
+
+/// 定期保存进度的任务，丢弃时停止
+pub struct Autosave(pub JoinHandle<()>);
+
+impl Drop for Autosave {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn save_then_load_keeps_progress() {
+        let file_path =
+            std::env::temp_dir().join(format!("download-sidecar-{}", std::process::id()));
+        let path = path(file_path.to_str().unwrap());
+        let validators = (Some("W/\"weak\"".to_string()), Some("Wed".to_string()));
+        let blocks = vec![(100, 50), (150, 50)];
+        let saved = Sidecar::new(
+            path.clone(),
+            "http://example.com/a.bin".to_string(),
+            200,
+            validators.clone(),
+            blocks.clone(),
+        );
+        saved.progress(0).store(20, Ordering::Relaxed);
+        saved.progress(1).store(50, Ordering::Relaxed);
+        saved.save().await.unwrap();
+
+        let loaded = Sidecar::load(&path).await.unwrap();
+        assert!(loaded.matches(&saved));
+        assert_eq!(loaded.start(), 100);
+        assert_eq!(loaded.progress(0).load(Ordering::Relaxed), 20);
+        assert_eq!(loaded.progress(1).load(Ordering::Relaxed), 50);
+        // 弱 ETag 不能用于 `If-Range`
+        assert_eq!(loaded.validator(), Some("Wed"));
+
+        let other = Sidecar::new(
+            path.clone(),
+            saved.uri.clone(),
+            200,
+            validators,
+            vec![(100, 100)],
+        );
+        assert!(!loaded.matches(&other));
+        loaded.remove().await.unwrap();
+        assert!(Sidecar::load(&path).await.is_none());
+    }
+}
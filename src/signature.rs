@@ -0,0 +1,87 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+#[cfg(feature = "signature")]
+use anyhow::Context;
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 已解析的分离签名及公钥，在下载前读取，签名或公钥有误时不必下载
+#[cfg(feature = "signature")]
+pub struct Verifier {
+    signature: pgp::composed::DetachedSignature,
+    keys: Vec<pgp::composed::SignedPublicKey>,
+}
+
+#[cfg(feature = "signature")]
+impl Verifier {
+    /// 解析从 `location` 取得的分离签名 `signature` 及 `keyring` 中的公钥，均支持 ASCII armor 及二进制格式
+    ///
+    /// `keyring` 可包含多个公钥或私钥，不含任何密钥时报错
+    pub async fn load(signature: Vec<u8>, location: &str, keyring: &Path) -> Result<Self> {
+        use std::fs::File;
+
+        use pgp::composed::{Deserializable, DetachedSignature, PublicOrSecret, SignedPublicKey};
+
+        let (location, keyring) = (location.to_string(), keyring.to_path_buf());
+        tokio::task::spawn_blocking(move || {
+            let (signature, _) = DetachedSignature::from_reader_single(&signature[..])
+                .with_context(|| Msg::SignatureMalformed(location))?;
+            let invalid = || Msg::GpgKeyInvalid(keyring.display().to_string());
+            let (keys, _) =
+                PublicOrSecret::from_reader_many(File::open(&keyring).with_context(invalid)?)
+                    .with_context(invalid)?;
+            let keys = keys
+                .map(|key| match key? {
+                    PublicOrSecret::Public(t) => Ok(t),
+                    PublicOrSecret::Secret(t) => Ok(SignedPublicKey::from(t)),
+                })
+                .collect::<pgp::errors::Result<Vec<_>>>()
+                .with_context(invalid)?;
+            if keys.is_empty() {
+                return Err(anyhow!(invalid()));
+            }
+            Ok(Self { signature, keys })
+        })
+        .await?
+    }
+
+    /// 验证 `path` 的签名，依次尝试各主密钥及子密钥，任一通过即视为验证成功
+    pub async fn verify(self, path: &Path) -> Result {
+        use std::fs::File;
+        use std::io::BufReader;
+
+        let path = path.to_path_buf();
+        tokio::task::spawn_blocking(move || {
+            let signature = &self.signature.signature;
+            let file = || File::open(&path).map(BufReader::new);
+            let verified = self.keys.iter().any(|key| {
+                file().is_ok_and(|t| signature.verify(&key.primary_key, t).is_ok())
+                    || key.public_subkeys.iter().any(|subkey| {
+                        file().is_ok_and(|t| signature.verify(&subkey.key, t).is_ok())
+                    })
+            });
+            if !verified {
+                return Err(anyhow!(Msg::SignatureInvalid(path.display().to_string())));
+            }
+            Ok(())
+        })
+        .await?
+    }
+}
+
+/// 未启用 `signature` 功能时无法验证签名
+#[cfg(not(feature = "signature"))]
+pub struct Verifier;
+
+#[cfg(not(feature = "signature"))]
+impl Verifier {
+    pub async fn load(_signature: Vec<u8>, _location: &str, _keyring: &Path) -> Result<Self> {
+        Err(anyhow!(Msg::SignatureUnsupported))
+    }
+
+    pub async fn verify(self, _path: &Path) -> Result {
+        Err(anyhow!(Msg::SignatureUnsupported))
+    }
+}
@@ -0,0 +1,96 @@
+//! `--smoke-test`：用一小段数据检验整个下载流程
+
+use anyhow::anyhow;
+use hyper::Uri;
+use tokio::fs::{read, remove_dir_all, File};
+use tokio::io::{AsyncReadExt, AsyncSeekExt, SeekFrom};
+
+use crate::http::{
+    add_download_bar, check_block_files, create_temp_dir, download_block, job, log, merge_blocks,
+    wait_blocks,
+};
+use crate::message::Msg;
+use crate::Result;
+
+/// 冒烟测试下载的首尾字节数
+const SMOKE_SAMPLE: usize = 64 * 1024;
+
+/// 分别以块文件和偏移写入两种方式下载资源首尾各一小段，比对两者的内容
+///
+/// 所有文件都写在临时文件目录中，完成后删除，不产生输出文件
+pub async fn run(uri: &Uri, content_length: usize) -> Result {
+    let ranges = sample_ranges(content_length);
+    create_temp_dir().await?;
+    let output = job().temp_dir.join("output");
+    File::create(&output)
+        .await?
+        .set_len(content_length as u64)
+        .await?;
+    let mut handles = Vec::new();
+    for (i, &(start, block_size)) in ranges.iter().enumerate() {
+        for (task_index, output) in [(i + 1, None), (ranges.len() + i + 1, Some(&output))] {
+            let bar = add_download_bar(block_size as u64, task_index)?;
+            handles.push(download_block(
+                uri.clone(),
+                (i, task_index),
+                start,
+                block_size,
+                output.cloned(),
+                bar,
+            ));
+        }
+    }
+    wait_blocks(handles).await?;
+
+    // 与正式下载一样校验并合并块文件；样本不是完整的资源，不校验摘要也不改名为输出文件
+    let sizes: Vec<usize> = ranges.iter().map(|&(_, block_size)| block_size).collect();
+    check_block_files(&sizes).await?;
+    let merged = job().temp_dir.join("merged");
+    merge_blocks(sizes.len(), &merged, |_| {}).await?;
+    let merged = read(&merged).await?;
+    let mut file = File::open(&output).await?;
+    let mut offset = 0;
+    for &(start, block_size) in &ranges {
+        let mut written = vec![0; block_size];
+        file.seek(SeekFrom::Start(start as u64)).await?;
+        file.read_exact(&mut written).await?;
+        if merged.get(offset..offset + block_size) != Some(&written[..]) {
+            return Err(anyhow!(Msg::SmokeTestMismatch {
+                start,
+                len: block_size,
+            }));
+        }
+        offset += block_size;
+    }
+    remove_dir_all(&job().temp_dir).await?;
+    log(Msg::SmokeTestPassed.to_string());
+    Ok(())
+}
+
+/// 比对的样本：资源首尾各一段，资源较小时为整个资源
+fn sample_ranges(content_length: usize) -> Vec<(usize, usize)> {
+    if content_length <= 2 * SMOKE_SAMPLE {
+        vec![(0, content_length)]
+    } else {
+        vec![
+            (0, SMOKE_SAMPLE),
+            (content_length - SMOKE_SAMPLE, SMOKE_SAMPLE),
+        ]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sample_ranges_cover_head_and_tail() {
+        assert_eq!(sample_ranges(0), [(0, 0)]);
+        assert_eq!(sample_ranges(2 * SMOKE_SAMPLE), [(0, 2 * SMOKE_SAMPLE)]);
+        let len = 2 * SMOKE_SAMPLE + 1;
+        assert_eq!(
+            sample_ranges(len),
+            [(0, SMOKE_SAMPLE), (SMOKE_SAMPLE + 1, SMOKE_SAMPLE)]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error};
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 进度条样式预设
+#[derive(Clone, Copy)]
+pub enum Preset {
+    Default,
+    /// 仅进度条与状态
+    Minimal,
+    /// 额外显示已用时间、速度，进度条使用 Unicode 方块字符
+    Detailed,
+    /// 仅百分比
+    PercentOnly,
+    /// 不使用颜色及 Unicode 字符
+    AsciiSafe,
+}
+
+/// `--help` 中列出的预设名称
+pub const PRESETS: [&str; 5] = [
+    "default",
+    "minimal",
+    "detailed",
+    "percent-only",
+    "ascii-safe",
+];
+
+impl FromStr for Preset {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "default" => Ok(Self::Default),
+            "minimal" => Ok(Self::Minimal),
+            "detailed" => Ok(Self::Detailed),
+            "percent-only" => Ok(Self::PercentOnly),
+            "ascii-safe" => Ok(Self::AsciiSafe),
+            _ => Err(anyhow!(Msg::UnknownProgressStyle(s.to_string()))),
+        }
+    }
+}
+
+impl Preset {
+    /// 下载进度条的模板
+    pub fn download_template(self) -> &'static str {
+        match self {
+            Self::Default => {
+                "[{bar:50.cyan/blue}] [{msg}] [{bytes}/{total_bytes}] {bytes_per_sec} ({eta})"
+            }
+            Self::Minimal => "[{bar:30}] {msg} {bytes_per_sec}",
+            Self::Detailed => {
+                "[{elapsed_precise}] [{bar:50.cyan/blue}] [{msg}] [{bytes}/{total_bytes}] {bytes_per_sec} ({eta})"
+            }
+            Self::PercentOnly => "[{msg}] {percent}%",
+            Self::AsciiSafe => "[{bar:50}] [{msg}] [{bytes}/{total_bytes}] {bytes_per_sec} ({eta})",
+        }
+    }
+
+    /// 所有连接的总进度及总速度的模板
+    pub fn total_template(self) -> &'static str {
+        match self {
+            Self::Default | Self::AsciiSafe => {
+                "[{msg}] [{bytes}/{total_bytes}] {bytes_per_sec} ({eta})"
+            }
+            Self::Minimal => "{msg} {bytes_per_sec}",
+            Self::Detailed => {
+                "[{elapsed_precise}] [{msg}] [{bytes}/{total_bytes}] {bytes_per_sec} ({eta})"
+            }
+            Self::PercentOnly => "[{msg}] {percent}% {bytes_per_sec}",
+        }
+    }
+
+    /// 合并进度条的模板
+    pub fn merge_template(self) -> &'static str {
+        match self {
+            Self::Default => "[{bar:50.magenta/cyan}] [{msg}] ({eta})",
+            Self::Minimal => "[{bar:30}] {msg}",
+            Self::Detailed => {
+                "[{elapsed_precise}] [{bar:50.magenta/cyan}] [{msg}] [{bytes}/{total_bytes}] ({eta})"
+            }
+            Self::PercentOnly => "[{msg}] {percent}%",
+            Self::AsciiSafe => "[{bar:50}] [{msg}] ({eta})",
+        }
+    }
+
+    /// 进度条的填充字符
+    pub fn progress_chars(self) -> &'static str {
+        match self {
+            Self::Detailed => "█▉▊▋▌▍▎▏ ",
+            _ => "#>-",
+        }
+    }
+}
@@ -0,0 +1,101 @@
+use std::fmt::{self, Display, Formatter};
+use std::future::Future;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::{anyhow, Error};
+use hyper::{Body, Client, Request, Response};
+
+use crate::connector::Connector;
+use crate::message::Msg;
+use crate::proxy::Proxies;
+#[cfg(feature = "http3")]
+use crate::quic;
+use crate::Result;
+
+pub type ResponseFuture<'a> = Pin<Box<dyn Future<Output = Result<Response<Body>>> + Send + 'a>>;
+
+/// 发送请求的客户端，各块只通过它发送请求，不关心使用的协议栈
+pub trait HttpClient: Send + Sync {
+    /// 发送请求，收到响应头后返回，响应体在读取时继续接收
+    fn request(&self, request: Request<Body>) -> ResponseFuture<'_>;
+}
+
+impl HttpClient for Client<Connector> {
+    fn request(&self, request: Request<Body>) -> ResponseFuture<'_> {
+        let response = Client::request(self, request);
+        Box::pin(async move { Ok(response.await?) })
+    }
+}
+
+/// 请求使用的传输方式
+#[derive(Clone, Copy)]
+pub enum Transport {
+    /// HTTP/1.1
+    Http1,
+    /// HTTP/2，HTTPS 通过 ALPN 协商，HTTP 使用 prior knowledge
+    Http2,
+    /// HTTPS 通过 ALPN 协商，服务器支持时使用 HTTP/2 在少量连接上复用各块的请求，否则使用 HTTP/1.1
+    Auto,
+    /// 实验性的 HTTP/3（QUIC），仅用于 https
+    #[cfg(feature = "http3")]
+    Http3,
+}
+
+impl FromStr for Transport {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.trim() {
+            "h1" => Ok(Self::Http1),
+            "h2" => Ok(Self::Http2),
+            "auto" => Ok(Self::Auto),
+            #[cfg(feature = "http3")]
+            "h3" => Ok(Self::Http3),
+            #[cfg(not(feature = "http3"))]
+            "h3" => Err(anyhow!(Msg::Http3Unsupported)),
+            _ => Err(anyhow!(Msg::UnknownTransport(s.to_string()))),
+        }
+    }
+}
+
+impl Display for Transport {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Http1 => "h1",
+            Self::Http2 => "h2",
+            Self::Auto => "auto",
+            #[cfg(feature = "http3")]
+            Self::Http3 => "h3",
+        })
+    }
+}
+
+impl Transport {
+    /// 创建使用该传输方式的客户端
+    pub fn client(
+        self,
+        server_name: Option<String>,
+        proxies: Proxies,
+        connect_timeout: Option<Duration>,
+    ) -> Result<Box<dyn HttpClient>> {
+        let mut builder = Client::builder();
+        let connector = match self {
+            #[cfg(feature = "http3")]
+            Self::Http3 => {
+                let client = quic::Client::new(server_name, proxies, connect_timeout)?;
+                return Ok(Box::new(client));
+            }
+            Self::Http1 => Connector::new(server_name, &[], proxies, connect_timeout)?,
+            Self::Http2 => {
+                builder.http2_only(true);
+                Connector::new(server_name, &["h2"], proxies, connect_timeout)?
+            }
+            Self::Auto => {
+                Connector::new(server_name, &["h2", "http/1.1"], proxies, connect_timeout)?
+            }
+        };
+        Ok(Box::new(builder.build(connector)))
+    }
+}
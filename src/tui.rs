@@ -0,0 +1,405 @@
+//! 全屏仪表盘，替代 `indicatif` 进度条
+//!
+//! 进度仍记录在隐藏的 `ProgressBar` 中，仪表盘定期读取并绘制。各块列在表格中，可用方向键选择，
+//! `p` 暂停或继续、`x` 中止选中的块
+
+use std::future::Future;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::time::Duration;
+
+use anyhow::anyhow;
+use tokio::select;
+use tokio::time::sleep;
+
+use crate::message::Msg;
+use crate::Result;
+
+/// 检查块是否被中止的间隔
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+/// 一个块的起始位置、重试次数及在仪表盘中的控制状态
+#[derive(Default)]
+pub struct Chunk {
+    /// 块在资源中的起始位置
+    start: AtomicUsize,
+    retries: AtomicUsize,
+    paused: AtomicBool,
+    aborted: AtomicBool,
+}
+
+impl Chunk {
+    pub fn set_start(&self, start: usize) {
+        self.start.store(start, Ordering::Relaxed);
+    }
+
+    pub fn retried(&self) {
+        self.retries.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn paused(&self) -> bool {
+        self.paused.load(Ordering::Relaxed)
+    }
+
+    pub fn set_paused(&self, paused: bool) {
+        self.paused.store(paused, Ordering::Relaxed);
+    }
+
+    /// 中止该块，正在进行的请求随即放弃
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+    }
+
+    /// 运行 `future`，块被中止时放弃并返回错误
+    pub async fn guard<T>(&self, future: impl Future<Output = Result<T>>) -> Result<T> {
+        let aborted = async {
+            while !self.aborted.load(Ordering::Relaxed) {
+                sleep(POLL_INTERVAL).await;
+            }
+        };
+        select! {
+            biased;
+            _ = aborted => Err(anyhow!(Msg::Aborted)),
+            t = future => t,
+        }
+    }
+}
+
+/// 是否因在仪表盘中中止而失败
+pub fn aborted(error: &anyhow::Error) -> bool {
+    matches!(error.downcast_ref(), Some(Msg::Aborted))
+}
+
+#[cfg(feature = "tui")]
+mod dashboard {
+    use std::collections::VecDeque;
+    use std::io::{stdout, IsTerminal, Stdout};
+    use std::process::exit;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::{Arc, Mutex};
+    use std::thread::{self, JoinHandle};
+    use std::time::{Duration, Instant};
+
+    use indicatif::{HumanBytes, ProgressBar};
+    use lazy_static::lazy_static;
+    use ratatui::backend::CrosstermBackend;
+    use ratatui::crossterm::cursor::{Hide, Show};
+    use ratatui::crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers};
+    use ratatui::crossterm::execute;
+    use ratatui::crossterm::terminal::{
+        disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen,
+    };
+    use ratatui::layout::{Constraint, Layout};
+    use ratatui::style::{Color, Modifier, Style};
+    use ratatui::text::Line;
+    use ratatui::widgets::{Block, Borders, Gauge, Paragraph, Row, Sparkline, Table, TableState};
+    use ratatui::{Frame, Terminal};
+
+    use super::Chunk;
+    use crate::interrupt;
+    use crate::message::Msg;
+    use crate::Result;
+
+    /// 刷新间隔
+    const TICK: Duration = Duration::from_millis(250);
+    /// 日志面板保留的行数
+    const MAX_LOGS: usize = 200;
+    /// 速度曲线保留的采样数
+    const MAX_SPEEDS: usize = 240;
+
+    lazy_static! {
+        static ref BARS: Mutex<Vec<(ProgressBar, Option<Arc<Chunk>>)>> = Mutex::new(Vec::new());
+        static ref LOGS: Mutex<VecDeque<String>> = Mutex::new(VecDeque::new());
+        static ref THREAD: Mutex<Option<JoinHandle<Result>>> = Mutex::new(None);
+    }
+
+    static RUNNING: AtomicBool = AtomicBool::new(false);
+
+    /// 启动仪表盘，标准输出不是终端时返回 `false`，由调用方回退到普通进度条
+    pub fn start() -> Result<bool> {
+        if !stdout().is_terminal() {
+            return Ok(false);
+        }
+        let mut terminal = Terminal::new(CrosstermBackend::new(stdout()))?;
+        execute!(terminal.backend_mut(), EnterAlternateScreen, Hide)?;
+        // 原始模式下 Ctrl+C 作为按键读取
+        enable_raw_mode()?;
+        RUNNING.store(true, Ordering::SeqCst);
+        *THREAD.lock().unwrap() = Some(thread::spawn(move || {
+            let result = render(&mut terminal);
+            restore(&mut terminal)?;
+            result
+        }));
+        Ok(true)
+    }
+
+    /// 停止仪表盘并恢复终端
+    pub fn stop() -> Result {
+        RUNNING.store(false, Ordering::SeqCst);
+        match THREAD.lock().unwrap().take() {
+            None => Ok(()),
+            Some(handle) => handle.join().unwrap(),
+        }
+    }
+
+    pub fn active() -> bool {
+        RUNNING.load(Ordering::SeqCst)
+    }
+
+    /// 在仪表盘中显示进度条，下载块的进度条带有 `chunk`，计入总进度与下载速度
+    pub fn add(bar: &ProgressBar, chunk: Option<Arc<Chunk>>) {
+        BARS.lock().unwrap().push((bar.clone(), chunk));
+    }
+
+    /// 写入日志面板
+    pub fn log(line: String) {
+        let mut logs = LOGS.lock().unwrap();
+        if logs.len() == MAX_LOGS {
+            logs.pop_front();
+        }
+        logs.push_back(line);
+    }
+
+    fn restore(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result {
+        disable_raw_mode()?;
+        execute!(terminal.backend_mut(), Show, LeaveAlternateScreen)?;
+        Ok(())
+    }
+
+    /// 定期绘制并处理按键，直至 `stop` 被调用
+    fn render(terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> Result {
+        let mut speeds = VecDeque::with_capacity(MAX_SPEEDS);
+        let mut last = (Instant::now(), 0);
+        let mut table = TableState::default().with_selected(0);
+        while active() {
+            let deadline = Instant::now() + TICK;
+            while let Some(remaining) = deadline.checked_duration_since(Instant::now()) {
+                if !event::poll(remaining)? {
+                    break;
+                }
+                if let Event::Key(key) = event::read()? {
+                    if key.kind == KeyEventKind::Press {
+                        handle_key(terminal, key, &mut table)?;
+                    }
+                }
+            }
+            let (position, _) = transferred(&BARS.lock().unwrap());
+            let now = Instant::now();
+            let speed = position.saturating_sub(last.1) as f64 / (now - last.0).as_secs_f64();
+            last = (now, position);
+            if speeds.len() == MAX_SPEEDS {
+                speeds.pop_front();
+            }
+            speeds.push_back(speed as u64);
+            terminal.draw(|frame| draw(frame, &speeds, &mut table))?;
+        }
+        Ok(())
+    }
+
+    /// 选择块、暂停或继续、中止选中的块，Ctrl+C 与终端中一样中断下载
+    fn handle_key(
+        terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+        key: KeyEvent,
+        table: &mut TableState,
+    ) -> Result {
+        let bars = BARS.lock().unwrap();
+        let selected = table
+            .selected()
+            .unwrap_or_default()
+            .min(bars.len().saturating_sub(1));
+        let chunk = bars.get(selected).and_then(|(_, chunk)| chunk.as_ref());
+        match key.code {
+            KeyCode::Char('c') if key.modifiers.contains(KeyModifiers::CONTROL) => {
+                // 再次按下时恢复终端后立即退出
+                if interrupt::interrupted() {
+                    restore(terminal)?;
+                    exit(130);
+                }
+                interrupt::interrupt();
+            }
+            KeyCode::Up | KeyCode::Char('k') => table.select(Some(selected.saturating_sub(1))),
+            KeyCode::Down | KeyCode::Char('j') => {
+                table.select(Some((selected + 1).min(bars.len().saturating_sub(1))))
+            }
+            KeyCode::Char('p') | KeyCode::Char(' ') => {
+                if let Some(chunk) = chunk {
+                    chunk.set_paused(!chunk.paused());
+                }
+            }
+            KeyCode::Char('x') => {
+                if let Some(chunk) = chunk {
+                    chunk.abort();
+                }
+            }
+            _ => {}
+        }
+        Ok(())
+    }
+
+    fn draw(frame: &mut Frame, speeds: &VecDeque<u64>, table: &mut TableState) {
+        let bars = BARS.lock().unwrap();
+        let [overall, blocks, graph, logs] = Layout::vertical([
+            Constraint::Length(3),
+            Constraint::Min(3),
+            Constraint::Length(6),
+            Constraint::Length(8),
+        ])
+        .areas(frame.area());
+
+        let (position, length) = transferred(&bars);
+        let speed = speeds.back().copied().unwrap_or_default();
+        frame.render_widget(
+            Gauge::default()
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(Msg::TuiOverall.to_string()),
+                )
+                .gauge_style(Style::default().fg(Color::Cyan))
+                .ratio(ratio(position, length))
+                .label(format!(
+                    "{}/{} ({}/s)",
+                    HumanBytes(position),
+                    HumanBytes(length),
+                    HumanBytes(speed)
+                )),
+            overall,
+        );
+
+        // 每行显示一个块的当前位置、进度、速度及重试次数，合并等进度条没有位置及重试次数
+        let rows = bars.iter().map(|(bar, chunk)| {
+            let (position, length) = (bar.position(), bar.length().unwrap_or_default());
+            let (offset, retries) = match chunk {
+                Some(t) => (
+                    (t.start.load(Ordering::Relaxed) as u64 + position).to_string(),
+                    t.retries.load(Ordering::Relaxed).to_string(),
+                ),
+                None => (String::new(), String::new()),
+            };
+            Row::new(vec![
+                bar.message().to_string(),
+                offset,
+                format!(
+                    "{:>3.0}% {}/{}",
+                    ratio(position, length) * 100.0,
+                    HumanBytes(position),
+                    HumanBytes(length)
+                ),
+                format!("{}/s", HumanBytes(bar.per_sec() as u64)),
+                retries,
+            ])
+        });
+        let header = Msg::TuiColumns.to_string();
+        let widths = [
+            Constraint::Min(20),
+            Constraint::Length(12),
+            Constraint::Length(28),
+            Constraint::Length(14),
+            Constraint::Length(8),
+        ];
+        if table.selected().is_some_and(|t| t >= bars.len()) {
+            table.select(Some(bars.len().saturating_sub(1)));
+        }
+        frame.render_stateful_widget(
+            Table::new(rows, widths)
+                .header(Row::new(header.split('\t')).style(Style::default().fg(Color::Yellow)))
+                .row_highlight_style(Style::default().add_modifier(Modifier::REVERSED))
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(Msg::TuiBlocks.to_string()),
+                ),
+            blocks,
+            table,
+        );
+
+        let data: Vec<u64> = speeds.iter().copied().collect();
+        let skip = data
+            .len()
+            .saturating_sub(graph.width.saturating_sub(2) as usize);
+        frame.render_widget(
+            Sparkline::default()
+                .block(
+                    Block::default()
+                        .borders(Borders::ALL)
+                        .title(Msg::TuiSpeed.to_string()),
+                )
+                .style(Style::default().fg(Color::Magenta))
+                .data(&data[skip..]),
+            graph,
+        );
+
+        let logs_buffer = LOGS.lock().unwrap();
+        let skip = logs_buffer
+            .len()
+            .saturating_sub(logs.height.saturating_sub(2) as usize);
+        let lines: Vec<Line> = logs_buffer
+            .iter()
+            .skip(skip)
+            .map(|t| Line::from(t.as_str()))
+            .collect();
+        frame.render_widget(
+            Paragraph::new(lines).block(
+                Block::default()
+                    .borders(Borders::ALL)
+                    .title(Msg::TuiLogs.to_string()),
+            ),
+            logs,
+        );
+    }
+
+    /// 计入总进度的已下载字节数与总字节数
+    fn transferred(bars: &[(ProgressBar, Option<Arc<Chunk>>)]) -> (u64, u64) {
+        bars.iter().filter(|(_, chunk)| chunk.is_some()).fold(
+            (0, 0),
+            |(position, length), (bar, _)| {
+                (
+                    position + bar.position(),
+                    length + bar.length().unwrap_or_default(),
+                )
+            },
+        )
+    }
+
+    fn ratio(position: u64, length: u64) -> f64 {
+        if length == 0 {
+            0.0
+        } else {
+            (position as f64 / length as f64).min(1.0)
+        }
+    }
+}
+
+#[cfg(feature = "tui")]
+pub use dashboard::{active, add, log, start, stop};
+
+/// 未启用 `tui` 功能时回退到普通进度条
+#[cfg(not(feature = "tui"))]
+mod fallback {
+    use std::sync::Arc;
+
+    use indicatif::ProgressBar;
+
+    use super::Chunk;
+    use crate::message::Msg;
+    use crate::Result;
+
+    pub fn start() -> Result<bool> {
+        eprintln!("{}", Msg::TuiUnsupported);
+        Ok(false)
+    }
+
+    pub fn stop() -> Result {
+        Ok(())
+    }
+
+    pub fn active() -> bool {
+        false
+    }
+
+    pub fn add(_bar: &ProgressBar, _chunk: Option<Arc<Chunk>>) {}
+
+    pub fn log(_line: String) {}
+}
+
+#[cfg(not(feature = "tui"))]
+pub use fallback::{active, add, log, start, stop};
@@ -0,0 +1,66 @@
+//! 多个模块共用的小工具：HMAC-SHA256、百分号编码及主目录
+
+use std::env;
+use std::path::PathBuf;
+
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+
+/// 以 `key` 计算 `data` 的 HMAC-SHA256
+pub fn hmac_sha256(key: &[u8], data: &[u8]) -> Vec<u8> {
+    // HMAC 接受任意长度的密钥，`new_from_slice` 不会失败
+    let mut mac = Hmac::<Sha256>::new_from_slice(key).expect("HMAC accepts keys of any length");
+    mac.update(data);
+    mac.finalize().into_bytes().to_vec()
+}
+
+/// 对非保留字符及 `keep` 以外的字节进行百分号编码
+pub fn percent_encode(bytes: &[u8], keep: &[u8]) -> String {
+    bytes
+        .iter()
+        .map(|&b| match b {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'_' | b'.' | b'~' => {
+                (b as char).to_string()
+            }
+            _ if keep.contains(&b) => (b as char).to_string(),
+            _ => format!("%{:02X}", b),
+        })
+        .collect()
+}
+
+/// 主目录：`HOME`，未设置时为 `USERPROFILE`
+pub fn home() -> PathBuf {
+    let home = env::var_os("HOME")
+        .or_else(|| env::var_os("USERPROFILE"))
+        .unwrap_or_default();
+    PathBuf::from(home)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::checksum::hex;
+
+    #[test]
+    fn hmac_sha256_matches_rfc_4231() {
+        // RFC 4231 的测试用例 2
+        let mac = hmac_sha256(b"Jefe", b"what do ya want for nothing?");
+        assert_eq!(
+            hex(&mac),
+            "5bdcc146bf60754e6a042426089575c75a003f089d2739839dec58b964ec3843"
+        );
+    }
+
+    #[test]
+    fn percent_encode_keeps_unreserved() {
+        for (input, keep, expected) in [
+            ("a-b_c.d~e", &b""[..], "a-b_c.d~e"),
+            ("dir/file name.txt", b"/", "dir/file%20name.txt"),
+            ("dir/file name.txt", b"", "dir%2Ffile%20name.txt"),
+            ("100%", b"", "100%25"),
+            ("日", b"", "%E6%97%A5"),
+        ] {
+            assert_eq!(percent_encode(input.as_bytes(), keep), expected);
+        }
+    }
+}
@@ -0,0 +1,194 @@
+//! 下载 WebDAV 服务器上的文件
+//!
+//! `dav://`、`davs://` 分别转为 `http://`、`https://`，地址中的用户名及密码以 Basic 认证发送。大小及校验值通过
+//! `Depth: 0` 的 `PROPFIND` 获取，各块照常以 range 请求下载
+
+use std::sync::Mutex;
+
+use anyhow::anyhow;
+use hyper::body::to_bytes;
+use hyper::header::{HeaderValue, CONTENT_TYPE};
+use hyper::{Body, Method, StatusCode, Uri};
+use regex::Regex;
+
+use crate::auth::Auth;
+use crate::http::{log, request_builder, send};
+use crate::interrupt;
+use crate::message::Msg;
+use crate::proxy::percent_decode;
+use crate::session::session;
+use crate::Result;
+
+/// 只请求下载需要的属性
+const PROPFIND_BODY: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<d:propfind xmlns:d="DAV:">
+  <d:prop>
+    <d:resourcetype/>
+    <d:getcontentlength/>
+    <d:getcontenttype/>
+    <d:getetag/>
+    <d:getlastmodified/>
+  </d:prop>
+</d:propfind>"#;
+
+/// 一次运行中地址带有用户名的主机及对应的 `Authorization` 请求头
+#[derive(Default)]
+pub struct State {
+    credentials: Mutex<Vec<(String, HeaderValue)>>,
+}
+
+/// `PROPFIND` 返回的属性
+pub struct Properties {
+    pub collection: bool,
+    pub content_length: Option<usize>,
+    pub content_type: Option<String>,
+    pub etag: Option<String>,
+    pub last_modified: Option<String>,
+}
+
+impl State {
+    /// 把 `dav://`、`davs://` 转为 HTTP 地址，并去掉地址中的用户名及密码，之后发往该主机的请求都使用这组凭据
+    pub fn resolve(&self, uri: &Uri) -> Result<Uri> {
+        let scheme = match uri.scheme_str() {
+            Some("dav") => "http",
+            Some("davs") => "https",
+            Some(t) => t,
+            None => return Ok(uri.clone()),
+        };
+        let authority = uri.authority().map(|t| t.as_str()).unwrap_or_default();
+        let (userinfo, host) = match authority.rsplit_once('@') {
+            Some((userinfo, host)) => (Some(userinfo), host),
+            None => (None, authority),
+        };
+        let path = uri.path_and_query().map(|t| t.as_str()).unwrap_or("/");
+        let resolved: Uri = format!("{}://{}{}", scheme, host, path).parse()?;
+        if let Some(userinfo) = userinfo {
+            let (user, password) = userinfo.split_once(':').unwrap_or((userinfo, ""));
+            let decode = |t| String::from_utf8_lossy(&percent_decode(t)).into_owned();
+            let auth = Auth::Basic {
+                user: decode(user),
+                password: decode(password),
+            };
+            self.credentials.lock().unwrap().push((
+                host.to_string(),
+                HeaderValue::from_str(&auth.header_value())?,
+            ));
+        }
+        Ok(resolved)
+    }
+
+    /// 发往地址中带有凭据的主机的请求需附加的 `Authorization` 请求头
+    pub fn authorization(&self, uri: &Uri) -> Option<HeaderValue> {
+        let host = uri.authority()?.as_str();
+        let credentials = self.credentials.lock().unwrap();
+        credentials
+            .iter()
+            .find(|(t, _)| t == host)
+            .map(|(_, value)| value.clone())
+    }
+}
+
+/// 通过 `Depth: 0` 的 `PROPFIND` 获取资源的属性，服务器不支持时返回 `None`
+pub async fn propfind(uri: &Uri) -> Result<Option<Properties>> {
+    let session = session();
+    let request = request_builder(Method::from_bytes(b"PROPFIND")?, uri)
+        .header("Depth", "0")
+        .header(CONTENT_TYPE, "application/xml; charset=utf-8")
+        .body(Body::from(PROPFIND_BODY))?;
+    let response = interrupt::guard(send(session.clients[0].as_ref(), request)).await?;
+    if response.status() != StatusCode::MULTI_STATUS {
+        if session.config.verbose {
+            log(Msg::PropfindFailed(response.status().to_string()).to_string());
+        }
+        return Ok(None);
+    }
+    let body = to_bytes(response.into_body()).await?;
+    let properties = parse(&String::from_utf8_lossy(&body))?;
+    if properties.collection {
+        return Err(anyhow!(Msg::WebDavCollection(uri.to_string())));
+    }
+    Ok(Some(properties))
+}
+
+/// 解析 `207 Multi-Status` 响应，各属性的命名空间前缀不固定
+pub fn parse(text: &str) -> Result<Properties> {
+    let property = |name: &str| {
+        let pattern = format!(r"<(?:[\w-]+:)?{}(?:\s[^>]*)?>([^<]*)</", name);
+        Regex::new(&pattern)
+            .ok()?
+            .captures(text)
+            .map(|t| unescape(t[1].trim()))
+            .filter(|t| !t.is_empty())
+    };
+    let collection = Regex::new(r"<(?:[\w-]+:)?collection\s*/?>")
+        .map_err(|e| anyhow!(e))?
+        .is_match(text);
+    let content_length = match property("getcontentlength") {
+        None => None,
+        Some(t) => Some(t.parse()?),
+    };
+    Ok(Properties {
+        collection,
+        content_length,
+        content_type: property("getcontenttype"),
+        etag: property("getetag"),
+        last_modified: property("getlastmodified"),
+    })
+}
+
+/// XML 的预定义实体，ETag 中的引号通常写作 `&quot;`
+pub fn unescape(text: &str) -> String {
+    text.replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&amp;", "&")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_reads_properties_with_any_prefix() {
+        let text = r#"<?xml version="1.0"?><D:multistatus xmlns:D="DAV:"><D:response>
+            <D:propstat><D:prop><D:resourcetype/>
+            <D:getcontentlength>1000</D:getcontentlength>
+            <D:getetag>&quot;abc&quot;</D:getetag></D:prop></D:propstat></D:response></D:multistatus>"#;
+        let properties = parse(text).unwrap();
+        assert!(!properties.collection);
+        assert_eq!(properties.content_length, Some(1000));
+        assert_eq!(properties.etag.as_deref(), Some("\"abc\""));
+        assert_eq!(properties.content_type, None);
+        let text =
+            r#"<multistatus xmlns="DAV:"><resourcetype><collection/></resourcetype></multistatus>"#;
+        assert!(parse(text).unwrap().collection);
+        assert!(parse("<d:getcontentlength>many</d:getcontentlength>").is_err());
+    }
+
+    #[test]
+    fn resolve_moves_credentials_into_authorization() {
+        let state = State::default();
+        for (uri, resolved) in [
+            ("dav://u:p@example.com/a.bin", "http://example.com/a.bin"),
+            (
+                "davs://example.org:8443/b?x=1",
+                "https://example.org:8443/b?x=1",
+            ),
+        ] {
+            let uri: Uri = uri.parse().unwrap();
+            assert_eq!(
+                state.resolve(&uri).unwrap().to_string(),
+                resolved,
+                "{:?}",
+                uri
+            );
+        }
+        let authorization = |uri: &str| state.authorization(&uri.parse().unwrap());
+        assert_eq!(
+            authorization("http://example.com/a.bin"),
+            Some(HeaderValue::from_static("Basic dTpw"))
+        );
+        assert_eq!(authorization("https://example.org:8443/b"), None);
+    }
+}
@@ -0,0 +1,66 @@
+//! 集成测试共用的本地 HTTP 服务器
+
+use std::io::{BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::thread;
+
+pub const SIZE: usize = 1_000_003;
+
+pub fn data() -> Vec<u8> {
+    (0..SIZE).map(|i| (i * 31 % 251) as u8).collect()
+}
+
+/// 每个连接只处理一个请求的 HTTP 服务器，支持 HEAD 及单个范围的 GET
+pub fn serve() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let address = listener.local_addr().unwrap();
+    thread::spawn(move || {
+        let data = data();
+        for stream in listener.incoming().flatten() {
+            let data = data.clone();
+            thread::spawn(move || respond(stream, &data));
+        }
+    });
+    format!("http://{}/data.bin", address)
+}
+
+fn respond(mut stream: TcpStream, data: &[u8]) {
+    let mut reader = BufReader::new(stream.try_clone().unwrap());
+    let mut line = String::new();
+    reader.read_line(&mut line).unwrap();
+    let head = line.starts_with("HEAD");
+    let mut range = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).unwrap() == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some(value) = header.to_ascii_lowercase().strip_prefix("range: bytes=") {
+            let (start, end) = value.trim().split_once('-').unwrap();
+            let start: usize = start.parse().unwrap();
+            let end = end
+                .parse()
+                .map_or(data.len() - 1, |t: usize| t.min(data.len() - 1));
+            range = Some((start, end));
+        }
+    }
+    let (status, body, extra) = match range {
+        Some((start, end)) => (
+            "206 Partial Content",
+            &data[start..=end],
+            format!("Content-Range: bytes {}-{}/{}\r\n", start, end, data.len()),
+        ),
+        None => ("200 OK", data, String::new()),
+    };
+    let header = format!(
+        "HTTP/1.1 {}\r\nContent-Length: {}\r\nAccept-Ranges: bytes\r\n\
+         Last-Modified: Wed, 01 Jan 2025 00:00:00 GMT\r\n{}Connection: close\r\n\r\n",
+        status,
+        body.len(),
+        extra
+    );
+    let _ = stream.write_all(header.as_bytes());
+    if !head {
+        let _ = stream.write_all(body);
+    }
+}
@@ -0,0 +1,46 @@
+//! 在同一进程中重复及同时运行多个下载器
+
+use std::fs;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use download::Downloader;
+
+mod common;
+
+use common::{data, serve, SIZE};
+
+#[tokio::test]
+async fn downloaders_run_repeatedly_and_concurrently() {
+    let uri = serve();
+    let dir = std::env::temp_dir().join(format!("download-downloader-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let output = |name: &str| dir.join(name).to_str().unwrap().to_string();
+    let run = |name: &str, connections| {
+        let downloaded = Arc::new(AtomicU64::new(0));
+        let reported = downloaded.clone();
+        let downloader = Downloader::new(uri.parse().unwrap(), output(name))
+            .connections(connections)
+            .progress(move |done, total| {
+                assert_eq!(total, SIZE as u64);
+                reported.fetch_max(done, Ordering::Relaxed);
+            });
+        async move {
+            downloader
+                .run()
+                .await
+                .map(|_| downloaded.load(Ordering::Relaxed))
+        }
+    };
+
+    let (first, second) = tokio::join!(run("a.bin", 4), run("b.bin", 2));
+    assert_eq!(first.unwrap(), SIZE as u64);
+    assert_eq!(second.unwrap(), SIZE as u64);
+    assert_eq!(run("c.bin", 3).await.unwrap(), SIZE as u64);
+
+    let expected = data();
+    for name in ["a.bin", "b.bin", "c.bin"] {
+        assert!(fs::read(dir.join(name)).unwrap() == expected, "{}", name);
+    }
+    fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,57 @@
+//! 续传中途被终止后重新运行，结果应与远端资源一致
+
+use std::fs;
+use std::path::Path;
+use std::process::{Command, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+mod common;
+
+use common::{data, serve, SIZE};
+
+fn download(dir: &Path, uri: &str, extra: &[&str]) -> Command {
+    let mut command = Command::new(env!("CARGO_BIN_EXE_download"));
+    command
+        .current_dir(dir)
+        .env("HOME", dir)
+        .env("XDG_CONFIG_HOME", dir)
+        .env("XDG_DATA_HOME", dir)
+        .env("LANG", "C")
+        .args(["4", uri, "out.bin"])
+        .args(extra)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null());
+    command
+}
+
+#[test]
+fn killed_resume_continues_from_recorded_progress() {
+    let uri = serve();
+    let dir = std::env::temp_dir().join(format!("download-resume-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    let expected = data();
+    fs::write(dir.join("out.bin.part"), &expected[..100_000]).unwrap();
+
+    // 限速使续传在扩展到完整长度后仍未完成，等记录了进度后强行终止
+    let mut child = download(&dir, &uri, &["--limit-rate-per-conn", "40k"])
+        .spawn()
+        .unwrap();
+    let sidecar = dir.join("out.bin.part.download.json");
+    let started = Instant::now();
+    while !sidecar.exists() && started.elapsed() < Duration::from_secs(10) {
+        thread::sleep(Duration::from_millis(50));
+    }
+    thread::sleep(Duration::from_millis(1500));
+    child.kill().unwrap();
+    child.wait().unwrap();
+    let part = fs::metadata(dir.join("out.bin.part")).unwrap();
+    assert_eq!(part.len(), SIZE as u64);
+    assert!(sidecar.exists());
+
+    let status = download(&dir, &uri, &[]).status().unwrap();
+    assert!(status.success());
+    assert!(fs::read(dir.join("out.bin")).unwrap() == expected);
+    assert!(!sidecar.exists());
+    fs::remove_dir_all(&dir).unwrap();
+}